@@ -0,0 +1,317 @@
+//! The campaign, the episodes of maps that are played one after another, and the intermission screen shown
+//! between them.
+//!
+//! The campaign manifest is a plain text file. Blank lines and lines starting with `#` are skipped, a line
+//! `episode <name>` starts a new episode and every other line is the path of a map file in that episode, relative
+//! to the manifest:
+//!
+//! ```text
+//! episode Escape
+//! courtyard.map
+//! cellar.map
+//! ```
+
+use std::path::{Path, PathBuf};
+use crate::enemy;
+use crate::level::{Floor, Level};
+use crate::pickup::{self, Item};
+use crate::text::{self, Anchor};
+use crate::trigger::{self, Event};
+use crate::world::World;
+use crate::{Canvas, ColorTex, Pos, Textures};
+
+const BACKGROUND_COLOR: (f32, f32, f32) = (0.05, 0.05, 0.2);
+const TITLE_COLOR: (f32, f32, f32) = (1.0, 0.8, 0.2);
+const TEXT_COLOR: (f32, f32, f32) = (1.0, 1.0, 1.0);
+
+/// A named run of maps.
+struct Episode
+{
+    name: String,
+    maps: Vec<PathBuf>,
+}
+
+/// Every episode in the order they are played, and how far through them the player is.
+pub struct Campaign
+{
+    episodes: Vec<Episode>,
+    /// The index of the episode being played.
+    episode: usize,
+    /// The index of the map being played within its episode.
+    map: usize,
+}
+
+/// Where the campaign went after a level was finished.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Advance
+{
+    /// On to the next map of the same episode.
+    NextMap,
+    /// The episode is over, on to the first map of the next one.
+    NextEpisode,
+    /// That was the last map of the last episode, the campaign starts over.
+    Finished,
+}
+
+impl Campaign
+{
+    /// Loads a campaign manifest, see the [module docs](index.html) for the format.
+    ///
+    /// Returns a message saying what is wrong with the file if it can not be loaded.
+    pub fn load(path: &Path) -> Result<Campaign, String>
+    {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut episodes: Vec<Episode> = Vec::new();
+        for (i, line) in text.lines().enumerate().map(|(i, line)| (i, line.trim()))
+        {
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            if let Some(name) = line.strip_prefix("episode")
+            {
+                episodes.push(Episode { name: name.trim().to_string(), maps: Vec::new() });
+            }
+            else
+            {
+                let episode = episodes.last_mut()
+                    .ok_or_else(|| format!("{}:{}: a map has to be in an episode", path.display(), i + 1))?;
+                episode.maps.push(dir.join(line));
+            }
+        }
+
+        episodes.retain(|episode| !episode.maps.is_empty());
+        if episodes.is_empty() { return Err(format!("{}: the campaign has no maps", path.display())); }
+        Ok(Campaign { episodes, episode: 0, map: 0 })
+    }
+
+    /// The path of the map being played.
+    pub fn map_path(&self) -> &Path
+    {
+        &self.episodes[self.episode].maps[self.map]
+    }
+
+    /// The name of the episode being played.
+    pub fn episode_name(&self) -> &str
+    {
+        &self.episodes[self.episode].name
+    }
+
+    /// Moves on to the map after the one being played.
+    pub fn advance(&mut self) -> Advance
+    {
+        self.map += 1;
+        if self.map < self.episodes[self.episode].maps.len() { return Advance::NextMap; }
+
+        self.map = 0;
+        self.episode += 1;
+        if self.episode < self.episodes.len() { return Advance::NextEpisode; }
+
+        self.episode = 0;
+        Advance::Finished
+    }
+}
+
+/// Makes the world for the start of a level, with the player at its start and its enemies, items and triggers in
+/// place. The `start` function of the level's script is queued up to run on the first frame.
+///
+/// If the player is coming from the level before, their health, armor, weapon and treasure are carried over
+/// from `carried`. Keys only open the doors of the level they are found in, so they are left behind.
+pub fn new_world(level: &Level, carried: Option<&mut World>) -> World
+{
+    let mut world = World::new(level.start);
+    for spawn in level.enemies.iter()
+    {
+        enemy::spawn(&mut world, level, spawn.tile, spawn.ang, &spawn.patrol);
+    }
+    for &(tile, item) in level.items.iter()
+    {
+        pickup::spawn(&mut world, level, tile, item);
+    }
+    for trigger in level.triggers.iter()
+    {
+        trigger::spawn(&mut world, trigger.clone());
+    }
+    if level.script.as_ref().is_some_and(|script| script.has_function("start"))
+    {
+        world.events.push_back(Event::Script(String::from("start")));
+    }
+
+    if let Some(old) = carried
+    {
+        let (old_player, player) = (old.player, world.player);
+        if let Some(health) = old.healths.remove(old_player)
+        {
+            world.healths.insert(player, health);
+        }
+        if let Some(weapon) = old.weapons.remove(old_player)
+        {
+            world.weapons.insert(player, weapon);
+        }
+        if let (Some(old_state), Some(state)) = (old.player_states.get(old_player), world.player_states.get_mut(player))
+        {
+            state.armor = old_state.armor;
+        }
+        if let (Some(old_inventory), Some(inventory)) = (old.inventories.get(old_player), world.inventories.get_mut(player))
+        {
+            inventory.treasure = old_inventory.treasure;
+        }
+        if let (Some(old_eye), Some(eye)) = (old.eyes.get(old_player), world.eyes.get_mut(player))
+        {
+            eye.head_bob = old_eye.head_bob;
+        }
+    }
+    world
+}
+
+/// What the player did in the level being played.
+pub struct LevelStats
+{
+    /// Seconds spent in the level, not counting time on the death screen.
+    pub time: f32,
+    pub secrets: usize,
+    /// How many secrets and treasures the level started with.
+    total_secrets: usize,
+    total_treasure: usize,
+    /// How much treasure the player was carrying when they started the level.
+    start_treasure: u32,
+}
+
+impl LevelStats
+{
+    /// Starts counting for a level that has just started.
+    pub fn new(level: &Level, world: &World) -> LevelStats
+    {
+        LevelStats
+        {
+            time: 0.0,
+            secrets: 0,
+            total_secrets: level.count_floors(Floor::Secret),
+            total_treasure: level.items.iter().filter(|&&(_, item)| item == Item::Treasure).count(),
+            start_treasure: world.inventories.get(world.player).map_or(0, |inventory| inventory.treasure),
+        }
+    }
+
+    /// Sums up the level now that it is over, for the intermission screen.
+    pub fn finish(&self, level: &Level, world: &World, advance: Advance, episode_name: &str) -> Intermission
+    {
+        // Counted at the end since triggers can bring in more enemies.
+        let total_kills = world.ais.iter().count();
+        let kills = world.ais.iter()
+            .filter(|&(entity, _)| world.healths.get(entity).is_some_and(|health| health.current <= 0))
+            .count();
+        let treasure = world.inventories.get(world.player).map_or(0, |inventory| inventory.treasure);
+        let percent = |count: usize, total: usize| (count * 100).checked_div(total).unwrap_or(100);
+        Intermission
+        {
+            level_name: level.name.clone(),
+            episode_name: episode_name.to_string(),
+            advance,
+            time: self.time,
+            kills: percent(kills, total_kills),
+            secrets: percent(self.secrets, self.total_secrets),
+            treasure: percent(treasure.saturating_sub(self.start_treasure) as usize, self.total_treasure),
+        }
+    }
+}
+
+/// The screen shown between levels, the game is paused until the player moves on.
+pub struct Intermission
+{
+    level_name: String,
+    /// The name of the episode that the level was in.
+    episode_name: String,
+    /// Where the campaign is going next.
+    pub advance: Advance,
+    /// Seconds spent in the level.
+    time: f32,
+    /// Percentages of the level's enemies killed, secrets found and treasure picked up.
+    kills: usize,
+    secrets: usize,
+    treasure: usize,
+}
+
+impl Intermission
+{
+    /// Draws the intermission screen over the whole of the current viewport.
+    pub fn draw(&self, canvas: &mut Canvas, textures: &Textures)
+    {
+        crate::draw_rect(Pos { position: [-1.0, 1.0] }, Pos { position: [1.0, -1.0] },
+            ColorTex::Color(&textures.empty, BACKGROUND_COLOR), 1.0, canvas);
+
+        let title = match self.advance
+        {
+            Advance::NextMap => format!("{} COMPLETE", self.level_name),
+            Advance::NextEpisode | Advance::Finished => format!("{} COMPLETE", self.episode_name),
+        };
+        let seconds = self.time as u32;
+        let lines = [
+            format!("TIME      {}:{:02}", seconds / 60, seconds % 60),
+            format!("KILLS     {}%", self.kills),
+            format!("SECRETS   {}%", self.secrets),
+            format!("TREASURE  {}%", self.treasure),
+        ];
+        let footer = if self.advance == Advance::Finished { "THE END - PRESS SPACE TO PLAY AGAIN" }
+            else { "PRESS SPACE TO CONTINUE" };
+
+        let pixel_h = 2.0 / canvas.dimensions().1 as f32;
+        text::draw_text(&title.to_uppercase(), Pos { position: [0.0, 0.6] }, Anchor::Bottom, 5.0, TITLE_COLOR,
+            &textures.font, canvas);
+        let line_height = (text::text_size("", 4.0).1 + 12.0) * pixel_h;
+        for (i, line) in lines.iter().enumerate()
+        {
+            text::draw_text(line, Pos { position: [-0.4, 0.4 - i as f32 * line_height] }, Anchor::TopLeft, 4.0,
+                TEXT_COLOR, &textures.font, canvas);
+        }
+        text::draw_text(footer, Pos { position: [0.0, -0.6] }, Anchor::Top, 3.0, TEXT_COLOR, &textures.font, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Loads a campaign manifest with the given text, written out to a file named `name` in the temp directory.
+    fn load(name: &str, text: &str) -> (PathBuf, Result<Campaign, String>)
+    {
+        let path = std::env::temp_dir().join(format!("campaign-test-{}-{}.txt", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let campaign = Campaign::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        (path, campaign)
+    }
+
+    #[test]
+    fn load_episodes()
+    {
+        let text = "# A comment.\n\nepisode Escape\ncourtyard.map\n  cellar.map  \nepisode Empty\n\nepisode Outdoors\n\
+            valley.map\n";
+        let (path, campaign) = load("episodes", text);
+        let mut campaign = campaign.unwrap();
+        let dir = path.parent().unwrap();
+
+        assert_eq!(campaign.episode_name(), "Escape");
+        assert_eq!(campaign.map_path(), dir.join("courtyard.map"));
+        assert_eq!(campaign.advance(), Advance::NextMap);
+        assert_eq!(campaign.map_path(), dir.join("cellar.map"));
+        // Episodes without any maps are left out.
+        assert_eq!(campaign.advance(), Advance::NextEpisode);
+        assert_eq!(campaign.episode_name(), "Outdoors");
+        assert_eq!(campaign.map_path(), dir.join("valley.map"));
+        assert_eq!(campaign.advance(), Advance::Finished);
+        assert_eq!(campaign.episode_name(), "Escape");
+        assert_eq!(campaign.map_path(), dir.join("courtyard.map"));
+    }
+
+    #[test]
+    fn load_errors()
+    {
+        let (path, campaign) = load("outside", "# Maps.\ncourtyard.map\n");
+        assert_eq!(campaign.err().unwrap(), format!("{}:2: a map has to be in an episode", path.display()));
+        let (path, campaign) = load("empty", "episode Escape\n");
+        assert_eq!(campaign.err().unwrap(), format!("{}: the campaign has no maps", path.display()));
+        let missing = std::env::temp_dir().join(format!("campaign-test-{}-missing.txt", std::process::id()));
+        assert!(Campaign::load(&missing).err().unwrap().starts_with(&format!("{}: ", missing.display())));
+    }
+}
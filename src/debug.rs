@@ -0,0 +1,206 @@
+//! An overlay with timing numbers and a way to inspect single rays, for tuning the renderer.
+
+use std::collections::VecDeque;
+use glium::glutin::event::VirtualKeyCode;
+use crate::level::Level;
+use crate::text::{self, Anchor};
+use crate::weapon::Hit;
+use crate::world::World;
+use crate::{Canvas, ColorTex, LegStart, Pos, Textures, FOV, RAYS};
+
+/// How many frames the frame time graph shows.
+const GRAPH_FRAMES: usize = 120;
+/// Height of the frame time graph in pixels per millisecond.
+const GRAPH_PIXELS_PER_MS: f32 = 2.0;
+/// Frame times longer than this are cut off at the top of the graph.
+const GRAPH_MAX_MS: f32 = 50.0;
+/// Scale of the overlay's text.
+const TEXT_SCALE: f32 = 2.0;
+const TEXT_COLOR: (f32, f32, f32) = (1.0, 1.0, 0.0);
+const UPDATE_COLOR: (f32, f32, f32) = (1.0, 0.5, 0.0);
+const RENDER_COLOR: (f32, f32, f32) = (0.0, 0.8, 0.0);
+
+/// How long a single frame took.
+#[derive(Copy, Clone)]
+struct FrameStats
+{
+    /// Seconds since the frame before it.
+    frame_time: f32,
+    /// Seconds spent moving things around.
+    update_time: f32,
+    /// Seconds spent drawing.
+    render_time: f32,
+    /// How many times [`draw_quad`] and [`draw_line`] drew something.
+    ///
+    /// [`draw_quad`]: ../fn.draw_quad.html
+    /// [`draw_line`]: ../fn.draw_line.html
+    draw_calls: usize,
+}
+
+/// The debug overlay's settings and the stats it shows.
+#[derive(Default)]
+pub struct DebugOverlay
+{
+    /// Whether the overlay is drawn.
+    pub shown: bool,
+    /// The most recent frames, oldest first.
+    frames: VecDeque<FrameStats>,
+    /// Where the mouse is in the window, in pixels from the top left.
+    cursor: Option<(f64, f64)>,
+}
+
+impl DebugOverlay
+{
+    /// Toggles the overlay with `F3`, any other key is ignored.
+    pub fn on_key_pressed(&mut self, key: VirtualKeyCode)
+    {
+        if key == VirtualKeyCode::F3
+        {
+            self.shown = !self.shown;
+        }
+    }
+
+    /// Tracks where the mouse is so the ray under it can be inspected.
+    pub fn set_cursor(&mut self, position: (f64, f64))
+    {
+        self.cursor = Some(position);
+    }
+
+    /// Adds the stats of a frame that was just shown.
+    pub fn record_frame(&mut self, frame_time: f32, update_time: f32, render_time: f32, draw_calls: usize)
+    {
+        self.frames.push_back(FrameStats { frame_time, update_time, render_time, draw_calls });
+        if self.frames.len() > GRAPH_FRAMES
+        {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Draws the overlay over the whole window.
+    ///
+    /// `view_3d` is the rect of the window (in pixels) that the 3d view was drawn into, if it was drawn. The ray
+    /// under the mouse is only inspected when the mouse is over it.
+    pub fn draw(&self, canvas: &mut Canvas, level: &Level, world: &World, textures: &Textures,
+        view_3d: Option<glium::Rect>)
+    {
+        if !self.shown { return; }
+
+        let player_pos = &world.player_pos();
+        let (width, height) = canvas.dimensions();
+        let (pixel_w, pixel_h) = (2.0 / width as f32, 2.0 / height as f32);
+        let last = self.frames.back().copied().unwrap_or(FrameStats { frame_time: 0.0, update_time: 0.0,
+            render_time: 0.0, draw_calls: 0 });
+
+        let (tile_w, tile_h) = level.tile_size();
+        let cell = ((player_pos.position[0] + 1.0) / tile_w, (player_pos.position[1] + 1.0) / tile_h);
+        let mut lines = vec![
+            format!("FPS {:.0}  FRAME {:.2}MS", if last.frame_time > 0.0 { 1.0 / last.frame_time } else { 0.0 },
+                last.frame_time * 1000.0),
+            format!("UPDATE {:.2}MS  RENDER {:.2}MS", last.update_time * 1000.0, last.render_time * 1000.0),
+            format!("DRAW CALLS {}", last.draw_calls),
+            format!("POS {:.3}, {:.3}  ANG {:.3}", player_pos.position[0], player_pos.position[1],
+                player_pos.ang.rem_euclid(2.0 * std::f32::consts::PI)),
+            format!("CELL {}, {}", cell.0.floor(), cell.1.floor()),
+        ];
+        match world.weapons.get(world.player).and_then(|weapon| weapon.last_hit)
+        {
+            Some(Hit::Wall { dist, .. }) => lines.push(format!("LAST SHOT WALL {:.4}", dist)),
+            Some(Hit::Entity { entity, dist }) => lines.push(format!("LAST SHOT ENTITY {} {:.4}", entity, dist)),
+            Some(Hit::Nothing) => lines.push(String::from("LAST SHOT MISSED")),
+            None => (),
+        }
+
+        // Find the column of the 3d view that is under the mouse and cast its ray again.
+        let inspected = match (self.cursor, view_3d)
+        {
+            (Some((x, y)), Some(view)) =>
+            {
+                let (x, y) = (x as f32 - view.left as f32, (height as f32 - y as f32) - view.bottom as f32);
+                if x >= 0.0 && y >= 0.0 && x < view.width as f32 && y < view.height as f32
+                {
+                    let screen_x = x / view.width as f32 * 2.0 - 1.0;
+                    let i = usize::min(((1.0 - screen_x) * RAYS as f32 / 2.0) as usize, RAYS - 1);
+                    Some((i, view))
+                }
+                else
+                {
+                    None
+                }
+            },
+            _ => None,
+        };
+        if let Some((i, view)) = inspected
+        {
+            let ray_ang = player_pos.ang - FOV / 2.0 + i as f32 * FOV / RAYS as f32;
+            let (ray_dist, horz, wall, ray_pos, legs) = crate::calc_dist_to_wall(level, player_pos, ray_ang);
+
+            lines.push(format!("RAY {} ANG {:.3}", i, ray_ang.rem_euclid(2.0 * std::f32::consts::PI)));
+            if wall == 0
+            {
+                lines.push(String::from(" NO HIT"));
+            }
+            else
+            {
+                lines.push(format!(" DIST {:.4}  CORRECTED {:.4}", ray_dist,
+                    ray_dist * f32::cos(ray_ang - player_pos.ang)));
+                lines.push(format!(" WALL {}  SIDE {}  U {:.3}", wall, if horz { "HORZ" } else { "VERT" },
+                    crate::pos_on_wall(level, legs[legs.len() - 1].0.ang, horz, ray_pos)));
+                lines.push(format!(" HIT {:.3}, {:.3}", ray_pos.0, ray_pos.1));
+                let count = |matches: fn(&LegStart) -> bool| legs.iter().filter(|leg| matches(&leg.2)).count();
+                let portals = count(|how| *how == LegStart::Portal);
+                let bounces = count(|how| matches!(how, LegStart::Mirror(_)));
+                let layers = count(|how| matches!(how, LegStart::SeeThrough(..)));
+                let over = count(|how| matches!(how, LegStart::Over(_)));
+                if legs.len() > 1
+                {
+                    lines.push(format!(" THROUGH {} PORTALS  {} BOUNCES  {} SEE THROUGH  {} OVER", portals, bounces,
+                        layers, over));
+                }
+            }
+
+            // Outline the column that is being inspected.
+            let column_right = view.left as f32 + (RAYS - i) as f32 * view.width as f32 / RAYS as f32;
+            let column_left = column_right - view.width as f32 / RAYS as f32;
+            for x in [column_left, column_right].iter()
+            {
+                let x = x * pixel_w - 1.0;
+                let bottom = view.bottom as f32 * pixel_h - 1.0;
+                let top = (view.bottom + view.height) as f32 * pixel_h - 1.0;
+                crate::draw_line(Pos { position: [x, bottom] }, Pos { position: [x, top] }, TEXT_COLOR, 1.0,
+                    &textures.empty, canvas);
+            }
+        }
+
+        // The text, under the FPS counter of the HUD.
+        let line_height = (text::text_size("", TEXT_SCALE).1 + 4.0) * pixel_h;
+        for (i, line) in lines.iter().enumerate()
+        {
+            text::draw_text(line, Pos { position: [-1.0 + 8.0 * pixel_w, 1.0 - 32.0 * pixel_h - i as f32 * line_height] },
+                Anchor::TopLeft, TEXT_SCALE, TEXT_COLOR, &textures.font, canvas);
+        }
+
+        // The frame time graph in the bottom left, above the HUD's status bar. Each frame is a bar with the time
+        // spent updating at the bottom and the time spent rendering on top of it.
+        let graph_bottom = -1.0 + 60.0 * pixel_h;
+        let graph_left = -1.0 + 8.0 * pixel_w;
+        let bar_width = 2.0 * pixel_w;
+        for (i, frame) in self.frames.iter().enumerate()
+        {
+            let left = graph_left + i as f32 * bar_width;
+            let bar_top = |ms: f32| graph_bottom + f32::min(ms, GRAPH_MAX_MS) * GRAPH_PIXELS_PER_MS * pixel_h;
+            let update_top = bar_top(frame.update_time * 1000.0);
+            let render_top = bar_top((frame.update_time + frame.render_time) * 1000.0);
+
+            crate::draw_rect(Pos { position: [left, update_top] }, Pos { position: [left + bar_width, graph_bottom] },
+                ColorTex::Color(&textures.empty, UPDATE_COLOR), 1.0, canvas);
+            crate::draw_rect(Pos { position: [left, render_top] }, Pos { position: [left + bar_width, update_top] },
+                ColorTex::Color(&textures.empty, RENDER_COLOR), 1.0, canvas);
+        }
+
+        // A line at 60 fps to compare against.
+        let target_y = graph_bottom + 1000.0 / 60.0 * GRAPH_PIXELS_PER_MS * pixel_h;
+        crate::draw_line(Pos { position: [graph_left, target_y] },
+            Pos { position: [graph_left + GRAPH_FRAMES as f32 * bar_width, target_y] }, TEXT_COLOR, 1.0,
+            &textures.empty, canvas);
+    }
+}
@@ -0,0 +1,573 @@
+//! Enemies and the state machine that drives them.
+
+use std::f32::consts::PI;
+use glium::texture::Texture2d;
+use crate::eye;
+use crate::level::Level;
+use crate::path::{self, FlowField, Tile};
+use crate::projectile::{self, ProjectileKind};
+use crate::world::{Collider, Entity, Health, Sheet, Sprite, Transform, World};
+use crate::{Canvas, ColorTex, Pos};
+
+/// How wide an enemy can see, in rads.
+const VIEW_CONE: f32 = 2.0;
+/// Enemies notice the player within this many tiles, even without seeing them.
+const HEARING_RADIUS: f32 = 1.5;
+/// Enemies start shooting when the player is seen within this many tiles.
+const ATTACK_RANGE: f32 = 4.0;
+/// Walking speed while patrolling, in tiles per second.
+const PATROL_SPEED: f32 = 1.0;
+/// Walking speed while chasing the player, in tiles per second.
+const CHASE_SPEED: f32 = 1.5;
+/// How close an enemy gets to the player before it stops walking, in tiles.
+const CHASE_STOP_DIST: f32 = 0.8;
+/// How fast an enemy turns in rads per second.
+const TURN_SPEED: f32 = 4.0;
+/// Radius of an enemy for bumping into walls, in tiles.
+const RADIUS: f32 = 0.3;
+/// Seconds between starting an attack and firing.
+const AIM_TIME: f32 = 0.5;
+/// Seconds an attack takes from start to finish.
+const ATTACK_TIME: f32 = 0.8;
+/// Seconds an enemy is stunned for after being hurt.
+const PAIN_TIME: f32 = 0.3;
+/// Seconds it takes to fall over when killed.
+const DEATH_TIME: f32 = 0.3;
+/// Seconds an enemy keeps looking for the player after losing sight of them.
+const GIVE_UP_TIME: f32 = 5.0;
+/// Seconds each walking frame is shown for.
+const WALK_FRAME_TIME: f32 = 0.25;
+/// How much health an enemy starts with.
+const START_HEALTH: i32 = 25;
+
+/// What an enemy is currently doing.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EnemyState
+{
+    /// Standing still until it notices the player.
+    Idle,
+    /// Walking between its patrol points until it notices the player.
+    Patrol,
+    /// Going after the player, or to where the player was last seen.
+    Chase,
+    /// Aiming and shooting at the player.
+    Attack,
+    /// Stunned after being hurt.
+    Pain,
+    /// Killed, this is never left.
+    Death,
+}
+
+/// The AI component, the state machine that makes an entity act like an enemy.
+pub struct Ai
+{
+    pub state: EnemyState,
+    /// Seconds since the current state was entered.
+    pub state_time: f32,
+    /// Play space points that it walks between while patrolling, in order.
+    patrol: Vec<[f32; 2]>,
+    /// The patrol point it is walking to.
+    patrol_index: usize,
+    /// The play space points it is walking through to get where it is going, in order.
+    path: Vec<[f32; 2]>,
+    /// Where the player was the last time it saw or heard them.
+    last_seen: Option<[f32; 2]>,
+    /// Seconds since it last saw or heard the player.
+    since_seen: f32,
+}
+
+/// Places an enemy at the center of a tile, facing `ang`. If it is given patrol points (as tiles) it patrols them,
+/// otherwise it stands guard.
+pub fn spawn(world: &mut World, level: &Level, tile: (usize, usize), ang: f32, patrol: &[(usize, usize)]) -> Entity
+{
+    let entity = world.spawn();
+    world.transforms.insert(entity, Transform { position: level.tile_center(tile), ang });
+    world.sprites.insert(entity, Sprite { sheet: Sheet::Enemy, frame: 0, size: 1.0, height: 0.0 });
+    world.colliders.insert(entity, Collider { radius: RADIUS });
+    world.healths.insert(entity, Health { current: START_HEALTH, max: START_HEALTH });
+    world.ais.insert(entity, Ai
+    {
+        state: if patrol.is_empty() { EnemyState::Idle } else { EnemyState::Patrol },
+        state_time: 0.0,
+        patrol: patrol.iter().map(|&tile| level.tile_center(tile)).collect(),
+        patrol_index: 0,
+        path: Vec::new(),
+        last_seen: None,
+        since_seen: 0.0,
+    });
+    entity
+}
+
+impl Ai
+{
+    fn set_state(&mut self, state: EnemyState)
+    {
+        self.state = state;
+        self.state_time = 0.0;
+        self.path.clear();
+    }
+
+    /// Forgets the path it was following, so it finds a new one from wherever it is now.
+    pub fn clear_path(&mut self)
+    {
+        self.path.clear();
+    }
+
+    /// Reacts to being hurt, going looking for where the damage came `from` if it is still alive.
+    pub fn hurt(&mut self, dead: bool, from: [f32; 2])
+    {
+        if self.state == EnemyState::Death { return; }
+
+        self.set_state(if dead { EnemyState::Death } else { EnemyState::Pain });
+        self.last_seen = Some(from);
+        self.since_seen = 0.0;
+    }
+
+    /// Whether the enemy at `me` can currently see or hear the player.
+    ///
+    /// The player is seen if they are in the enemy's view cone and the ray from the enemy towards them does not hit
+    /// a wall first. They are heard if they are close enough, no matter where the enemy is looking.
+    fn notices(me: &Transform, level: &Level, player_pos: &Transform) -> bool
+    {
+        let (tile_w, _) = level.tile_size();
+        let dx = player_pos.position[0] - me.position[0];
+        let dy = player_pos.position[1] - me.position[1];
+        let dist = f32::sqrt(dx * dx + dy * dy);
+
+        if dist < HEARING_RADIUS * tile_w { return true; }
+
+        let to_player = f32::atan2(dy, dx);
+        f32::abs(angle_diff(to_player, me.ang)) <= VIEW_CONE / 2.0 &&
+            crate::line_of_sight(level, me.position, player_pos.position)
+    }
+
+    /// Runs the state machine for a single frame, moving the enemy at `me`.
+    ///
+    /// `flow` leads to the player, it is used to chase them while they can be seen. The player can only be noticed
+    /// while they are `on_ground`, on the same storey as the enemies.
+    ///
+    /// Returns whether it fired at the player.
+    fn update(&mut self, me: &mut Transform, level: &Level, player_pos: &Transform, on_ground: bool,
+        flow: &FlowField, frame_time: f32) -> bool
+    {
+        let (tile_w, _) = level.tile_size();
+        let aiming = self.state == EnemyState::Attack && self.state_time < AIM_TIME;
+        self.state_time += frame_time;
+        if self.state == EnemyState::Death { return false; }
+
+        let noticed = on_ground && Ai::notices(me, level, player_pos);
+        if noticed
+        {
+            self.last_seen = Some(player_pos.position);
+            self.since_seen = 0.0;
+        }
+        else
+        {
+            self.since_seen += frame_time;
+        }
+
+        match self.state
+        {
+            EnemyState::Idle | EnemyState::Patrol if noticed => self.set_state(EnemyState::Chase),
+            EnemyState::Idle => (),
+            EnemyState::Patrol =>
+            {
+                if self.path.is_empty()
+                {
+                    let target = self.patrol[self.patrol_index];
+                    let tiles = level.tile_at(me.position).zip(level.tile_at(target))
+                        .and_then(|(from, to)| path::find_path(level, from, to));
+                    // A patrol point that can not be reached is not walked to, it stands guard instead until it
+                    // next goes back to patrolling after losing the player.
+                    if tiles.is_none()
+                    {
+                        self.set_state(EnemyState::Idle);
+                        return false;
+                    }
+                    self.path = path_points(level, tiles, target);
+                }
+                if self.follow_path(me, level, PATROL_SPEED * tile_w * frame_time, frame_time)
+                {
+                    self.patrol_index = (self.patrol_index + 1) % self.patrol.len();
+                }
+            },
+            EnemyState::Chase =>
+            {
+                let in_range = dist_between(me.position, player_pos.position) < ATTACK_RANGE * tile_w;
+                if noticed && in_range && crate::line_of_sight(level, me.position, player_pos.position)
+                {
+                    self.set_state(EnemyState::Attack);
+                }
+                else if self.since_seen > GIVE_UP_TIME
+                {
+                    self.last_seen = None;
+                    self.set_state(if self.patrol.is_empty() { EnemyState::Idle } else { EnemyState::Patrol });
+                }
+                else if let Some(target) = self.last_seen
+                {
+                    // While the player can be seen everyone follows the same flow field to them, once they are
+                    // lost it finds its own way to where they were last seen.
+                    if noticed
+                    {
+                        let tiles = level.tile_at(me.position).and_then(|tile| flow.path_from(level, tile));
+                        self.path = path_points(level, tiles, target);
+                    }
+                    else if self.path.is_empty()
+                    {
+                        let tiles = level.tile_at(me.position).zip(level.tile_at(target))
+                            .and_then(|(from, to)| path::find_path(level, from, to));
+                        self.path = path_points(level, tiles, target);
+                    }
+                    // On the same tile as the target there are no tiles left to go through, so it heads straight at
+                    // it.
+                    if self.path.is_empty() && level.tile_at(me.position) == level.tile_at(target)
+                    {
+                        self.path.push(target);
+                    }
+
+                    if dist_between(me.position, target) > CHASE_STOP_DIST * tile_w
+                    {
+                        self.follow_path(me, level, CHASE_SPEED * tile_w * frame_time, frame_time);
+                    }
+                }
+            },
+            EnemyState::Attack =>
+            {
+                turn_to(me, player_pos.position, frame_time);
+                if aiming && self.state_time >= AIM_TIME
+                {
+                    return true;
+                }
+                if self.state_time >= ATTACK_TIME
+                {
+                    self.set_state(EnemyState::Chase);
+                }
+            },
+            EnemyState::Pain =>
+            {
+                if self.state_time >= PAIN_TIME
+                {
+                    self.set_state(EnemyState::Chase);
+                }
+            },
+            EnemyState::Death => (),
+        }
+        false
+    }
+
+    /// Walks `me` towards the next point of its path, dropping the point once it gets there.
+    ///
+    /// Returns whether the end of the path has been reached.
+    fn follow_path(&mut self, me: &mut Transform, level: &Level, step: f32, frame_time: f32) -> bool
+    {
+        if let Some(&next) = self.path.first()
+        {
+            if walk_to(me, level, next, step, frame_time)
+            {
+                self.path.remove(0);
+            }
+        }
+        self.path.is_empty()
+    }
+
+    /// The frame of the enemy sprite sheet that shows what it is doing.
+    fn frame(&self) -> usize
+    {
+        match self.state
+        {
+            EnemyState::Idle => 0,
+            EnemyState::Patrol | EnemyState::Chase => 1 + (self.state_time / WALK_FRAME_TIME) as usize % 2,
+            EnemyState::Attack => if self.state_time >= AIM_TIME { 3 } else { 0 },
+            EnemyState::Pain => 4,
+            EnemyState::Death => if self.state_time < DEATH_TIME { 4 } else { 5 },
+        }
+    }
+
+    /// Draws the enemy at `me` from above as a marker with a line for where it is facing.
+    pub fn draw_2d(&self, me: &Transform, canvas: &mut Canvas, empty_tex: &Texture2d)
+    {
+        let color = match self.state
+        {
+            EnemyState::Idle | EnemyState::Patrol => (1.0, 0.6, 0.0),
+            EnemyState::Chase | EnemyState::Attack => (1.0, 0.0, 0.0),
+            EnemyState::Pain => (1.0, 0.5, 0.5),
+            EnemyState::Death => (0.3, 0.0, 0.0),
+        };
+        let size = 0.05;
+        let [x, y] = me.position;
+        crate::draw_rect(Pos { position: [x - size / 2.0, y + size / 2.0] }, Pos { position: [x + size / 2.0, y - size / 2.0] },
+            ColorTex::Color(empty_tex, color), 1.0, canvas);
+        if self.state != EnemyState::Death
+        {
+            let facing = Pos { position: [x + 0.08 * f32::cos(me.ang), y + 0.08 * f32::sin(me.ang)] };
+            crate::draw_line(Pos { position: me.position }, facing, color, 1.0, empty_tex, canvas);
+        }
+    }
+
+    /// Draws the path that the enemy at `me` is walking along from above.
+    pub fn draw_path(&self, me: &Transform, canvas: &mut Canvas, empty_tex: &Texture2d)
+    {
+        path::draw_path(me.position, &self.path, (0.0, 1.0, 1.0), empty_tex, canvas);
+    }
+}
+
+/// The AI system. Runs the state machine of every entity that has one for a single frame, moving `flow` to follow
+/// the player first. Enemies that fire throw a fireball at the player.
+pub fn update(world: &mut World, level: &Level, flow: &mut FlowField, frame_time: f32)
+{
+    let player_pos = world.player_pos();
+    let on_ground = eye::storey(world) == 0;
+    if let Some(tile) = level.tile_at(player_pos.position)
+    {
+        flow.retarget(level, tile);
+    }
+
+    for entity in world.ais.entities()
+    {
+        let (ai, me) = match (world.ais.get_mut(entity), world.transforms.get_mut(entity))
+        {
+            (Some(ai), Some(me)) => (ai, me),
+            _ => continue,
+        };
+        let fired = ai.update(me, level, &player_pos, on_ground, flow, frame_time);
+        let frame = ai.frame();
+        let position = me.position;
+
+        if let Some(sprite) = world.sprites.get_mut(entity)
+        {
+            sprite.frame = frame;
+        }
+        if fired
+        {
+            let ang = f32::atan2(player_pos.position[1] - position[1], player_pos.position[0] - position[0]);
+            projectile::spawn(world, level, ProjectileKind::Fireball, position, ang, entity);
+        }
+    }
+}
+
+/// Turns `me` towards a point, a little at a time.
+fn turn_to(me: &mut Transform, target: [f32; 2], frame_time: f32)
+{
+    let to_target = f32::atan2(target[1] - me.position[1], target[0] - me.position[0]);
+    let diff = angle_diff(to_target, me.ang);
+    let max_turn = TURN_SPEED * frame_time;
+    me.ang += f32::max(-max_turn, f32::min(max_turn, diff));
+}
+
+/// Turns and walks `me` towards a point, sliding along any walls in the way.
+///
+/// Returns whether the point has been reached.
+fn walk_to(me: &mut Transform, level: &Level, target: [f32; 2], step: f32, frame_time: f32) -> bool
+{
+    turn_to(me, target, frame_time);
+
+    let dx = target[0] - me.position[0];
+    let dy = target[1] - me.position[1];
+    let dist = f32::sqrt(dx * dx + dy * dy);
+    if dist <= step
+    {
+        me.position = target;
+        return true;
+    }
+
+    let radius = RADIUS * level.tile_size().0;
+    let new_x = [me.position[0] + dx / dist * step, me.position[1]];
+    if level.is_open(new_x, radius)
+    {
+        me.position = new_x;
+    }
+    let new_y = [me.position[0], me.position[1] + dy / dist * step];
+    if level.is_open(new_y, radius)
+    {
+        me.position = new_y;
+    }
+    false
+}
+
+/// Turns the tiles of a path into the play space points to walk through, ending exactly at `end` instead of the
+/// center of the last tile.
+fn path_points(level: &Level, tiles: Option<Vec<Tile>>, end: [f32; 2]) -> Vec<[f32; 2]>
+{
+    let mut points: Vec<[f32; 2]> = tiles.unwrap_or_default().into_iter().map(|tile| level.tile_center(tile)).collect();
+    if let Some(last) = points.last_mut()
+    {
+        *last = end;
+    }
+    points
+}
+
+/// The smallest signed angle that turns `b` into `a`, between `-π` and `π`.
+pub fn angle_diff(a: f32, b: f32) -> f32
+{
+    (a - b + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// The distance between two points in play space.
+pub fn dist_between(a: [f32; 2], b: [f32; 2]) -> f32
+{
+    f32::sqrt((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// A room with a wall down the middle, the right side shut off from the left.
+    fn level() -> Level
+    {
+        Level::from_rows(&["11111", "10101", "10101", "11111"])
+    }
+
+    #[test]
+    fn patrol_to_unreachable_point_stands_guard()
+    {
+        let level = level();
+        // The player is on the other side of the wall, out of sight and hearing.
+        let mut world = World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 });
+        world.player_pos_mut().position = level.tile_center((3, 2));
+        let enemy = spawn(&mut world, &level, (1, 2), PI / 2.0, &[(1, 1), (3, 1)]);
+        let mut flow = FlowField::new(&level, (3, 2));
+
+        for _ in 0..200
+        {
+            update(&mut world, &level, &mut flow, 0.02);
+        }
+        let ai = world.ais.get(enemy).unwrap();
+        assert_eq!(ai.state, EnemyState::Idle);
+        assert_eq!(ai.patrol_index, 1);
+        assert_eq!(level.tile_at(world.transforms.get(enemy).unwrap().position), Some((1, 1)));
+    }
+
+    /// An open room with a pillar in it. It is an even number of tiles across, like the maps, for the ray casts.
+    fn room() -> Level
+    {
+        Level::from_rows(&["11111111", "10000001", "10000001", "10010001", "10000001", "10000001", "10000001",
+            "11111111"])
+    }
+
+    /// Runs the AI system for `seconds`, a frame at a time.
+    fn run(world: &mut World, level: &Level, seconds: f32)
+    {
+        let mut flow = FlowField::new(level, level.tile_at(world.player_pos().position).unwrap());
+        for _ in 0..(seconds / 0.02) as usize
+        {
+            update(world, level, &mut flow, 0.02);
+        }
+    }
+
+    #[test]
+    fn notices_in_view_cone_with_line_of_sight()
+    {
+        let level = room();
+        let me = Transform { position: level.tile_center((1, 3)), ang: 0.0 };
+        let player = |tile| Transform { position: level.tile_center(tile), ang: 0.0 };
+        assert!(Ai::notices(&me, &level, &player((2, 3))), "heard up close");
+        assert!(Ai::notices(&me, &level, &player((6, 1))), "seen ahead");
+        assert!(!Ai::notices(&me, &level, &player((6, 3))), "the pillar is in the way");
+        let behind = Transform { ang: PI, ..me };
+        assert!(!Ai::notices(&behind, &level, &player((6, 1))), "out of the view cone");
+    }
+
+    #[test]
+    fn idle_chases_then_attacks_and_fires()
+    {
+        let level = room();
+        let mut world = World::new(Transform { position: level.tile_center((4, 1)), ang: 0.0 });
+        let enemy = spawn(&mut world, &level, (1, 1), 0.0, &[]);
+        assert_eq!(world.ais.get(enemy).unwrap().state, EnemyState::Idle);
+
+        run(&mut world, &level, 0.02);
+        assert_eq!(world.ais.get(enemy).unwrap().state, EnemyState::Chase);
+        run(&mut world, &level, 0.02);
+        assert_eq!(world.ais.get(enemy).unwrap().state, EnemyState::Attack);
+        assert_eq!(world.projectiles.iter().count(), 0);
+        run(&mut world, &level, AIM_TIME);
+        assert_eq!(world.projectiles.iter().count(), 1);
+    }
+
+    #[test]
+    fn chase_walks_to_the_player()
+    {
+        // A long hall with the player out of attack range at the far end, so the enemy walks up to them.
+        let level = Level::from_rows(&["11111111111111", "10000000000001", "10000000000001", "11111111111111"]);
+        let mut world = World::new(Transform { position: level.tile_center((12, 1)), ang: 0.0 });
+        let enemy = spawn(&mut world, &level, (1, 1), 0.0, &[]);
+        run(&mut world, &level, 0.02);
+        assert_eq!(world.ais.get(enemy).unwrap().state, EnemyState::Chase);
+        run(&mut world, &level, 6.0);
+        let position = world.transforms.get(enemy).unwrap().position;
+        assert!(dist_between(position, world.player_pos().position) < ATTACK_RANGE * level.tile_size().0);
+    }
+
+    #[test]
+    fn pain_then_death()
+    {
+        let mut ai = Ai
+        {
+            state: EnemyState::Patrol,
+            state_time: 1.0,
+            patrol: vec![[0.0, 0.0]],
+            patrol_index: 0,
+            path: vec![[0.5, 0.5]],
+            last_seen: None,
+            since_seen: 3.0,
+        };
+        ai.hurt(false, [0.2, 0.3]);
+        assert_eq!(ai.state, EnemyState::Pain);
+        assert_eq!(ai.state_time, 0.0);
+        assert!(ai.path.is_empty());
+        assert_eq!(ai.last_seen, Some([0.2, 0.3]));
+        assert_eq!(ai.since_seen, 0.0);
+
+        ai.hurt(true, [0.0, 0.0]);
+        assert_eq!(ai.state, EnemyState::Death);
+        ai.hurt(false, [0.0, 0.0]);
+        assert_eq!(ai.state, EnemyState::Death, "the dead stay dead");
+    }
+
+    #[test]
+    fn pain_wears_off_into_chase()
+    {
+        let level = room();
+        let mut world = World::new(Transform { position: level.tile_center((5, 5)), ang: 0.0 });
+        let enemy = spawn(&mut world, &level, (1, 1), PI, &[]);
+        world.ais.get_mut(enemy).unwrap().hurt(false, level.tile_center((5, 5)));
+        run(&mut world, &level, PAIN_TIME + 0.02);
+        assert_eq!(world.ais.get(enemy).unwrap().state, EnemyState::Chase);
+    }
+
+    #[test]
+    fn gives_up_and_goes_back_to_patrolling()
+    {
+        let level = level();
+        let mut world = World::new(Transform { position: level.tile_center((3, 2)), ang: 0.0 });
+        let enemy = spawn(&mut world, &level, (1, 2), PI / 2.0, &[(1, 1), (1, 2)]);
+        let ai = world.ais.get_mut(enemy).unwrap();
+        ai.set_state(EnemyState::Chase);
+        ai.last_seen = Some(level.tile_center((1, 1)));
+        run(&mut world, &level, GIVE_UP_TIME + 0.1);
+        let ai = world.ais.get(enemy).unwrap();
+        assert_eq!(ai.state, EnemyState::Patrol);
+        assert_eq!(ai.last_seen, None);
+    }
+
+    #[test]
+    fn player_on_upper_storey_is_not_noticed()
+    {
+        let level = room();
+        let mut world = World::new(Transform { position: level.tile_center((2, 1)), ang: 0.0 });
+        eye::set_storey(&mut world, 1);
+        let enemy = spawn(&mut world, &level, (1, 1), 0.0, &[]);
+        run(&mut world, &level, 0.1);
+        assert_eq!(world.ais.get(enemy).unwrap().state, EnemyState::Idle);
+    }
+
+    #[test]
+    fn angle_diff_wraps()
+    {
+        assert!((angle_diff(0.1, 2.0 * PI - 0.1) - 0.2).abs() < 1e-5);
+        assert!((angle_diff(-PI + 0.1, PI - 0.1) - 0.2).abs() < 1e-5);
+        assert!((angle_diff(1.0, 0.5) - 0.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,235 @@
+//! How high the player's eyes are off of the floor, which the 3d view is drawn from. The player can crouch down and
+//! jump up, and the view bobs up and down while they walk unless head bob is turned off. They can also look up and
+//! down, which moves the horizon of the view down and up without tilting the walls.
+//!
+//! The eyes also keep track of which storey of the level the player is on. Stairs and lifts carry them between
+//! storeys and they fall down to the storey below if there is nothing under them.
+
+use crate::level::{Floor, Level};
+use crate::world::World;
+
+/// How high the eyes are when standing, compared to a tile. Walls are drawn centered on the screen from here.
+pub const STAND_HEIGHT: f32 = 0.5;
+/// How high the eyes are when crouching.
+const CROUCH_HEIGHT: f32 = 0.3;
+/// How fast the player crouches down and stands back up, in tiles per second.
+const CROUCH_SPEED: f32 = 1.5;
+/// How fast the player leaves the floor when they jump, in tiles per second.
+const JUMP_SPEED: f32 = 1.8;
+/// How fast the player falls back down, in tiles per second per second.
+const GRAVITY: f32 = 6.0;
+/// How far the view bobs up and down while walking, in tiles.
+const BOB_HEIGHT: f32 = 0.02;
+/// How much of the head bob's cycle goes by for every tile walked, in radians.
+const BOB_RATE: f32 = 2.0 * std::f32::consts::PI;
+/// How fast the head bob fades in and out when starting and stopping walking, per second.
+const BOB_FADE: f32 = 8.0;
+
+/// The furthest that the horizon can be moved from the middle of the view by looking up or down, in half view
+/// heights.
+pub const MAX_PITCH: f32 = 0.6;
+/// How fast the player looks up and down with the keys, in half view heights per second.
+const PITCH_SPEED: f32 = 1.2;
+/// How fast stairs take the player up and down, in tiles per second.
+const STAIRS_SPEED: f32 = 3.0;
+/// How fast lifts carry the player up and down, in tiles per second.
+const LIFT_SPEED: f32 = 1.0;
+/// How fast the player falls down to the storey below, in tiles per second.
+const FALL_SPEED: f32 = 4.0;
+
+/// The eye component, for the player whose view is drawn.
+pub struct Eye
+{
+    /// The storey that the player is on or on their way to, `0` being the ground floor.
+    storey: usize,
+    /// How high the floor under the player is right now, it moves towards their storey at `climb_speed`.
+    floor: f32,
+    /// How fast the floor under the player is moving to their storey, in tiles per second.
+    climb_speed: f32,
+    /// Whether the player was on stairs or a lift last frame, so they are only carried once each time they step on.
+    on_shaft: bool,
+    /// How high the eyes are before jumping and bobbing, it moves between the standing and crouching heights.
+    base: f32,
+    /// How far the player is off of the floor from jumping.
+    jump: f32,
+    /// How fast the player is going up from jumping, negative while they fall.
+    velocity: f32,
+    /// How far through its cycle the head bob is, in radians.
+    bob: f32,
+    /// How much of the head bob is shown, from `0` when standing still to `1` when walking.
+    bob_amount: f32,
+    /// How far the player is looking up, as how far the horizon is moved down the view in half view heights. It is
+    /// negative while looking down.
+    pitch: f32,
+    /// Whether the view bobs while walking, it can be turned off for players that get motion sick.
+    pub head_bob: bool,
+}
+
+impl Default for Eye
+{
+    fn default() -> Eye
+    {
+        Eye
+        {
+            storey: 0,
+            floor: 0.0,
+            climb_speed: FALL_SPEED,
+            on_shaft: false,
+            base: STAND_HEIGHT, jump: 0.0, velocity: 0.0, bob: 0.0, bob_amount: 0.0,
+            pitch: 0.0,
+            head_bob: true,
+        }
+    }
+}
+
+impl Eye
+{
+    /// How high the eyes are off of the ground floor right now, compared to a tile.
+    pub fn height(&self) -> f32
+    {
+        let bob = if self.head_bob { BOB_HEIGHT * self.bob_amount * f32::sin(self.bob) } else { 0.0 };
+        self.floor + self.base + self.jump + bob
+    }
+}
+
+/// The storey that the player is on, `0` being the ground floor.
+pub fn storey(world: &World) -> usize
+{
+    world.eyes.get(world.player).map_or(0, |eye| eye.storey)
+}
+
+/// Puts the player straight down on the ground floor, for when they are moved somewhere without walking there.
+/// They are counted as already on any stairs or lift that they land on, so it does not carry them off.
+pub fn land(world: &mut World)
+{
+    if let Some(eye) = world.eyes.get_mut(world.player)
+    {
+        eye.storey = 0;
+        eye.floor = 0.0;
+        eye.climb_speed = FALL_SPEED;
+        eye.on_shaft = true;
+        eye.jump = 0.0;
+        eye.velocity = 0.0;
+    }
+}
+
+/// Puts the player straight onto a storey, without climbing there.
+#[cfg(test)]
+pub fn set_storey(world: &mut World, storey: usize)
+{
+    if let Some(eye) = world.eyes.get_mut(world.player)
+    {
+        eye.storey = storey;
+        eye.floor = storey as f32;
+    }
+}
+
+/// How high the player's eyes are off of the ground floor right now, compared to a tile.
+pub fn height(world: &World) -> f32
+{
+    world.eyes.get(world.player).map_or(STAND_HEIGHT, Eye::height)
+}
+
+/// Where the horizon of the player's view is, from `-1` at the bottom of the view to `1` at the top.
+pub fn horizon(world: &World) -> f32
+{
+    world.eyes.get(world.player).map_or(0.0, |eye| -eye.pitch)
+}
+
+/// Turns the player's view up by `amount` half view heights, or down if it is negative, as far as [`MAX_PITCH`].
+///
+/// [`MAX_PITCH`]: constant.MAX_PITCH.html
+pub fn look(world: &mut World, amount: f32)
+{
+    if let Some(eye) = world.eyes.get_mut(world.player)
+    {
+        eye.pitch = (eye.pitch + amount).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+}
+
+/// The eye system. Moves the player's eyes towards the crouching height while `crouch` is held and back to
+/// standing height otherwise, starts a jump if `jump` is held while on the floor and runs the jump's arc, and moves
+/// the head bob along by how far the player `walked` in tiles. The view is turned up while `look` is positive and
+/// down while it is negative.
+///
+/// Stepping onto stairs or a lift sends the player to the other end of it, and the player falls if the storey they
+/// are on has nothing to stand on where they are. On outdoor levels they stand on the terrain instead. The player's
+/// sprite is lifted up with them.
+pub fn update(world: &mut World, level: &Level, jump: bool, crouch: bool, walked: f32, look: f32, frame_time: f32)
+{
+    self::look(world, look * PITCH_SPEED * frame_time);
+    let position = world.player_pos().position;
+    let tile = level.tile_at(position);
+    let eye = match world.eyes.get_mut(world.player) { Some(eye) => eye, None => return };
+
+    if let Some(tile) = tile
+    {
+        let top = level.shaft_top(tile);
+        let on_shaft = top > 0;
+        if on_shaft && !eye.on_shaft
+        {
+            eye.storey = if eye.storey < top { top } else { 0 };
+            eye.climb_speed = if level.floor(tile.0, tile.1) == Floor::Lift { LIFT_SPEED } else { STAIRS_SPEED };
+        }
+        eye.on_shaft = on_shaft;
+        while !level.can_stand(eye.storey, tile)
+        {
+            eye.storey -= 1;
+            eye.climb_speed = FALL_SPEED;
+        }
+    }
+    let step = eye.climb_speed * frame_time;
+    eye.floor = match &level.terrain
+    {
+        Some(terrain) => terrain.height_at(position),
+        None => eye.floor + (eye.storey as f32 - eye.floor).clamp(-step, step),
+    };
+
+    let target = if crouch { CROUCH_HEIGHT } else { STAND_HEIGHT };
+    let step = CROUCH_SPEED * frame_time;
+    eye.base += (target - eye.base).clamp(-step, step);
+
+    let on_floor = eye.jump == 0.0;
+    if jump && on_floor
+    {
+        eye.velocity = JUMP_SPEED;
+    }
+    if !on_floor || eye.velocity > 0.0
+    {
+        eye.velocity -= GRAVITY * frame_time;
+        eye.jump += eye.velocity * frame_time;
+        if eye.jump <= 0.0
+        {
+            eye.jump = 0.0;
+            eye.velocity = 0.0;
+        }
+    }
+
+    // The head only bobs from walking on the floor, not while in the air.
+    let walking = if walked > 0.0 && on_floor { 1.0 } else { 0.0 };
+    eye.bob = (eye.bob + walked * BOB_RATE) % (2.0 * std::f32::consts::PI);
+    eye.bob_amount += (walking - eye.bob_amount) * f32::min(BOB_FADE * frame_time, 1.0);
+
+    // Sprites are drawn standing on the ground, so only how far the player is off of it lifts theirs.
+    let lifted = eye.floor - level.ground_at(position) + eye.jump;
+    if let Some(sprite) = world.sprites.get_mut(world.player)
+    {
+        sprite.height = lifted;
+    }
+}
+
+/// Turns the player's head bob on or off.
+///
+/// Returns whether it is on now.
+pub fn toggle_head_bob(world: &mut World) -> bool
+{
+    match world.eyes.get_mut(world.player)
+    {
+        Some(eye) =>
+        {
+            eye.head_bob = !eye.head_bob;
+            eye.head_bob
+        },
+        None => false,
+    }
+}
@@ -0,0 +1,138 @@
+//! The heads up display that is drawn on top of the 3d view.
+
+use crate::level::Level;
+use crate::pickup::Item;
+use crate::text::{self, Anchor};
+use crate::weapon::Weapon;
+use crate::world::{Sheet, World};
+use crate::{Canvas, ColorTex, Pos, Textures};
+
+/// Height of the status bar along the bottom of the view in pixels.
+const STATUS_BAR_HEIGHT: f32 = 52.0;
+/// Color of the status bar.
+const STATUS_BAR_COLOR: (f32, f32, f32) = (0.0, 0.0, 0.4);
+/// Scale of the labels in the status bar.
+const LABEL_SCALE: f32 = 2.0;
+/// Scale of the values in the status bar and of messages.
+const VALUE_SCALE: f32 = 3.0;
+/// Size of the key icons in the status bar in pixels.
+const KEY_ICON_SIZE: f32 = 24.0;
+/// Color of everything written on the HUD.
+const TEXT_COLOR: (f32, f32, f32) = (1.0, 1.0, 1.0);
+/// How many seconds a message stays on the screen for.
+const MESSAGE_TIME: f32 = 3.0;
+/// The most messages that are shown at once, older ones are dropped first.
+const MAX_MESSAGES: usize = 4;
+
+/// The state of the HUD that is not part of the game itself.
+#[derive(Default)]
+pub struct Hud
+{
+    /// Messages that are being shown and how many seconds they have left, oldest first.
+    messages: Vec<(String, f32)>,
+    /// Frames per second, smoothed over the last few frames.
+    fps: f32,
+}
+
+impl Hud
+{
+    /// Shows a message at the top of the screen for a few seconds.
+    pub fn show_message(&mut self, message: &str)
+    {
+        self.messages.push((message.to_string(), MESSAGE_TIME));
+        if self.messages.len() > MAX_MESSAGES
+        {
+            self.messages.remove(0);
+        }
+    }
+
+    /// Ages the messages and tracks the frame rate.
+    pub fn update(&mut self, frame_time: f32)
+    {
+        for message in self.messages.iter_mut()
+        {
+            message.1 -= frame_time;
+        }
+        self.messages.retain(|message| message.1 > 0.0);
+
+        if frame_time > 0.0
+        {
+            self.fps = if self.fps == 0.0 { 1.0 / frame_time } else { self.fps * 0.95 + 0.05 / frame_time };
+        }
+    }
+
+    /// Draws the HUD over the whole of the current viewport.
+    pub fn draw(&self, canvas: &mut Canvas, level: &Level, world: &World, textures: &Textures)
+    {
+        let (view_width, view_height) = canvas.view_size();
+        let (pixel_w, pixel_h) = (2.0 / view_width as f32, 2.0 / view_height as f32);
+
+        // The status bar along the bottom, split into evenly sized fields.
+        let bar_top = status_bar_top(canvas);
+        crate::draw_rect(Pos { position: [-1.0, bar_top] }, Pos { position: [1.0, -1.0] },
+            ColorTex::Color(&textures.empty, STATUS_BAR_COLOR), 1.0, canvas);
+
+        let weapon = world.weapons.get(world.player);
+        let count = |count: fn(&Weapon) -> u32| weapon.map_or(0, count).to_string();
+        let health = world.healths.get(world.player).map_or(0, |health| i32::max(health.current, 0));
+        let armor = world.player_states.get(world.player).map_or(0, |state| state.armor);
+        let inventory = world.inventories.get(world.player);
+        let fields = [("LEVEL", level.name.clone()), ("HEALTH", health.to_string()), ("ARMOR", armor.to_string()),
+            ("AMMO", count(|weapon| weapon.ammo)), ("ROCKETS", count(|weapon| weapon.rockets)),
+            ("GRENADES", count(|weapon| weapon.grenades)),
+            ("TREASURE", inventory.map_or(0, |inventory| inventory.treasure).to_string()), ("KEYS", String::new())];
+        let field_width = 2.0 / fields.len() as f32;
+        for (i, (label, value)) in fields.iter().enumerate()
+        {
+            let center = -1.0 + (i as f32 + 0.5) * field_width;
+            text::draw_text(label, Pos { position: [center, bar_top - 4.0 * pixel_h] }, Anchor::Top, LABEL_SCALE,
+                TEXT_COLOR, &textures.font, canvas);
+            // Shrink values that would spill into the next field.
+            let fits = text::text_size(value, VALUE_SCALE).0 * pixel_w <= field_width;
+            let scale = if fits { VALUE_SCALE } else { LABEL_SCALE };
+            text::draw_text(value, Pos { position: [center, -1.0 + 6.0 * pixel_h] }, Anchor::Bottom, scale,
+                TEXT_COLOR, &textures.font, canvas);
+        }
+
+        // The keys are shown as their sprites in the last field.
+        let keys = inventory.map_or(&[][..], |inventory| &inventory.keys[..]);
+        let key_size = KEY_ICON_SIZE * pixel_w;
+        let keys_left = 1.0 - field_width / 2.0 - keys.len() as f32 * key_size / 2.0;
+        let frames = Sheet::Items.frames() as f32;
+        for (i, &key) in keys.iter().enumerate()
+        {
+            let frame = Item::Key(key).frame() as f32;
+            let (left_u, right_u) = (frame / frames, (frame + 1.0) / frames);
+            let left = keys_left + i as f32 * key_size;
+            let bottom = -1.0 + 6.0 * pixel_h;
+            crate::draw_rect(Pos { position: [left, bottom + KEY_ICON_SIZE * pixel_h] },
+                Pos { position: [left + key_size, bottom] },
+                ColorTex::Texture(&textures.items, ([left_u, 1.0], [right_u, 1.0], [right_u, 0.0], [left_u, 0.0])),
+                1.0, canvas);
+        }
+
+        text::draw_text(&format!("FPS {:.0}", self.fps), Pos { position: [-1.0 + 8.0 * pixel_w, 1.0 - 8.0 * pixel_h] },
+            Anchor::TopLeft, LABEL_SCALE, TEXT_COLOR, &textures.font, canvas);
+
+        self.draw_messages(canvas, textures);
+    }
+
+    /// Draws the messages being shown along the top of the current viewport, on their own so they can go over the
+    /// intermission screen as well.
+    pub fn draw_messages(&self, canvas: &mut Canvas, textures: &Textures)
+    {
+        let pixel_h = 2.0 / canvas.view_size().1 as f32;
+        let line_height = (text::text_size("", VALUE_SCALE).1 + 4.0) * pixel_h;
+        for (i, (message, _)) in self.messages.iter().enumerate()
+        {
+            text::draw_text(message, Pos { position: [0.0, 1.0 - 8.0 * pixel_h - i as f32 * line_height] },
+                Anchor::Top, VALUE_SCALE, TEXT_COLOR, &textures.font, canvas);
+        }
+    }
+}
+
+/// Where the top of the status bar is in the current viewport's screen space.
+pub fn status_bar_top(canvas: &Canvas) -> f32
+{
+    -1.0 + STATUS_BAR_HEIGHT * 2.0 / canvas.view_size().1 as f32
+}
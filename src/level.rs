@@ -0,0 +1,773 @@
+//! The play space that the game takes place in, and the map files that it is loaded from.
+//!
+//! A map file is plain text. Blank lines and lines starting with `#` are skipped, and every other line is a
+//! setting made of a keyword followed by its values:
+//!
+//! ```text
+//! name Courtyard
+//! size 12 12
+//! open_sky no
+//! start 10 10 3.7
+//! light 0.8
+//! enemy 8 1 1.57 8,1 10,1 10,3
+//! trigger 3,4 5,4 enter once spawn 8,1 3.14
+//! teleporter 1,1 0 9,9 3.14
+//! portal 4,1 left 6,2 bottom
+//! ```
+//!
+//! `start` is the tile that the player starts on and the angle they face. `light` is how bright the level starts
+//! out, `1` being full brightness. Each `enemy` is the tile it stands on and its angle, followed by any tiles it
+//! patrols. Each `trigger` is an area that fires an event, see the [`trigger`] module for how they are written.
+//! Each `teleporter` is a pair of pads, see the [`teleport`] module, and each `portal` is a pair of joined wall
+//! faces, see the [`portal`] module. `terrain` is a height map and color map next to the map file that are drawn
+//! instead of the tiles, see the [`terrain`] module.
+//! The `tiles`, `sky`, `items`, `floors` and `heights` keywords are each followed by one line per row of the level,
+//! with one digit per tile, which fill in the [`Layouts`]. Letters carry on from the digits for anything past `9`,
+//! so `a` is `10`. Only `size` and `tiles` have to be given, `size` has to come before any of the layouts and each
+//! layout can only be given once.
+//!
+//! `tiles` is the ground floor, and each `storey` after it is followed by the wall types of the next floor up in
+//! the same way. The tops of the walls of a storey are what is stood on from the storey above, anywhere else on an
+//! upper storey is open air that the player falls through. Stairs and lifts on the floor layout carry the player
+//! between the storeys that are open above them. Everything other than the player stays on the ground floor.
+//!
+//! Thin walls run along the top or left edge of their tile and diagonal walls run from one corner of their tile to
+//! the other, so the rest of the tile can still be walked through by the player. Like the other special walls they
+//! only work on the ground floor, and everything other than the player treats them as filling the whole tile.
+//! Enemies can not find their way through them, so they are best put where a wall would otherwise be and not across
+//! a way that enemies need to go.
+//!
+//! A map can also have a [`script`] next to it.
+//!
+//! [`Layouts`]: struct.Layouts.html
+//! [`trigger`]: ../trigger/index.html
+//! [`script`]: ../script/index.html
+//! [`teleport`]: ../teleport/index.html
+//! [`portal`]: ../portal/index.html
+//! [`terrain`]: ../terrain/index.html
+
+use std::path::Path;
+use crate::path::Tile;
+use crate::pickup::Item;
+use crate::portal::Portal;
+use crate::script::Script;
+use crate::teleport::Teleporter;
+use crate::terrain::Terrain;
+use crate::trigger::Trigger;
+use crate::PlayerPos;
+
+/// The most decals that a level keeps, older ones are removed first.
+const MAX_DECALS: usize = 64;
+/// How many steps the height of a wall in the height layout goes up in for each tile of height.
+const HEIGHT_STEPS: f32 = 4.0;
+/// The wall type of exit switches, walking into one ends the level.
+pub const EXIT_SWITCH: u8 = 7;
+/// The wall type of mirrors, the view bounces off of them.
+pub const MIRROR: u8 = 8;
+/// The wall type of grates, which can be seen through the gaps in but not walked through.
+pub const GRATE: u8 = 9;
+/// The wall type of windows, which can be seen through but not walked through. Written as `a` in map files.
+pub const GLASS: u8 = 10;
+/// The wall type of thin walls along the top edge of their tile, as the map is written. Written as `b` in map
+/// files.
+pub const THIN_TOP: u8 = 11;
+/// The wall type of thin walls along the left edge of their tile. Written as `c` in map files.
+pub const THIN_LEFT: u8 = 12;
+/// The wall type of walls going across their tile from its top left corner to its bottom right one, as the map is
+/// written. Written as `d` in map files.
+pub const DIAGONAL_DOWN: u8 = 13;
+/// The wall type of walls going across their tile from its bottom left corner to its top right one. Written as `e`
+/// in map files.
+pub const DIAGONAL_UP: u8 = 14;
+/// The wall type that walls on upper storeys are seen as by rays that cross them, as special walls only work on
+/// the ground floor.
+const UPPER_WALL: u8 = 1;
+
+/// Whether a wall type can be seen through, so whatever is behind it is drawn as well.
+pub fn is_see_through(wall: u8) -> bool
+{
+    wall == GRATE || wall == GLASS
+}
+
+/// Whether a wall type is a thin or diagonal wall, which is a line inside of its tile instead of filling it.
+pub fn is_line(wall: u8) -> bool
+{
+    (THIN_TOP..=DIAGONAL_UP).contains(&wall)
+}
+
+/// Whether a wall type is a diagonal wall.
+pub fn is_diagonal(wall: u8) -> bool
+{
+    wall == DIAGONAL_DOWN || wall == DIAGONAL_UP
+}
+
+/// Reads a tile written as `col,row` in a map file.
+///
+/// Returns a message saying what is wrong if it can not be read.
+pub fn parse_tile(value: &str) -> Result<Tile, String>
+{
+    let mut parts = value.split(',').map(|part| part.parse::<usize>());
+    match (parts.next(), parts.next(), parts.next())
+    {
+        (Some(Ok(col)), Some(Ok(row)), None) => Ok((col, row)),
+        _ => Err(format!("expected a tile as col,row but found `{}`", value)),
+    }
+}
+
+/// The layout of a single level.
+///
+/// Tiles are stored row major, and the whole level is mapped onto the `[-1, 1]` square that the player moves
+/// around in.
+pub struct Level
+{
+    /// The name shown to the player.
+    pub name: String,
+    /// Number of tiles across.
+    pub width: usize,
+    /// Number of tiles down.
+    pub height: usize,
+    /// The wall type of each tile on the ground floor. `0` is open floor.
+    pub tiles: Vec<u8>,
+    /// The wall type of each tile on every storey above the ground floor, lowest first.
+    pub upper: Vec<Vec<u8>>,
+    /// Which tiles are open to the sky instead of having a ceiling.
+    pub sky: Vec<bool>,
+    /// What the floor of each tile does to the player standing on it.
+    pub floors: Vec<Floor>,
+    /// How tall the ground floor wall of each tile would be compared to a tile, whether or not there is a wall on it.
+    pub heights: Vec<f32>,
+    /// The height of the top of the level, the tallest wall or the ceiling of the top storey. Anything past a
+    /// column of walls this tall is hidden by it.
+    pub tallest: f32,
+    /// If set the whole level is outdoors and the sky is drawn in place of the ceiling everywhere.
+    pub open_sky: bool,
+    /// How bright the walls, floors and sprites are, `1` is full brightness.
+    pub light: f32,
+    /// Marks left on the walls, oldest first.
+    pub decals: Vec<Decal>,
+    /// The items that the level starts with and the tiles they lie on, as `(col, row)`.
+    pub items: Vec<((usize, usize), Item)>,
+    /// Where the player starts.
+    pub start: PlayerPos,
+    /// The enemies that the level starts with.
+    pub enemies: Vec<EnemySpawn>,
+    /// The areas that fire events when the player is in them.
+    pub triggers: Vec<Trigger>,
+    /// The pairs of teleporter pads.
+    pub teleporters: Vec<Teleporter>,
+    /// The pairs of joined wall faces.
+    pub portals: Vec<Portal>,
+    /// The level's logic, if it has any.
+    pub script: Option<Script>,
+    /// The outdoor ground that is drawn in place of the tiles, if the level has any.
+    pub terrain: Option<Terrain>,
+}
+
+/// Where an enemy starts and what it does before it sees the player.
+#[derive(Clone, Debug)]
+pub struct EnemySpawn
+{
+    /// The tile it stands on, as `(col, row)`.
+    pub tile: (usize, usize),
+    /// The way it faces.
+    pub ang: f32,
+    /// The tiles it walks between, it stands guard if there are none.
+    pub patrol: Vec<(usize, usize)>,
+}
+
+/// The layouts that a level is made of. Each has one entry per tile, row major.
+#[derive(Copy, Clone)]
+pub struct Layouts<'a>
+{
+    /// The wall type of each tile, `0` is open floor.
+    pub tiles: &'a [u8],
+    /// Any non zero entry marks that tile as open to the sky.
+    pub sky: &'a [u8],
+    /// The items lying on each tile, using the codes of [`Item::from_code`].
+    ///
+    /// [`Item::from_code`]: ../pickup/enum.Item.html#method.from_code
+    pub items: &'a [u8],
+    /// The floor of each tile, using the codes of [`Floor::from_code`].
+    ///
+    /// [`Floor::from_code`]: enum.Floor.html#method.from_code
+    pub floors: &'a [u8],
+    /// How tall the wall on each tile is in quarters of a tile, `0` is the usual height of a whole tile.
+    pub heights: &'a [u8],
+}
+
+/// The types of floor.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Floor
+{
+    /// Nothing happens when standing on it.
+    Plain,
+    /// Hurts the player every so often while they stand on it, like slime or lava.
+    Hazard,
+    /// The player comes back here after dying once they have stood on it.
+    Checkpoint,
+    /// A hidden spot, it counts as found once the player stands on it.
+    Secret,
+    /// Ends the level when the player stands on it.
+    Exit,
+    /// Steps that take the player up to the top of the storeys open above it, or back down to the ground floor.
+    Stairs,
+    /// A platform that slowly carries the player up to the top of the storeys open above it, or back down.
+    Lift,
+}
+
+impl Floor
+{
+    /// The floor for a code in a level's floor layout. `1` is a hazard, `2` is a checkpoint, `3` is a secret, `4`
+    /// is an exit, `5` is stairs, `6` is a lift and anything else is plain floor.
+    pub fn from_code(code: u8) -> Floor
+    {
+        match code
+        {
+            1 => Floor::Hazard,
+            2 => Floor::Checkpoint,
+            3 => Floor::Secret,
+            4 => Floor::Exit,
+            5 => Floor::Stairs,
+            6 => Floor::Lift,
+            _ => Floor::Plain,
+        }
+    }
+
+    /// Its color in the 2d view and the 3d view's floor, if it is drawn differently from plain floor.
+    pub fn color(self) -> Option<(f32, f32, f32)>
+    {
+        match self
+        {
+            Floor::Plain | Floor::Secret => None,
+            Floor::Exit => Some((0.9, 0.9, 0.9)),
+            Floor::Hazard => Some((0.3, 0.8, 0.1)),
+            Floor::Checkpoint => Some((0.2, 0.4, 0.9)),
+            Floor::Stairs => Some((0.6, 0.45, 0.3)),
+            Floor::Lift => Some((0.5, 0.5, 0.55)),
+        }
+    }
+}
+
+/// A mark left on the face of a wall, like a bullet hole.
+#[derive(Copy, Clone)]
+pub struct Decal
+{
+    /// The point on the wall that it is centered on, in play space.
+    pub position: [f32; 2],
+    /// Whether it is on a horizontal grid line, the same as the `if_on_horz` of a ray cast.
+    pub horz: bool,
+    /// How far up the wall its center is off of the ground floor, compared to a tile.
+    pub height: f32,
+}
+
+impl Level
+{
+    /// Makes a level out of layouts that all match its size.
+    ///
+    /// Returns a message saying which layout does not match if one does not.
+    pub fn new(name: &str, width: usize, height: usize, layouts: &Layouts, open_sky: bool) -> Result<Level, String>
+    {
+        let Layouts { tiles, sky, items, floors, heights } = *layouts;
+        let checks = [("tile", tiles), ("sky", sky), ("item", items), ("floor", floors), ("height", heights)];
+        if let Some((layout, _)) = checks.iter().find(|(_, layout)| layout.len() != width * height)
+        {
+            return Err(format!("the {} layout does not match the level size", layout));
+        }
+        let heights: Vec<f32> = heights.iter()
+            .map(|&code| if code == 0 { 1.0 } else { code as f32 / HEIGHT_STEPS })
+            .collect();
+
+        Ok(Level
+        {
+            name: name.to_string(),
+            width,
+            height,
+            tiles: tiles.to_vec(),
+            upper: Vec::new(),
+            sky: sky.iter().map(|&s| s != 0).collect(),
+            floors: floors.iter().map(|&code| Floor::from_code(code)).collect(),
+            tallest: heights.iter().copied().fold(0.0, f32::max),
+            heights,
+            open_sky,
+            light: 1.0,
+            decals: Vec::new(),
+            items: items.iter().enumerate()
+                .filter_map(|(i, &code)| Item::from_code(code).map(|item| ((i % width, i / width), item)))
+                .collect(),
+            start: PlayerPos { position: [0.0, 0.0], ang: 0.0 },
+            enemies: Vec::new(),
+            triggers: Vec::new(),
+            teleporters: Vec::new(),
+            portals: Vec::new(),
+            script: None,
+            terrain: None,
+        })
+    }
+
+    /// Makes a level with nothing in it other than the ground floor walls, given as rows of wall types written the
+    /// same way as in a map file.
+    #[cfg(test)]
+    pub fn from_rows(rows: &[&str]) -> Level
+    {
+        let tiles: Vec<u8> = rows.iter().flat_map(|row| row.chars().map(|c| c.to_digit(36).unwrap() as u8)).collect();
+        let empty = vec![0; tiles.len()];
+        let layouts = Layouts { tiles: &tiles, sky: &empty, items: &empty, floors: &empty, heights: &empty };
+        Level::new("", rows[0].len(), rows.len(), &layouts, false).unwrap()
+    }
+
+    /// Loads a level from a map file, see the [module docs](index.html) for the format.
+    ///
+    /// Returns a message saying what is wrong with the file, or with the level's script, if it can not be loaded.
+    pub fn load(path: &Path) -> Result<Level, String>
+    {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let error = |line: usize, message: &str| format!("{}:{}: {}", path.display(), line + 1, message);
+
+        let mut name = String::new();
+        let mut size = None;
+        let mut open_sky = false;
+        let mut light = 1.0;
+        let mut start = ((0, 0), 0.0);
+        let mut enemies = Vec::new();
+        let mut triggers = Vec::new();
+        let mut teleporters = Vec::new();
+        let mut portals = Vec::new();
+        let mut layouts: [Vec<u8>; 5] = Default::default();
+        let mut upper = Vec::new();
+        let mut terrain = None;
+        // Every tile that a setting puts something on and the line it is on, checked once the size is known.
+        let mut placed: Vec<(usize, Tile)> = Vec::new();
+        // The layouts are read at the size they were given, so it can not change once one has been.
+        let mut any_layout = false;
+
+        let mut lines = text.lines().enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        while let Some((i, line)) = lines.next()
+        {
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let values: Vec<&str> = words.collect();
+            let number = |index: usize| -> Result<f32, String>
+            {
+                values.get(index).and_then(|value| value.parse().ok()).ok_or_else(|| error(i, "expected a number"))
+            };
+            let tile = |value: &str| parse_tile(value).map_err(|message| error(i, &message));
+
+            match keyword
+            {
+                "name" => name = values.join(" "),
+                "size" if any_layout => return Err(error(i, "the size can not change after a layout")),
+                "size" => size = Some((number(0)? as usize, number(1)? as usize)),
+                "open_sky" => open_sky = values.first() == Some(&"yes"),
+                "light" => light = f32::max(number(0)?, 0.0),
+                "start" =>
+                {
+                    start = ((number(0)? as usize, number(1)? as usize), number(2)?);
+                    placed.push((i, start.0));
+                },
+                "enemy" =>
+                {
+                    let spawn = EnemySpawn
+                    {
+                        tile: (number(0)? as usize, number(1)? as usize),
+                        ang: number(2)?,
+                        patrol: values.iter().skip(3).map(|&value| tile(value)).collect::<Result<_, _>>()?,
+                    };
+                    let tiles = std::iter::once(spawn.tile).chain(spawn.patrol.iter().copied());
+                    placed.extend(tiles.map(|tile| (i, tile)));
+                    enemies.push(spawn);
+                },
+                "trigger" =>
+                {
+                    let trigger = Trigger::parse(&values).map_err(|message| error(i, &message))?;
+                    placed.extend(trigger.tiles().into_iter().map(|tile| (i, tile)));
+                    triggers.push(trigger);
+                },
+                "teleporter" =>
+                {
+                    let teleporter = Teleporter::parse(&values).map_err(|message| error(i, &message))?;
+                    placed.extend(teleporter.tiles().into_iter().map(|tile| (i, tile)));
+                    teleporters.push(teleporter);
+                },
+                "portal" =>
+                {
+                    let portal = Portal::parse(&values).map_err(|message| error(i, &message))?;
+                    placed.extend(portal.tiles().into_iter().map(|tile| (i, tile)));
+                    portals.push((i, portal));
+                },
+                "terrain" => match values.as_slice()
+                {
+                    [heights, colors] => terrain = Some(Terrain::load(&path.with_file_name(heights),
+                        &path.with_file_name(colors)).map_err(|message| error(i, &message))?),
+                    _ => return Err(error(i, "expected a height map and a color map")),
+                },
+                "tiles" | "sky" | "items" | "floors" | "heights" | "storey" =>
+                {
+                    let (width, height) = size.ok_or_else(|| error(i, "the size has to come before any layout"))?;
+                    any_layout = true;
+                    let layout = match ["tiles", "sky", "items", "floors", "heights"].iter().position(|&k| k == keyword)
+                    {
+                        Some(index) if !layouts[index].is_empty() =>
+                            return Err(error(i, &format!("`{}` is given more than once", keyword))),
+                        Some(index) => &mut layouts[index],
+                        None =>
+                        {
+                            upper.push(Vec::new());
+                            upper.last_mut().unwrap()
+                        },
+                    };
+                    for _ in 0..height
+                    {
+                        let (i, row) = lines.next().ok_or_else(|| error(i, "the layout is missing rows"))?;
+                        let row: Option<Vec<u8>> = row.chars().map(|c| c.to_digit(36).map(|d| d as u8)).collect();
+                        match row
+                        {
+                            Some(row) if row.len() == width => layout.extend(row),
+                            _ => return Err(error(i, &format!("expected a row of {} digits or letters", width))),
+                        }
+                    }
+                },
+                _ => return Err(error(i, &format!("unknown setting `{}`", keyword))),
+            }
+        }
+
+        let (width, height) = size.ok_or_else(|| format!("{}: the map has no size", path.display()))?;
+        if layouts[0].is_empty() { return Err(format!("{}: the map has no tiles", path.display())); }
+        if let Some(&(i, _)) = placed.iter().find(|&&(_, (col, row))| col >= width || row >= height)
+        {
+            return Err(error(i, "tile is outside of the level"));
+        }
+        for layout in layouts.iter_mut().skip(1).filter(|layout| layout.is_empty())
+        {
+            *layout = vec![0; width * height];
+        }
+
+        let [tiles, sky, items, floors, heights] = &layouts;
+        let layouts = Layouts { tiles, sky, items, floors, heights };
+        let mut level = Level::new(&name, width, height, &layouts, open_sky)
+            .map_err(|message| format!("{}: {}", path.display(), message))?;
+        level.set_upper(upper).map_err(|message| format!("{}: {}", path.display(), message))?;
+        level.start = PlayerPos { position: level.tile_center(start.0), ang: start.1 };
+        level.light = light;
+        level.enemies = enemies;
+        level.triggers = triggers;
+        level.teleporters = teleporters;
+        // Portals can only be checked against the walls once they are all in place.
+        for (i, portal) in portals
+        {
+            portal.check(&level).map_err(|message| error(i, &message))?;
+            level.portals.push(portal);
+        }
+        level.terrain = terrain;
+        let script_path = path.with_extension("rhai");
+        if script_path.exists()
+        {
+            level.script = Some(Script::load(&script_path)?);
+        }
+        Ok(level)
+    }
+
+    /// Puts storeys of walls above the ground floor, lowest first, which have to match the level's size.
+    ///
+    /// Returns a message saying so and leaves the level as it was if one of them does not match.
+    pub fn set_upper(&mut self, upper: Vec<Vec<u8>>) -> Result<(), String>
+    {
+        if upper.iter().any(|storey| storey.len() != self.width * self.height)
+        {
+            return Err(String::from("a storey layout does not match the level size"));
+        }
+        self.upper = upper;
+        self.tallest = self.heights.iter().copied().fold(self.storeys() as f32, f32::max);
+        Ok(())
+    }
+
+    /// Leaves a mark on a wall, removing the oldest one if there are too many.
+    pub fn add_decal(&mut self, decal: Decal)
+    {
+        self.decals.push(decal);
+        if self.decals.len() > MAX_DECALS
+        {
+            self.decals.remove(0);
+        }
+    }
+
+    /// The wall type at the given tile, tiles outside of the level are treated as open floor.
+    pub fn tile(&self, col: usize, row: usize) -> u8
+    {
+        if col < self.width && row < self.height { self.tiles[row * self.width + col] } else { 0 }
+    }
+
+    /// How tall the wall on the given tile is compared to a tile, open floor and tiles outside of the level have no
+    /// height.
+    pub fn wall_height(&self, (col, row): (usize, usize)) -> f32
+    {
+        if self.tile(col, row) == 0 { 0.0 } else { self.heights[row * self.width + col] }
+    }
+
+    /// How many storeys the level has, counting the ground floor.
+    pub fn storeys(&self) -> usize
+    {
+        1 + self.upper.len()
+    }
+
+    /// The wall type at the given tile on a storey, `0` being the ground floor. Tiles outside of the level and
+    /// storeys above the top are treated as open, and thin and diagonal walls on upper storeys as plain walls.
+    pub fn wall_on(&self, storey: usize, (col, row): (usize, usize)) -> u8
+    {
+        if storey == 0 { return self.tile(col, row); }
+        match self.upper.get(storey - 1)
+        {
+            Some(tiles) if col < self.width && row < self.height => match tiles[row * self.width + col]
+            {
+                wall if is_line(wall) => UPPER_WALL,
+                wall => wall,
+            },
+            _ => 0,
+        }
+    }
+
+    /// The line that the thin or diagonal wall of type `wall` on the given tile runs along in play space, of the
+    /// form `(start, end)` with the start on the left. `None` if the wall type is not one of them.
+    pub fn line_wall(&self, wall: u8, (col, row): (usize, usize)) -> Option<([f32; 2], [f32; 2])>
+    {
+        let (tile_w, tile_h) = self.tile_size();
+        let (left, top) = (col as f32 * tile_w - 1.0, row as f32 * tile_h - 1.0);
+        let (right, bottom) = (left + tile_w, top + tile_h);
+        match wall
+        {
+            THIN_TOP => Some(([left, top], [right, top])),
+            THIN_LEFT => Some(([left, top], [left, bottom])),
+            DIAGONAL_DOWN => Some(([left, top], [right, bottom])),
+            DIAGONAL_UP => Some(([left, bottom], [right, top])),
+            _ => None,
+        }
+    }
+
+    /// The wall type that rays going across the given tile see, the ground floor's if it has a wall there. Any
+    /// walls only on upper storeys are seen as plain walls.
+    pub fn column_wall(&self, tile: (usize, usize)) -> u8
+    {
+        match self.tile(tile.0, tile.1)
+        {
+            0 if (1..self.storeys()).any(|storey| self.wall_on(storey, tile) != 0) => UPPER_WALL,
+            wall => wall,
+        }
+    }
+
+    /// The walls stacked up on the given tile, of the form `(wall_type, bottom, top)` with the heights off of the
+    /// ground floor compared to a tile, from the highest down.
+    pub fn blocks(&self, tile: (usize, usize)) -> impl Iterator<Item = (u8, f32, f32)> + '_
+    {
+        (0..self.storeys()).rev()
+            .map(move |storey| (self.wall_on(storey, tile), storey))
+            .filter(|&(wall, _)| wall != 0)
+            .map(move |(wall, storey)|
+            {
+                let bottom = storey as f32;
+                (wall, bottom, if storey == 0 { self.wall_height(tile) } else { bottom + 1.0 })
+            })
+    }
+
+    /// How high the walls on the given tile go up without any gaps from the ground floor, nothing behind them that
+    /// is lower than this can be seen past them.
+    pub fn solid_height(&self, tile: (usize, usize)) -> f32
+    {
+        let ground = self.wall_height(tile);
+        if ground < 1.0 { return ground; }
+        let stacked = (1..self.storeys()).take_while(|&storey| self.wall_on(storey, tile) != 0).count();
+        f32::max(ground, stacked as f32 + 1.0)
+    }
+
+    /// Whether the given tile can be stood on from a storey, which on upper storeys needs a wall below it with its
+    /// top level with the storey's floor, or stairs or a lift that reach it.
+    pub fn can_stand(&self, storey: usize, tile: (usize, usize)) -> bool
+    {
+        storey == 0 || self.shaft_top(tile) >= storey || self.blocks(tile).any(|(_, _, top)| top == storey as f32)
+    }
+
+    /// The highest storey that the stairs or lift on the given tile reach, as far as the storeys above it are
+    /// open. Tiles without stairs or a lift only reach the ground floor.
+    pub fn shaft_top(&self, (col, row): (usize, usize)) -> usize
+    {
+        if !matches!(self.floor(col, row), Floor::Stairs | Floor::Lift) { return 0; }
+        (1..self.storeys()).take_while(|&storey| self.wall_on(storey, (col, row)) == 0).count()
+    }
+
+    /// Whether the sky can be seen when looking up from the given tile.
+    pub fn is_sky(&self, col: usize, row: usize) -> bool
+    {
+        self.open_sky || (col < self.width && row < self.height && self.sky[row * self.width + col])
+    }
+
+    /// The floor of the given tile, tiles outside of the level have plain floor.
+    pub fn floor(&self, col: usize, row: usize) -> Floor
+    {
+        if col < self.width && row < self.height { self.floors[row * self.width + col] } else { Floor::Plain }
+    }
+
+    /// How many tiles have the given floor.
+    pub fn count_floors(&self, floor: Floor) -> usize
+    {
+        self.floors.iter().filter(|&&f| f == floor).count()
+    }
+
+    /// Sets the floor of the given tile, if it is in the level.
+    pub fn set_floor(&mut self, col: usize, row: usize, floor: Floor)
+    {
+        if col < self.width && row < self.height
+        {
+            self.floors[row * self.width + col] = floor;
+        }
+    }
+
+    /// The walls that are within `reach` of `position` straight along either axis, as `(col, row)`. These are the
+    /// walls that something at `position` is walking into.
+    pub fn walls_touched(&self, position: [f32; 2], reach: f32) -> Vec<(usize, usize)>
+    {
+        let mut walls: Vec<(usize, usize)> = [(reach, 0.0), (-reach, 0.0), (0.0, reach), (0.0, -reach)].iter()
+            .filter_map(|&(dx, dy)| self.tile_at([position[0] + dx, position[1] + dy]))
+            .filter(|&(col, row)| self.tile(col, row) != 0)
+            .collect();
+        walls.dedup();
+        walls
+    }
+
+    /// The size of a single tile in play space units as `(width, height)`.
+    pub fn tile_size(&self) -> (f32, f32)
+    {
+        (2.0 / self.width as f32, 2.0 / self.height as f32)
+    }
+
+    /// Whether the given tile can be walked through. Anything outside of the level can not be.
+    pub fn is_walkable(&self, col: usize, row: usize) -> bool
+    {
+        col < self.width && row < self.height && self.tile(col, row) == 0
+    }
+
+    /// Whether a square of play space centered on `position`, reaching `radius` out each way, is clear of walls.
+    pub fn is_open(&self, position: [f32; 2], radius: f32) -> bool
+    {
+        [(-radius, -radius), (radius, -radius), (-radius, radius), (radius, radius)].iter().all(|&(ox, oy)|
+        {
+            self.tile_at([position[0] + ox, position[1] + oy]).is_some_and(|(col, row)| self.is_walkable(col, row))
+        })
+    }
+
+    /// The tile that a play space position is in as `(col, row)`, or `None` if it is outside of the level.
+    pub fn tile_at(&self, position: [f32; 2]) -> Option<(usize, usize)>
+    {
+        let (tile_w, tile_h) = self.tile_size();
+        let col = f32::floor((position[0] + 1.0) / tile_w);
+        let row = f32::floor((position[1] + 1.0) / tile_h);
+        if col >= 0.0 && row >= 0.0 && (col as usize) < self.width && (row as usize) < self.height
+        {
+            Some((col as usize, row as usize))
+        }
+        else
+        {
+            None
+        }
+    }
+
+    /// How high the ground is at a play space position compared to a tile. It is only off of the ground floor on
+    /// outdoor levels, where it is the height of the terrain.
+    pub fn ground_at(&self, position: [f32; 2]) -> f32
+    {
+        self.terrain.as_ref().map_or(0.0, |terrain| terrain.height_at(position))
+    }
+
+    /// The play space position of the center of a tile given as `(col, row)`.
+    pub fn tile_center(&self, tile: (usize, usize)) -> [f32; 2]
+    {
+        let (tile_w, tile_h) = self.tile_size();
+        [(tile.0 as f32 + 0.5) * tile_w - 1.0, (tile.1 as f32 + 0.5) * tile_h - 1.0]
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Loads a map with the given text, written out to a file named `name` in the temp directory.
+    fn load(name: &str, text: &str) -> (std::path::PathBuf, Result<Level, String>)
+    {
+        let path = std::env::temp_dir().join(format!("level-test-{}-{}.map", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let level = Level::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        (path, level)
+    }
+
+    /// The message of a map that does not load.
+    fn error(name: &str, text: &str) -> String
+    {
+        let (path, level) = load(name, text);
+        level.err().unwrap().replace(&path.display().to_string(), "map")
+    }
+
+    #[test]
+    fn load_map()
+    {
+        let text = "name Test Room\nsize 3 2\nstart 1 0 1.5\nenemy 1 1 0 1,0\ntiles\n101\n000\nfloors\n040\n000\n\
+            storey\n111\n101\n";
+        let level = load("good", text).1.unwrap();
+        assert_eq!(level.name, "Test Room");
+        assert_eq!((level.width, level.height), (3, 2));
+        assert_eq!(level.tiles, vec![1, 0, 1, 0, 0, 0]);
+        assert_eq!(level.floor(1, 0), Floor::Exit);
+        assert_eq!(level.storeys(), 2);
+        assert_eq!(level.tile_at(level.start.position), Some((1, 0)));
+        assert_eq!(level.enemies[0].patrol, vec![(1, 0)]);
+    }
+
+    #[test]
+    fn load_repeated_layout()
+    {
+        assert_eq!(error("tiles", "size 2 1\ntiles\n00\ntiles\n00\n"), "map:4: `tiles` is given more than once");
+        assert_eq!(error("floors", "size 2 1\ntiles\n00\nfloors\n00\nfloors\n11\n"),
+            "map:6: `floors` is given more than once");
+    }
+
+    #[test]
+    fn load_size_after_layout()
+    {
+        assert_eq!(error("size", "size 2 1\ntiles\n00\nsize 3 1\n"), "map:4: the size can not change after a layout");
+        assert_eq!(error("storey", "size 2 1\ntiles\n00\nsize 3 1\nstorey\n000\n"),
+            "map:4: the size can not change after a layout");
+    }
+
+    #[test]
+    fn load_other_errors()
+    {
+        assert_eq!(error("no-size", "tiles\n00\n"), "map:1: the size has to come before any layout");
+        assert_eq!(error("no-tiles", "size 2 1\n"), "map: the map has no tiles");
+        assert_eq!(error("short-row", "size 2 1\ntiles\n0\n"), "map:3: expected a row of 2 digits or letters");
+        assert_eq!(error("outside", "size 2 1\nstart 2 0 0\ntiles\n00\n"), "map:2: tile is outside of the level");
+        assert_eq!(error("unknown", "size 2 1\nwater yes\n"), "map:2: unknown setting `water`");
+    }
+
+    #[test]
+    fn new_checks_layout_sizes()
+    {
+        let (tiles, short) = ([0; 4], [0; 3]);
+        let layouts = |floors| Layouts { tiles: &tiles, sky: &tiles, items: &tiles, floors, heights: &tiles };
+        assert!(Level::new("", 2, 2, &layouts(&tiles), false).is_ok());
+        assert_eq!(Level::new("", 2, 2, &layouts(&short), false).err().unwrap(),
+            "the floor layout does not match the level size");
+    }
+
+    #[test]
+    fn set_upper_checks_storey_sizes()
+    {
+        let tiles = [0; 4];
+        let layouts = Layouts { tiles: &tiles, sky: &tiles, items: &tiles, floors: &tiles, heights: &tiles };
+        let mut level = Level::new("", 2, 2, &layouts, false).unwrap();
+        assert_eq!(level.set_upper(vec![vec![1; 4], vec![1; 6]]).unwrap_err(),
+            "a storey layout does not match the level size");
+        assert_eq!(level.storeys(), 1);
+        assert!(level.set_upper(vec![vec![1; 4]]).is_ok());
+        assert_eq!(level.storeys(), 2);
+    }
+}
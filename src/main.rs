@@ -4,35 +4,53 @@
 //! 
 //! [`glium`]: ../glium/index.html
 
+mod campaign;
+mod debug;
+mod enemy;
+mod eye;
+mod hud;
+mod level;
+mod minimap;
+mod path;
+mod pickup;
+mod player;
+mod portal;
+mod projectile;
+mod script;
+mod teleport;
+mod terrain;
+mod text;
+mod trigger;
+mod weapon;
+mod world;
+
 use std::time;
 use std::collections::HashMap;
 use glium::{glutin, Surface, Display, Program, Frame};
 use glium::texture::Texture2d;
+use campaign::{Advance, Campaign, Intermission, LevelStats};
+use debug::DebugOverlay;
+use hud::Hud;
+use level::{Level, DIAGONAL_UP, EXIT_SWITCH, GLASS, GRATE, MIRROR, THIN_LEFT, THIN_TOP};
+use minimap::Minimap;
+use path::{FlowField, Tile};
+use pickup::{DoorEvent, KeyColor};
+use player::PlayerEvent;
+use projectile::ProjectileKind;
+use text::Font;
+use world::{Entity, Sheet, World};
+
+/// The campaign manifest that lists the maps to play, see the [`campaign`] module for the format.
+/// 
+/// [`campaign`]: campaign/index.html
+const CAMPAIGN: &str = r"maps\campaign.txt";
 
-/// Height of play space.
-const GAME_HEIGHT: usize = 12;
-/// Width of play space.
-const GAME_WIDTH: usize = 12;
-/// Array that defines the layout of the play space.
-const GAME: [u8; GAME_HEIGHT * GAME_WIDTH] = [
-    1,1,1,1,1,1,1,1,1,1,1,1,
-    1,0,1,0,0,0,0,1,0,0,0,1,
-    1,0,1,0,1,1,0,0,0,3,0,1,
-    1,0,0,0,1,0,0,1,0,0,0,1,
-    1,3,2,0,1,0,1,1,0,0,0,1,
-    1,0,0,0,1,0,0,1,0,1,1,1,
-    1,0,1,0,1,1,0,1,0,1,1,1,
-    1,1,0,1,1,1,0,1,0,0,0,1,
-    1,0,0,0,0,0,0,1,0,1,1,1,
-    1,0,3,1,1,1,0,1,0,1,0,1,
-    1,0,0,0,0,0,2,1,0,0,0,1,
-    1,1,1,1,1,1,1,1,1,1,1,1,
-];
-
-/// The speed that the player moves in units per second.
-const MOVE_SPEED: f32 = 4.0 / GAME_HEIGHT as f32;
+/// The speed that the player moves in tiles per second.
+const MOVE_SPEED: f32 = 2.0;
 /// The speed that the player turns in rads per second.
 const LOOK_SPEED: f32 = 2.0;
+/// How far the player looks up or down for each unit the mouse is moved, in half view heights.
+const MOUSE_PITCH: f32 = 0.003;
 
 // TODO: turn some of these into args
 
@@ -41,10 +59,10 @@ const RAYS: usize = 256;
 /// Field of view
 const FOV: f32 = 1.2;
 
-const START_POS: PlayerPos = PlayerPos { position: [0.8, 0.8], ang: 3.7 };
-
 /// Whether the game should be rendered with colors or textures.
 const COLORS: bool = false;
+/// Size of the decals left on walls compared to a tile.
+const DECAL_SIZE: f32 = 0.08;
 
 enum ColorTex<'a>
 {
@@ -59,7 +77,13 @@ enum ColorTex<'a>
     /// 
     /// [`draw_quad`]: fn.draw_quad.html
     /// [`draw_rect`]: fn.draw_rect.html
-    Texture(&'a Texture2d, ([f32; 2],[f32; 2],[f32; 2],[f32; 2]))
+    Texture(&'a Texture2d, ([f32; 2],[f32; 2],[f32; 2],[f32; 2])),
+    /// Tells the [`draw_quad`] and [`draw_rect`] to draw with the given color, but only where the given texture
+    /// is opaque. The second param are the texture coords. This is used for drawing text.
+    /// 
+    /// [`draw_quad`]: fn.draw_quad.html
+    /// [`draw_rect`]: fn.draw_rect.html
+    Mask(&'a Texture2d, ([f32; 2],[f32; 2],[f32; 2],[f32; 2]), (f32,f32,f32)),
 }
 
 /// `Vertex` is used for [`glium`]'s draw functions.
@@ -73,6 +97,88 @@ struct Vertex
 }
 glium::implement_vertex!(Vertex, position, tex_coords);
 
+/// All of the textures that the game is drawn with.
+struct Textures
+{
+    main_wall: Texture2d,
+    wall2: Texture2d,
+    wall3: Texture2d,
+    /// A panorama of the sky that covers a full turn, with the horizon along the bottom edge.
+    sky: Texture2d,
+    /// The enemy's animation frames side by side, see [`Sheet::Enemy`].
+    /// 
+    /// [`Sheet::Enemy`]: world/enum.Sheet.html#variant.Enemy
+    enemy: Texture2d,
+    /// The player's gun's animation frames side by side.
+    weapon: Texture2d,
+    /// The shape of a bullet hole, only its alpha is used.
+    bullet_hole: Texture2d,
+    /// Every type of projectile and the frames of an explosion side by side.
+    projectiles: Texture2d,
+    /// Every type of item side by side, see [`Item::frame`].
+    /// 
+    /// [`Item::frame`]: pickup/enum.Item.html#method.frame
+    items: Texture2d,
+    /// The red, blue and yellow doors side by side.
+    doors: Texture2d,
+    /// The exit switch.
+    switch: Texture2d,
+    /// Grates, the gaps are fully see through.
+    grate: Texture2d,
+    /// What the player looks like, see [`Sheet::Player`].
+    /// 
+    /// [`Sheet::Player`]: world/enum.Sheet.html#variant.Player
+    player: Texture2d,
+    /// An empty texture for draws that only use a color.
+    empty: Texture2d,
+    /// The glyphs for drawing text.
+    font: Font,
+}
+
+/// How the game is shown on the window.
+#[derive(Copy, Clone, PartialEq)]
+enum ViewMode
+{
+    /// First person, like wolfenstein 3d.
+    ThreeD,
+    /// From above, with the rays that are cast to render the 3d view.
+    TwoD,
+    /// The 2d view on the left half of the window and the 3d view on the right half.
+    Split,
+}
+
+impl ViewMode
+{
+    /// The mode that the view mode key switches to.
+    fn next(self) -> ViewMode
+    {
+        match self
+        {
+            ViewMode::ThreeD => ViewMode::TwoD,
+            ViewMode::TwoD => ViewMode::Split,
+            ViewMode::Split => ViewMode::ThreeD,
+        }
+    }
+}
+
+/// Everything that is drawn on top of the game itself.
+struct Overlays
+{
+    minimap: Minimap,
+    hud: Hud,
+    debug: DebugOverlay,
+}
+
+impl Overlays
+{
+    /// Passes a key press on to each overlay's own keys.
+    fn on_key_pressed(&mut self, key: glutin::event::VirtualKeyCode)
+    {
+        self.minimap.on_key_pressed(key);
+        self.debug.on_key_pressed(key);
+    }
+}
+
 #[derive(Copy, Clone)]
 struct PlayerPos
 {
@@ -86,6 +192,120 @@ struct Pos
     position: [f32; 2],
 }
 
+/// A flat picture standing in the level that always faces the player, like the enemies in wolfenstein 3d. This is
+/// what an entity's [`Sprite`] is drawn as.
+/// 
+/// [`Sprite`]: world/struct.Sprite.html
+#[derive(Copy, Clone)]
+struct Billboard<'a>
+{
+    /// Where it stands in play space.
+    position: [f32; 2],
+    /// The sheet that the frame is taken from.
+    texture: &'a Texture2d,
+    /// The left and right `u` texture coords of the frame to draw.
+    frame: (f32, f32),
+    /// How big it is compared to a tile.
+    size: f32,
+    /// How far off of the floor its bottom is compared to a tile.
+    height: f32,
+}
+
+/// Says where on the window the draw functions put things.
+#[derive(Copy, Clone)]
+struct View
+{
+    /// A row major affine transform that every vertex is moved by before it is drawn.
+    transform: [[f32; 3]; 3],
+    /// If set, the `[-1, 1]` screen space is mapped onto this rect of the window (in pixels) instead of all of it.
+    viewport: Option<glium::Rect>,
+    /// If set, nothing is drawn outside of this rect of the window (in pixels).
+    scissor: Option<glium::Rect>,
+    /// How opaque everything is drawn, anything less than `1.0` is blended with what is already there.
+    alpha: f32,
+}
+
+impl View
+{
+    /// Draws straight onto the whole window.
+    fn full_screen() -> View
+    {
+        View { transform: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], viewport: None, scissor: None, alpha: 1.0 }
+    }
+
+    fn apply(&self, pos: Pos) -> [f32; 2]
+    {
+        let t = &self.transform;
+        [t[0][0] * pos.position[0] + t[0][1] * pos.position[1] + t[0][2],
+         t[1][0] * pos.position[0] + t[1][1] * pos.position[1] + t[1][2]]
+    }
+}
+
+/// Everything needed to draw into the frame currently being rendered.
+struct Canvas<'a>
+{
+    target: Frame,
+    display: &'a Display,
+    program: &'a Program,
+    /// The view that the next draws go through.
+    view: View,
+    /// How many draws have been made into the frame so far.
+    draw_calls: usize,
+}
+
+impl<'a> Canvas<'a>
+{
+    /// Starts drawing a new frame.
+    fn new(display: &'a Display, program: &'a Program) -> Canvas<'a>
+    {
+        Canvas { target: display.draw(), display, program, view: View::full_screen(), draw_calls: 0 }
+    }
+
+    /// The size of the window in pixels as `(width, height)`.
+    fn dimensions(&self) -> (u32, u32)
+    {
+        self.target.get_dimensions()
+    }
+
+    /// The size of the current viewport in pixels as `(width, height)`.
+    fn view_size(&self) -> (u32, u32)
+    {
+        match self.view.viewport
+        {
+            Some(viewport) => (viewport.width, viewport.height),
+            None => self.dimensions(),
+        }
+    }
+
+    /// Fills the current viewport with a color.
+    fn clear(&mut self, color: (f32, f32, f32))
+    {
+        self.target.clear(self.view.viewport.as_ref(), Some((color.0, color.1, color.2, 1.0)), false, None, None);
+    }
+
+    /// The parameters for a draw through the current view. `blend` forces alpha blending on even if the view is
+    /// fully opaque.
+    fn draw_parameters(&self, blend: bool) -> glium::DrawParameters<'static>
+    {
+        glium::DrawParameters
+        {
+            blend: if blend || self.view.alpha < 1.0 { glium::Blend::alpha_blending() } else { Default::default() },
+            viewport: self.view.viewport,
+            scissor: self.view.scissor,
+            .. Default::default()
+        }
+    }
+
+    /// Shows the frame on the window.
+    /// 
+    /// Returns how many draws went into it.
+    fn finish(self) -> usize
+    {
+        let _ = self.target.finish();
+        self.draw_calls
+    }
+}
+
 /// Draws a quad with 2 triangles.
 /// ```text
 ///  ___
@@ -95,23 +315,24 @@ struct Pos
 ///  ---
 /// ```
 fn draw_quad(top_left: Pos, top_right: Pos, bottom_right: Pos, bottom_left: Pos, color_tex: ColorTex,
-    mul: f32, target: &mut Frame, display: &Display, program: &Program)
+    mul: f32, canvas: &mut Canvas)
 {
     let tex_coords = match color_tex
     {
-        ColorTex::Texture(_, coords) => coords,
+        ColorTex::Texture(_, coords) | ColorTex::Mask(_, coords, _) => coords,
         ColorTex::Color(_,_) => ([0.0,1.0],[1.0,1.0],[1.0,0.0],[0.0, 0.0])
     };
+    let masked = matches!(color_tex, ColorTex::Mask(..));
 
-    let vertex1 = Vertex { position: top_left.position, tex_coords: tex_coords.0 };
-    let vertex2 = Vertex { position: top_right.position, tex_coords: tex_coords.1 };
-    let vertex3 = Vertex { position: bottom_right.position, tex_coords: tex_coords.2 };
-    let vertex4 = Vertex { position: bottom_left.position, tex_coords: tex_coords.3 };
+    let vertex1 = Vertex { position: canvas.view.apply(top_left), tex_coords: tex_coords.0 };
+    let vertex2 = Vertex { position: canvas.view.apply(top_right), tex_coords: tex_coords.1 };
+    let vertex3 = Vertex { position: canvas.view.apply(bottom_right), tex_coords: tex_coords.2 };
+    let vertex4 = Vertex { position: canvas.view.apply(bottom_left), tex_coords: tex_coords.3 };
 
     let shape = vec![vertex1, vertex2, vertex3, vertex4];
 
     // upload shape data to video memory
-    let shape_vb = match glium::VertexBuffer::new(display, &shape)
+    let shape_vb = match glium::VertexBuffer::new(canvas.display, &shape)
     {
         Ok(vb) => vb,
         Err(glium::vertex::BufferCreationError::BufferCreationError(
@@ -123,7 +344,7 @@ fn draw_quad(top_left: Pos, top_right: Pos, bottom_right: Pos, bottom_left: Pos,
             },
         e => e.unwrap() // I don't like this but the only other option is not supported err.
     };
-    let indices = match glium::IndexBuffer::new(display, glium::index::PrimitiveType::TrianglesList, &[0u16,1,3,1,2,3][..])
+    let indices = match glium::IndexBuffer::new(canvas.display, glium::index::PrimitiveType::TrianglesList, &[0u16,1,3,1,2,3][..])
     {
         Ok(vb) => vb,
         Err(glium::index::BufferCreationError::BufferCreationError(
@@ -141,43 +362,91 @@ fn draw_quad(top_left: Pos, top_right: Pos, bottom_right: Pos, bottom_left: Pos,
         ColorTex::Color(empty_tex, color) => glium::uniform! {
             rgb_color: color,
             use_texture: false,
-            tex: empty_tex,
-            mult: mul
+            use_mask: false,
+            tex: empty_tex.sampled(),
+            mult: mul,
+            alpha: canvas.view.alpha
         },
         ColorTex::Texture(texture, _) => glium::uniform! {
             rgb_color: (0.0,0.0,0.0),
             use_texture: true,
-            tex: texture,
-            mult: mul
-        }
+            use_mask: false,
+            tex: texture.sampled(),
+            mult: mul,
+            alpha: canvas.view.alpha
+        },
+        // Masks are pixel art so they are kept sharp when scaled up.
+        ColorTex::Mask(texture, _, color) => glium::uniform! {
+            rgb_color: color,
+            use_texture: false,
+            use_mask: true,
+            tex: texture.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            mult: mul,
+            alpha: canvas.view.alpha
+        },
     };
     
     // Note that DrawErrors tend to be if the code was writen wrong and would cause a failure every time.
     // That is why I think an unwrap is ok
-    target.draw(&shape_vb, &indices, program, &uniforms, &Default::default()).unwrap();
+    let params = canvas.draw_parameters(masked);
+    canvas.target.draw(&shape_vb, &indices, canvas.program, &uniforms, &params).unwrap();
+    canvas.draw_calls += 1;
 }
 
 /// A wrapper around [`draw_quad`].
 /// 
 /// [`draw_quad`]: fn.draw_quad.html
-fn draw_rect(top_left: Pos, bottom_right: Pos, color_tex: ColorTex, mul: f32, target: &mut Frame,
-    display: &Display, program: &Program)
+fn draw_rect(top_left: Pos, bottom_right: Pos, color_tex: ColorTex, mul: f32, canvas: &mut Canvas)
 {
     let top_right = Pos { position: [ bottom_right.position[0],  top_left.position[1]] };
     let bottom_left = Pos { position: [ top_left.position[0], bottom_right.position[1]] };
 
-    draw_quad(top_left, top_right, bottom_right, bottom_left, color_tex, mul, target, display, program)
+    draw_quad(top_left, top_right, bottom_right, bottom_left, color_tex, mul, canvas)
+}
+
+/// Draws a lot of rects from `texture` in a single draw, for when a draw for each would be too slow. Each is given
+/// as `(top_left, bottom_right, tex_coords)`, and the whole rect is the color of the texture at `tex_coords`.
+fn draw_rects(rects: &[(Pos, Pos, [f32; 2])], texture: &Texture2d, mul: f32, canvas: &mut Canvas)
+{
+    if rects.is_empty() { return; }
+
+    let mut shape = Vec::with_capacity(rects.len() * 4);
+    let mut indices = Vec::with_capacity(rects.len() * 6);
+    for &(top_left, bottom_right, tex_coords) in rects
+    {
+        let first = shape.len() as u32;
+        let corners = [top_left.position, [bottom_right.position[0], top_left.position[1]], bottom_right.position,
+            [top_left.position[0], bottom_right.position[1]]];
+        shape.extend(corners.iter()
+            .map(|&position| Vertex { position: canvas.view.apply(Pos { position }), tex_coords }));
+        indices.extend([0, 1, 3, 1, 2, 3].iter().map(|&index| first + index));
+    }
+
+    // Like for a single quad, the only errors here are running out of memory or the code being wrong.
+    let shape_vb = glium::VertexBuffer::new(canvas.display, &shape).unwrap();
+    let indices = glium::IndexBuffer::new(canvas.display, glium::index::PrimitiveType::TrianglesList, &indices)
+        .unwrap();
+    let uniforms = glium::uniform! {
+        rgb_color: (0.0,0.0,0.0),
+        use_texture: true,
+        use_mask: false,
+        tex: texture.sampled(),
+        mult: mul,
+        alpha: canvas.view.alpha
+    };
+    let params = canvas.draw_parameters(false);
+    canvas.target.draw(&shape_vb, &indices, canvas.program, &uniforms, &params).unwrap();
+    canvas.draw_calls += 1;
 }
 
 /// Draws a line segment.
-fn draw_line(v1: Pos, v2: Pos, color: (f32,f32,f32), mul: f32, empty_tex: &Texture2d, target: &mut Frame,
-    display: &Display, program: &Program)
+fn draw_line(v1: Pos, v2: Pos, color: (f32,f32,f32), mul: f32, empty_tex: &Texture2d, canvas: &mut Canvas)
 {
     let line = vec![
-        Vertex { position: v1.position, tex_coords: [0.0,0.0] },
-        Vertex { position: v2.position, tex_coords: [0.0,0.0] },
+        Vertex { position: canvas.view.apply(v1), tex_coords: [0.0,0.0] },
+        Vertex { position: canvas.view.apply(v2), tex_coords: [0.0,0.0] },
     ];
-    let line_vb = match glium::VertexBuffer::new(display, &line)
+    let line_vb = match glium::VertexBuffer::new(canvas.display, &line)
     {
         Ok(vb) => vb,
         Err(glium::vertex::BufferCreationError::BufferCreationError(
@@ -194,31 +463,37 @@ fn draw_line(v1: Pos, v2: Pos, color: (f32,f32,f32), mul: f32, empty_tex: &Textu
     let uniforms = glium::uniform! {
             rgb_color: color,
             use_texture: false,
-            tex: empty_tex,
-            mult: mul
+            use_mask: false,
+            tex: empty_tex.sampled(),
+            mult: mul,
+            alpha: canvas.view.alpha
     };
     
     // Note that DrawErrors tend to be if the code was writen wrong and would cause a failure every time.
     // That is why I think an unwrap is ok
-    target.draw(&line_vb, &indices, program, &uniforms, &Default::default()).unwrap();
+    let params = canvas.draw_parameters(false);
+    canvas.target.draw(&line_vb, indices, canvas.program, &uniforms, &params).unwrap();
+    canvas.draw_calls += 1;
 }
 
-fn at_wall(pos: (f32, f32), horz: bool) -> u8
+fn at_wall(level: &Level, walls: &dyn Fn(Tile) -> u8, pos: (f32, f32), horz: bool) -> u8
 {
-    let pos2 = ((pos.0 + 1.0) * (GAME_WIDTH as f32) / 2.0, (pos.1 + 1.0) * (GAME_WIDTH as f32) / 2.0);
+    // Thin and diagonal walls do not fill their tiles, cast_on finds them inside of them as it enters them instead.
+    let walls = |tile| match walls(tile) { wall if level::is_line(wall) => 0, wall => wall };
+    let pos2 = ((pos.0 + 1.0) * (level.width as f32) / 2.0, (pos.1 + 1.0) * (level.height as f32) / 2.0);
     let (mut col, mut row) = (f32::floor(pos2.0) as usize, f32::floor(pos2.1) as usize);
 
-    if row as usize * GAME_WIDTH + col < GAME_WIDTH * GAME_HEIGHT
+    if row * level.width + col < level.width * level.height
     {
         let v1;
         let v2;
         if horz
         {
             row = pos2.1.round() as usize; // We have to round here because of floating point rounding errors.
-            v1 = GAME[row * GAME_WIDTH + col];
+            v1 = walls((col, row));
             if row != 0
             {
-                v2 = GAME[(row-1) * GAME_WIDTH + col];
+                v2 = walls((col, row - 1));
             }
             else
             {
@@ -228,10 +503,10 @@ fn at_wall(pos: (f32, f32), horz: bool) -> u8
         else
         {
             col = pos2.0.round() as usize;
-            v1 = GAME[row * GAME_WIDTH + col];
+            v1 = walls((col, row));
             if col != 0
             {
-                v2 = GAME[row * GAME_WIDTH + (col - 1)];
+                v2 = walls((col - 1, row));
             }
             else
             {
@@ -244,7 +519,15 @@ fn at_wall(pos: (f32, f32), horz: bool) -> u8
         // I chose to just pick the special wall because that made the code easier.
         // The only reason this would ever be an issue is if you are inside a wall looking at the
         // adjacent wall. It's hard to explain.
-        u8::max(v1,v2)
+        // See through walls are the exception, a solid wall behind one is what is hit and there is no face between
+        // two of the same kind.
+        match (level::is_see_through(v1), level::is_see_through(v2))
+        {
+            (true, true) if v1 == v2 => 0,
+            (true, false) if v2 > 0 => v2,
+            (false, true) if v1 > 0 => v1,
+            _ => u8::max(v1,v2),
+        }
     }
     else
     {
@@ -252,10 +535,187 @@ fn at_wall(pos: (f32, f32), horz: bool) -> u8
     }
 }
 
-/// Preforms a single ray cast.
+/// The most portals that a single ray goes through, past this the portal is drawn as the wall it is on.
+const MAX_PORTALS: usize = 8;
+/// The most times that a single ray bounces off of mirrors, past this the mirror is drawn as a plain wall.
+const MAX_BOUNCES: usize = 4;
+/// The most see through walls that a single ray goes through, past this they are drawn as solid walls.
+const MAX_LAYERS: usize = 8;
+/// The most faces of walls shorter than the tallest wall that a single ray passes over, past this the wall is drawn
+/// as if nothing could be seen over it.
+const MAX_STEPS: usize = 32;
+/// How opaque windows are.
+const GLASS_ALPHA: f32 = 0.3;
+/// How much darker the tops of walls are than their faces.
+const TOP_SHADE: f32 = 0.5;
+/// How much darker the view gets with each bounce off of a mirror.
+const MIRROR_DARKEN: f32 = 0.85;
+/// The color that mirrors tint what is seen in them, and how strongly they tint it with each bounce.
+const MIRROR_TINT: (f32, f32, f32) = (0.6, 0.8, 1.0);
+const MIRROR_TINT_ALPHA: f32 = 0.15;
+/// The corrected distance that the walls on the player's own tile are drawn at.
+const NEAR_WALL: f32 = 0.001;
+
+/// How a leg of a ray cast started.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum LegStart
+{
+    /// At the start of the ray.
+    Origin,
+    /// Coming out of a portal.
+    Portal,
+    /// Bouncing off of a mirror on a horizontal grid line or not, the same as the `if_on_horz` of a ray cast.
+    Mirror(bool),
+    /// Going through a see through wall of the given type, on a horizontal grid line or not.
+    SeeThrough(u8, bool),
+    /// Passing a face of a wall that is shorter than the tallest wall, on a horizontal grid line or not.
+    Over(bool),
+}
+
+/// A straight part of a ray cast, rays are split into legs where they go through portals and see through walls,
+/// over short walls and bounce off of mirrors. Of the form `(start, start_dist, how)`, where `start` is where the
+/// leg starts and the way it goes, `start_dist` is how far along the whole ray it starts and `how` is what started
+/// it.
+type RayLeg = (PlayerPos, f32, LegStart);
+
+/// Preforms a single ray cast, going through any portals and see through walls, over any walls that something
+/// taller could be seen past and bouncing off of any mirrors that it hits, see [`cast_straight`].
+/// 
+/// Returns a tuple of the form `(ray_dist, if_on_horz, wall_type, ray_end_pos, legs)`, where `ray_dist` is
+/// measured along every leg and `ray_end_pos` is on the last leg.
+/// 
+/// [`cast_straight`]: fn.cast_straight.html
+fn calc_dist_to_wall(level: &Level, player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32,f32), Vec<RayLeg>)
+{
+    let mut legs = vec![(PlayerPos { position: player_pos.position, ang: angle }, 0.0, LegStart::Origin)];
+    let (mut portals, mut bounces, mut layers, mut steps) = (0, 0, 0, 0);
+    loop
+    {
+        let &(start, start_dist, _) = legs.last().expect("there is always a first leg");
+        let (dist, horz, wall, ray_pos) = cast_on(level, &|tile| level.column_wall(tile), &start, start.ang);
+        let (_, to) = crossing(level, start.ang, horz, ray_pos);
+        let next = match wall
+        {
+            // Thin and diagonal walls are not on the grid line, so there is nothing past them to carry on to.
+            _ if wall == 0 || level::is_line(wall) => None,
+            _ if level::is_see_through(wall) && layers < MAX_LAYERS =>
+            {
+                layers += 1;
+                Some((go_through(level, start.ang, horz, ray_pos), LegStart::SeeThrough(wall, horz)))
+            },
+            MIRROR if bounces < MAX_BOUNCES =>
+            {
+                bounces += 1;
+                Some((reflect(level, start.ang, horz, ray_pos), LegStart::Mirror(horz)))
+            },
+            _ =>
+            {
+                let exit = if portals < MAX_PORTALS { portal::through(level, start.ang, horz, ray_pos) } else { None };
+                let to_height = to.map_or(level.tallest, |to| level.solid_height(to));
+                match exit
+                {
+                    Some(exit) =>
+                    {
+                        portals += 1;
+                        Some((exit, LegStart::Portal))
+                    },
+                    // Whatever is past a column of walls shorter than the tallest one could still be seen over or
+                    // under it.
+                    None if to_height < level.tallest && steps < MAX_STEPS =>
+                    {
+                        steps += 1;
+                        Some((go_through(level, start.ang, horz, ray_pos), LegStart::Over(horz)))
+                    },
+                    None => None,
+                }
+            },
+        };
+        match next
+        {
+            Some((next, how)) => legs.push((next, start_dist + dist, how)),
+            None =>
+            {
+                // The wall that is hit is the one on the far side of the grid line, even when the ray was over a
+                // different wall on the near side.
+                let wall = to.filter(|_| !level::is_line(wall)).map(|to| level.column_wall(to)).filter(|&to| to > 0)
+                    .unwrap_or(wall);
+                return (start_dist + dist, horz, wall, ray_pos, legs);
+            },
+        }
+    }
+}
+
+/// Where a ray cast going in the direction `ang` carries on from after bouncing off of a mirror, and the way it
+/// goes from there. `horz` and `ray_pos` are the `if_on_horz` and `ray_end_pos` of the cast.
+fn reflect(level: &Level, ang: f32, horz: bool, ray_pos: (f32, f32)) -> PlayerPos
+{
+    // Start just off of the mirror so the next cast does not hit it again straight away.
+    let gap = 0.001 * level.tile_size().0;
+    if horz
+    {
+        PlayerPos { position: [ray_pos.0, ray_pos.1 - gap * f32::sin(ang).signum()], ang: -ang }
+    }
+    else
+    {
+        PlayerPos { position: [ray_pos.0 - gap * f32::cos(ang).signum(), ray_pos.1], ang: std::f32::consts::PI - ang }
+    }
+}
+
+/// Where a ray cast going in the direction `ang` carries on from after going through a see through wall or over a
+/// short one. `horz` and `ray_pos` are the `if_on_horz` and `ray_end_pos` of the cast.
+fn go_through(level: &Level, ang: f32, horz: bool, ray_pos: (f32, f32)) -> PlayerPos
+{
+    // Start just past the face so the next cast does not hit it again straight away.
+    let gap = 0.001 * level.tile_size().0;
+    if horz
+    {
+        PlayerPos { position: [ray_pos.0, ray_pos.1 + gap * f32::sin(ang).signum()], ang }
+    }
+    else
+    {
+        PlayerPos { position: [ray_pos.0 + gap * f32::cos(ang).signum(), ray_pos.1], ang }
+    }
+}
+
+/// The tiles on either side of the grid line that a ray cast going in the direction `ang` hit, of the form
+/// `(from, to)`. Either is `None` if it is outside of the level. `horz` and `ray_pos` are the `if_on_horz` and
+/// `ray_end_pos` of the cast.
+fn crossing(level: &Level, ang: f32, horz: bool, ray_pos: (f32, f32)) -> (Option<Tile>, Option<Tile>)
+{
+    let (tile_w, tile_h) = level.tile_size();
+    let (grid_x, grid_y) = ((ray_pos.0 + 1.0) / tile_w, (ray_pos.1 + 1.0) / tile_h);
+    let (from, to) = if horz
+    {
+        let (col, line) = (f32::floor(grid_x) as isize, f32::round(grid_y) as isize);
+        if f32::sin(ang) > 0.0 { ((col, line - 1), (col, line)) } else { ((col, line), (col, line - 1)) }
+    }
+    else
+    {
+        let (line, row) = (f32::round(grid_x) as isize, f32::floor(grid_y) as isize);
+        if f32::cos(ang) > 0.0 { ((line - 1, row), (line, row)) } else { ((line, row), (line - 1, row)) }
+    };
+    let on_map = |(col, row): (isize, isize)|
+    {
+        if col < 0 || row < 0 || col as usize >= level.width || row as usize >= level.height { return None; }
+        Some((col as usize, row as usize))
+    };
+    (on_map(from), on_map(to))
+}
+
+/// Preforms a single ray cast in a straight line on the ground floor, stopping at the first wall even if it is a
+/// portal.
 /// 
 /// Returns a tuple of the form `(ray_dist, if_on_horz, wall_type, ray_end_pos)`.
-fn calc_dist_to_wall(player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32,f32))
+fn cast_straight(level: &Level, player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32,f32))
+{
+    cast_on(level, &|(col, row)| level.tile(col, row), player_pos, angle)
+}
+
+/// The same as [`cast_straight`], but with the wall type of each tile given by `walls`, so other storeys or whole
+/// columns of walls can be cast against.
+/// 
+/// [`cast_straight`]: fn.cast_straight.html
+fn cast_on(level: &Level, walls: &dyn Fn(Tile) -> u8, player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32,f32))
 {
     let mut yoffset;
     let mut xoffset;
@@ -263,8 +723,8 @@ fn calc_dist_to_wall(player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32
     let mut ray_y;
     let mut ray_x;
      
-    let yrungs = (GAME_HEIGHT as f32) / 2.0;
-    let xrungs = (GAME_WIDTH as f32) / 2.0;
+    let yrungs = (level.height as f32) / 2.0;
+    let xrungs = (level.width as f32) / 2.0;
 
     let mut dist_to_horz = 10000.0;
     let mut dist_to_vert = 10000.0;
@@ -272,9 +732,34 @@ fn calc_dist_to_wall(player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32
     let mut horz_wall = 0;
     let mut vert_wall = 0;
 
+    // Thin and diagonal walls are looked for on the player's tile and on each tile the ray enters as it crosses a
+    // grid line, keeping the nearest one that it hits.
+    let (tile_w, tile_h) = level.tile_size();
+    let mut line_hit: Option<(f32, bool, u8, (f32, f32))> = None;
+    let mut check_line = |position: [f32; 2]|
+    {
+        let tile = match level.tile_at(position) { Some(tile) => tile, None => return };
+        let wall = walls(tile);
+        if !level::is_line(wall) { return; }
+        let (start, end) = match level.line_wall(wall, tile) { Some(line) => line, None => return };
+        if let Some(dist) = ray_hits_line(player_pos.position, angle, start, end)
+        {
+            if line_hit.is_none_or(|hit| dist < hit.0)
+            {
+                let [x, y] = player_pos.position;
+                let end = (x + dist * f32::cos(angle), y + dist * f32::sin(angle));
+                line_hit = Some((dist, wall == THIN_TOP, wall, end));
+            }
+        }
+    };
+    // Just past a crossing, so on the tile that the ray is entering.
+    let nudge = 0.001 * f32::min(tile_w, tile_h);
+    let entering = |x: f32, y: f32| [x + nudge * f32::cos(angle), y + nudge * f32::sin(angle)];
+    check_line(player_pos.position);
+
     // Check Horizontal grid lines
     {
-        yoffset = 2.0 / (GAME_HEIGHT as f32);
+        yoffset = 2.0 / (level.height as f32);
         if f32::sin(angle) > 0.0
         {
             ray_y = f32::ceil(player_pos.position[1] * yrungs) / yrungs;
@@ -291,9 +776,10 @@ fn calc_dist_to_wall(player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32
         ray_x = (ray_y - player_pos.position[1]) / f32::tan(angle) + player_pos.position[0];
         xoffset = yoffset / f32::tan(angle);
 
-        while ray_x >= -1.0 && ray_x <= 1.0 && ray_y >= -1.0 && ray_y <= 1.0 && f32::sin(angle) != 0.0
+        while (-1.0..=1.0).contains(&ray_x) && (-1.0..=1.0).contains(&ray_y) && f32::sin(angle) != 0.0
         {
-            horz_wall = at_wall((ray_x, ray_y), true);
+            check_line(entering(ray_x, ray_y));
+            horz_wall = at_wall(level, walls, (ray_x, ray_y), true);
             if horz_wall > 0
             {
                 dist_to_horz = f32::sqrt((ray_y - player_pos.position[1]).powf(2.0) + (ray_x - player_pos.position[0]).powf(2.0));
@@ -308,7 +794,7 @@ fn calc_dist_to_wall(player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32
     
     // Check vertical grid lines
     {
-        xoffset = 2.0 / (GAME_WIDTH as f32);
+        xoffset = 2.0 / (level.width as f32);
         if f32::cos(angle) > 0.0
         {
             ray_x = f32::ceil(player_pos.position[0] * xrungs) / xrungs;
@@ -325,9 +811,10 @@ fn calc_dist_to_wall(player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32
         ray_y = (ray_x - player_pos.position[0]) * f32::tan(angle) + player_pos.position[1];
         yoffset = xoffset * f32::tan(angle);
 
-        while ray_x >= -1.0 && ray_x <= 1.0 && ray_y >= -1.0 && ray_y <= 1.0 && f32::cos(angle) != 0.0
+        while (-1.0..=1.0).contains(&ray_x) && (-1.0..=1.0).contains(&ray_y) && f32::cos(angle) != 0.0
         {
-            vert_wall = at_wall((ray_x, ray_y), false);
+            check_line(entering(ray_x, ray_y));
+            vert_wall = at_wall(level, walls, (ray_x, ray_y), false);
             if vert_wall > 0
             {
                 dist_to_vert = f32::sqrt((ray_y - player_pos.position[1]).powf(2.0) + (ray_x - player_pos.position[0]).powf(2.0));
@@ -339,128 +826,876 @@ fn calc_dist_to_wall(player_pos: &PlayerPos, angle: f32) -> (f32, bool, u8, (f32
     }
 
     // pick shortest
-    if dist_to_horz < dist_to_vert
+    let hit = if dist_to_horz < dist_to_vert
     {
         (dist_to_horz, true, horz_wall, (ray_x_h, ray_y_h))
     }
     else
     {
         (dist_to_vert, false, vert_wall, (ray_x, ray_y))
+    };
+    match line_hit
+    {
+        Some(line_hit) if line_hit.0 < hit.0 => line_hit,
+        _ => hit,
     }
-    
 }
 
+/// How far along a ray from `origin` going in the direction `ang` it crosses the line from `start` to `end`, if it
+/// does at all.
+fn ray_hits_line(origin: [f32; 2], ang: f32, start: [f32; 2], end: [f32; 2]) -> Option<f32>
+{
+    let cross = |a: [f32; 2], b: [f32; 2]| a[0] * b[1] - a[1] * b[0];
+    let dir = [f32::cos(ang), f32::sin(ang)];
+    let line = [end[0] - start[0], end[1] - start[1]];
+    let to_start = [start[0] - origin[0], start[1] - origin[1]];
+
+    let denom = cross(dir, line);
+    // The ray runs alongside the line, so it only ever grazes it.
+    if denom.abs() < f32::EPSILON { return None; }
+    let dist = cross(to_start, line) / denom;
+    let along = cross(to_start, dir) / denom;
+    if dist > 0.0 && (0.0..=1.0).contains(&along) { Some(dist) } else { None }
+}
+
+/// The tile that the thin or diagonal wall of type `wall` that a ray cast hit at `ray_pos` is on.
+fn line_tile(level: &Level, wall: u8, ray_pos: (f32, f32)) -> Option<Tile>
+{
+    // Thin walls are on the top or left edge of their tile, so step into it off of the edge.
+    let (tile_w, tile_h) = level.tile_size();
+    match wall
+    {
+        THIN_TOP => level.tile_at([ray_pos.0, ray_pos.1 + 0.001 * tile_h]),
+        THIN_LEFT => level.tile_at([ray_pos.0 + 0.001 * tile_w, ray_pos.1]),
+        _ => level.tile_at([ray_pos.0, ray_pos.1]),
+    }
+}
+
+/// Whether there are no walls on the straight line between two points in play space.
+/// 
+/// This casts a ray from `from` towards `to` the same way the 3d view does and checks that the first wall it hits
+/// is further away than `to`. Portals block the line like any other wall.
+fn line_of_sight(level: &Level, from: [f32; 2], to: [f32; 2]) -> bool
+{
+    let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+    let dist = f32::sqrt(dx * dx + dy * dy);
+    let ang = f32::atan2(dy, dx);
+    let (wall_dist, _, wall, _) = cast_straight(level, &PlayerPos { position: from, ang }, ang);
+    wall == 0 || wall_dist > dist
+}
+
+/// The result of one of the ray casts in [`ray_casts_in_view`] of the form
+/// `(ray_num, ray_ang, ray_dist, if_on_horz, wall_type, ray_pos, legs)`.
+/// 
+/// [`ray_casts_in_view`]: fn.ray_casts_in_view.html
+type ViewRay = (usize, f32, f32, bool, u8, (f32,f32), Vec<RayLeg>);
+
 /// Preforms all of the ray casts for the rendering with [`calc_dist_to_wall`].
 /// 
-/// Returns a vector of tuples of the form `(ray_num, ray_ang, ray_dist, if_on_horz, wall_type, ray_pos)`.
+/// Returns a vector of [`ViewRay`]s.
 /// 
 /// [`calc_dist_to_wall`]: fn.calc_dist_to_wall.html
-fn ray_casts_in_view(player_pos: &PlayerPos, rays: usize, fov: f32) -> Vec<(usize, f32, f32, bool, u8, (f32,f32))>
+/// [`ViewRay`]: type.ViewRay.html
+fn ray_casts_in_view(level: &Level, player_pos: &PlayerPos, rays: usize, fov: f32) -> Vec<ViewRay>
 {
     (0..rays)
         .map(|i| (i, player_pos.ang - fov/2.0 + i as f32 * fov / (rays as f32)))
-        .map(|(i, ray_ang)| {let res = calc_dist_to_wall(player_pos, ray_ang); (i, ray_ang, res.0, res.1, res.2, res.3)})
+        .map(|(i, ray_ang)| {let res = calc_dist_to_wall(level, player_pos, ray_ang); (i, ray_ang, res.0, res.1, res.2, res.3, res.4)})
         .collect()
 }
 
-fn get_colortex_for_wall<'a>(wall: u8, colors: bool, main_wall_texture: &'a Texture2d, 
-    wall2_texture: &'a Texture2d, wall3_texture: &'a Texture2d, empty_tex: &'a Texture2d, 
+/// Walks through every tile that a ray passes over, stopping at `max_dist` or the edge of the level.
+/// 
+/// Returns a vector of tuples of the form `(col, row, enter_dist, exit_dist)` in the order the ray passes them.
+fn tiles_on_ray(level: &Level, player_pos: &PlayerPos, angle: f32, max_dist: f32) -> Vec<(usize, usize, f32, f32)>
+{
+    let (tile_w, tile_h) = level.tile_size();
+    let (dir_x, dir_y) = (f32::cos(angle), f32::sin(angle));
+
+    let grid_x = (player_pos.position[0] + 1.0) / tile_w;
+    let grid_y = (player_pos.position[1] + 1.0) / tile_h;
+    let mut col = f32::floor(grid_x) as isize;
+    let mut row = f32::floor(grid_y) as isize;
+
+    // The distance along the ray to cross a whole tile and to the next tile edge, for both axes.
+    let (step_col, delta_x, mut next_x) = if dir_x > 0.0
+    {
+        (1, tile_w / dir_x, (col as f32 + 1.0 - grid_x) * tile_w / dir_x)
+    }
+    else if dir_x < 0.0
+    {
+        (-1, -tile_w / dir_x, (grid_x - col as f32) * tile_w / -dir_x)
+    }
+    else
+    {
+        (0, f32::INFINITY, f32::INFINITY)
+    };
+    let (step_row, delta_y, mut next_y) = if dir_y > 0.0
+    {
+        (1, tile_h / dir_y, (row as f32 + 1.0 - grid_y) * tile_h / dir_y)
+    }
+    else if dir_y < 0.0
+    {
+        (-1, -tile_h / dir_y, (grid_y - row as f32) * tile_h / -dir_y)
+    }
+    else
+    {
+        (0, f32::INFINITY, f32::INFINITY)
+    };
+
+    let mut tiles = Vec::new();
+    let mut dist = 0.0;
+    while dist < max_dist && col >= 0 && row >= 0 && (col as usize) < level.width && (row as usize) < level.height
+    {
+        let exit = f32::min(f32::min(next_x, next_y), max_dist);
+        tiles.push((col as usize, row as usize, dist, exit));
+        dist = exit;
+
+        if next_x < next_y
+        {
+            col += step_col;
+            next_x += delta_x;
+        }
+        else
+        {
+            row += step_row;
+            next_y += delta_y;
+        }
+    }
+
+    tiles
+}
+
+/// Walks through every tile that a ray cast passes over, leg by leg, stopping at `max_dist` along the whole ray.
+/// 
+/// Returns a vector of tuples of the form `(col, row, enter_dist, exit_dist)` like [`tiles_on_ray`], with the
+/// distances measured along the whole ray.
+/// 
+/// [`tiles_on_ray`]: fn.tiles_on_ray.html
+fn tiles_on_legs(level: &Level, legs: &[RayLeg], max_dist: f32) -> Vec<(usize, usize, f32, f32)>
+{
+    let mut tiles = Vec::new();
+    for (i, (start, start_dist, _)) in legs.iter().enumerate()
+    {
+        let end_dist = legs.get(i + 1).map_or(max_dist, |next| f32::min(next.1, max_dist));
+        if end_dist <= *start_dist { break; }
+        tiles.extend(tiles_on_ray(level, start, start.ang, end_dist - start_dist).into_iter()
+            .map(|(col, row, enter, exit)| (col, row, start_dist + enter, start_dist + exit)));
+    }
+    tiles
+}
+
+fn get_colortex_for_wall<'a>(wall: u8, colors: bool, textures: &'a Textures,
     tex_coords: ([f32; 2], [f32; 2], [f32; 2], [f32; 2]))
     -> ColorTex<'a>
 {
     match (wall, colors)
     {
-        (3,true) => ColorTex::Color(empty_tex, (1.0/f32::sqrt(2.0), 0.0, 1.0/f32::sqrt(2.0))),
-        (2,true) => ColorTex::Color(empty_tex, (0.0, 1.0, 0.0)),
-        (1, true) => ColorTex::Color(empty_tex, (1.0, 0.0, 0.0)),
-        (_, true) => ColorTex::Color(empty_tex, (0.0, 0.0, 0.0)),
+        (4..=6, true) => ColorTex::Color(&textures.empty,
+            KeyColor::from_door(wall).map_or((0.0, 0.0, 0.0), KeyColor::color)),
+        (EXIT_SWITCH, true) => ColorTex::Color(&textures.empty, (0.9, 0.9, 0.9)),
+        (MIRROR, _) => ColorTex::Color(&textures.empty, (0.75, 0.85, 0.9)),
+        (GRATE, _) => ColorTex::Texture(&textures.grate, tex_coords),
+        (GLASS, _) => ColorTex::Color(&textures.empty, (0.6, 0.8, 0.9)),
+        (3,true) => ColorTex::Color(&textures.empty, (1.0/f32::sqrt(2.0), 0.0, 1.0/f32::sqrt(2.0))),
+        (2,true) => ColorTex::Color(&textures.empty, (0.0, 1.0, 0.0)),
+        (1 | THIN_TOP..=DIAGONAL_UP, true) => ColorTex::Color(&textures.empty, (1.0, 0.0, 0.0)),
+        (_, true) => ColorTex::Color(&textures.empty, (0.0, 0.0, 0.0)),
+
+        (4..=6, false) =>
+        {
+            // Squeeze the coords into the door's third of the texture.
+            let left = KeyColor::from_door(wall).map_or(0, KeyColor::index) as f32;
+            let door = |[u, v]: [f32; 2]| [(left + u.clamp(0.0, 1.0)) / 3.0, v];
+            ColorTex::Texture(&textures.doors,
+                (door(tex_coords.0), door(tex_coords.1), door(tex_coords.2), door(tex_coords.3)))
+        },
+        (EXIT_SWITCH, false) => ColorTex::Texture(&textures.switch, tex_coords),
+        (3,false) => ColorTex::Texture(&textures.wall3, tex_coords),
+        (2,false) => ColorTex::Texture(&textures.wall2, tex_coords),
+        (1 | THIN_TOP..=DIAGONAL_UP, false) => ColorTex::Texture(&textures.main_wall, tex_coords),
+        (_, false) => ColorTex::Texture(&textures.empty, tex_coords),
+    }
+}
+
+/// Draws the part of the sky panorama that is seen when looking between `view_angs`, given as `(left, right)`,
+/// into the screen rect from `top_left` to `bottom_right`.
+/// 
+/// The panorama covers a full turn so it wraps around every `2π`, and it is fixed to the horizon so the rows
+/// drawn only depend on how far the rect is above the horizon. Its top is high enough to still fill the view when
+/// the player looks all the way up.
+fn draw_sky(top_left: Pos, bottom_right: Pos, view_angs: (f32, f32), sky_texture: &Texture2d, canvas: &mut Canvas)
+{
+    let turn = 2.0 * std::f32::consts::PI;
+    let u_left = (-view_angs.0 / turn).rem_euclid(1.0);
+    let u_right = u_left + (view_angs.0 - view_angs.1) / turn;
+    let sky_top = 1.0 + eye::MAX_PITCH;
+    let (v_top, v_bottom) = (top_left.position[1] / sky_top, bottom_right.position[1] / sky_top);
+
+    if u_right > 1.0
+    {
+        // Split the rect where the panorama wraps around.
+        let split_x = top_left.position[0] + 
+            (bottom_right.position[0] - top_left.position[0]) * (1.0 - u_left) / (u_right - u_left);
+        let tex_coords = ([u_left, v_top], [1.0, v_top], [1.0, v_bottom], [u_left, v_bottom]);
+        draw_rect(top_left, Pos { position: [split_x, v_bottom] }, ColorTex::Texture(sky_texture, tex_coords), 
+            1.0, canvas);
+        let tex_coords = ([0.0, v_top], [u_right - 1.0, v_top], [u_right - 1.0, v_bottom], [0.0, v_bottom]);
+        draw_rect(Pos { position: [split_x, v_top] }, bottom_right, ColorTex::Texture(sky_texture, tex_coords), 
+            1.0, canvas);
+    }
+    else
+    {
+        let tex_coords = ([u_left, v_top], [u_right, v_top], [u_right, v_bottom], [u_left, v_bottom]);
+        draw_rect(top_left, bottom_right, ColorTex::Texture(sky_texture, tex_coords), 1.0, canvas);
+    }
+}
 
-        (3,false) => ColorTex::Texture(wall3_texture, tex_coords),
-        (2,false) => ColorTex::Texture(wall2_texture, tex_coords),
-        (1, false) => ColorTex::Texture(main_wall_texture, tex_coords),
-        (_, false) => ColorTex::Texture(empty_tex, tex_coords),
+/// Where along the face of a wall a ray hit it, from `0.0` to `1.0`. This is the `u` texture coord of the hit.
+fn pos_on_wall(level: &Level, ray_ang: f32, horz: bool, ray_pos: (f32, f32)) -> f32
+{
+    let (tile_w, tile_h) = level.tile_size();
+    if horz
+    {
+        let block_on = f32::floor((ray_pos.0 + 1.0) / tile_w);
+        let pos = (ray_pos.0 + 1.0 - block_on * tile_w) / tile_w;
+        if f32::sin(ray_ang) > 0.0 {1.0 - pos} else {pos}
+    }
+    else
+    {
+        let block_on = f32::floor((ray_pos.1 + 1.0) / tile_h);
+        let pos = (ray_pos.1 + 1.0 - block_on * tile_h) / tile_h;
+        if f32::cos(ray_ang) < 0.0 {1.0 - pos} else {pos}
     }
 }
 
 // TODO: dont re draw calc/create the rects every time, use more uniforms
 /// Renders the game in 3d mode.
-fn draw_3d_game(display: &Display, program: &Program, player_pos: &PlayerPos, main_wall_texture: &Texture2d, 
-    wall2_texture: &Texture2d, wall3_texture: &Texture2d, empty_tex: &Texture2d)
+/// 
+/// Looking up and down shears the whole view so the horizon moves, everything is drawn around a horizon at `0` and
+/// the view's transform moves it to where the player's [`horizon`] is.
+/// 
+/// [`horizon`]: eye/fn.horizon.html
+fn draw_3d_game(canvas: &mut Canvas, level: &Level, player_pos: &PlayerPos, textures: &Textures, world: &World)
 {
-    let mut target = display.draw();
-    target.clear_color(0.0, 0.0, 1.0, 1.0);
-    draw_rect(Pos{ position: [-1.0,1.0]}, Pos{ position: [1.0,0.0]}, 
-        ColorTex::Color(empty_tex, (0.5, 0.5, 0.5)), 1.0, &mut target, display, program);
+    canvas.clear((0.0, 0.0, 1.0));
+    let view = canvas.view;
+    let horizon = eye::horizon(world);
+    canvas.view.transform[0][2] += view.transform[0][1] * horizon;
+    canvas.view.transform[1][2] += view.transform[1][1] * horizon;
+    // The top and bottom of the view, moved along with the horizon.
+    let (view_top, view_bottom) = (1.0 - horizon, -1.0 - horizon);
+
+    if level.open_sky
+    {
+        draw_sky(Pos{ position: [-1.0,view_top]}, Pos{ position: [1.0,0.0]}, 
+            (player_pos.ang + FOV/2.0, player_pos.ang - FOV/2.0), &textures.sky, canvas);
+    }
+    else
+    {
+        draw_rect(Pos{ position: [-1.0,view_top]}, Pos{ position: [1.0,0.0]}, 
+            ColorTex::Color(&textures.empty, (0.5, 0.5, 0.5)), 1.0, canvas);
+    }
 
     let rays = RAYS;
-
-    for (i, ray_ang, ray_dist, horz, wall, ray_pos) in ray_casts_in_view(player_pos, rays, FOV)
+    let eye = eye::height(world);
+    let column = |i: usize| ((rays-i-1) as f32 * 2.0 / rays as f32 - 1.0, (rays-i) as f32 * 2.0 / rays as f32 - 1.0);
+    // How far away the wall drawn in each column is, so sprites behind walls can be hidden.
+    let mut depths = vec![f32::INFINITY; rays];
+    // The see through walls in each column that sprites can be behind, nearest first.
+    let mut fronts: Vec<Vec<WallSlice>> = (0..rays).map(|_| Vec::new()).collect();
+    // The same for each mirror that rays first bounce off of, by the grid line it is on. Columns that do not see
+    // the mirror stay at no depth at all so nothing is drawn there.
+    let mut mirrors: Vec<((bool, i32), Vec<Window>)> = Vec::new();
+
+    for (i, ray_ang, ray_dist, horz, wall, ray_pos, legs) in ray_casts_in_view(level, player_pos, rays, FOV)
     {
-        if ray_dist > 100.0 || wall == 0 { continue; }
-        // I want to make the walls look more linear but I cant seem to figure out how.
-        let dist = ray_dist*f32::cos(f32::abs(ray_ang - player_pos.ang));//f32::cos(f32::abs(ray_ang - player_pos.dir)/10.0);
-        let height = (2.0/GAME_HEIGHT as f32) / dist;
+        let (col_left, col_right) = column(i);
+        let dist_mul = f32::cos(f32::abs(ray_ang - player_pos.ang));
 
-        let pos_on_wall = if horz
+        let tiles = tiles_on_legs(level, &legs, ray_dist);
+
+        // Punch the sky through the ceiling over any outdoor tiles between the player and the wall.
+        if !level.open_sky
         {
-            let block_on = f32::floor((ray_pos.0 + 1.0) * (GAME_WIDTH as f32) / 2.0);
-            let pos = (ray_pos.0 + 1.0 - block_on * (2.0/GAME_WIDTH as f32)) / (2.0/GAME_WIDTH as f32);
-            if f32::sin(ray_ang) > 0.0 {1.0 - pos} else {pos}
+            // The ceiling is over the top storey, so it stays above the player on every one of them.
+            let ceiling = level.storeys() as f32;
+            let mut sky_run: Option<(f32, f32)> = None;
+            for (j, &(col, row, enter_dist, exit_dist)) in tiles.iter().enumerate()
+            {
+                if level.is_sky(col, row)
+                {
+                    sky_run = Some((sky_run.map_or(enter_dist, |run| run.0), exit_dist));
+                    if j + 1 < tiles.len() { continue; }
+                }
+                if let Some((near, far)) = sky_run.take()
+                {
+                    let top = if near > 0.0 { f32::min(wall_y(level, eye, ceiling, near * dist_mul), view_top) }
+                        else { view_top };
+                    let bottom = wall_y(level, eye, ceiling, far * dist_mul);
+                    if bottom < view_top
+                    {
+                        draw_sky(Pos { position: [col_left, top] }, Pos { position: [col_right, bottom] },
+                            (ray_ang + FOV / rays as f32, ray_ang), &textures.sky, canvas);
+                    }
+                }
+            }
         }
-        else
+
+        // Color the floor of any special floors and teleporter pads between the player and the wall. The terrain
+        // colors them itself on outdoor levels.
+        for &(col, row, enter_dist, exit_dist) in tiles.iter().filter(|_| level.terrain.is_none())
+        {
+            let color = match floor_color(level, col, row) { Some(color) => color, None => continue };
+            let near = if enter_dist > 0.0 { f32::max(wall_y(level, eye, 0.0, enter_dist * dist_mul), view_bottom) }
+                else { view_bottom };
+            let far = wall_y(level, eye, 0.0, exit_dist * dist_mul);
+            draw_rect(Pos { position: [col_right, far] }, Pos { position: [col_left, near] },
+                ColorTex::Color(&textures.empty, color), 0.7 * level.light, canvas);
+        }
+
+        // How bright each leg is drawn, getting darker with each mirror it has bounced off of.
+        let bounces = |leg: usize| legs[..=leg].iter().filter(|leg| matches!(leg.2, LegStart::Mirror(_))).count();
+        let bounce_mul = |leg: usize| level.light * MIRROR_DARKEN.powi(bounces(leg) as i32);
+        // Every wall that the ray saw over, under or through before the one it stopped at, in the order that it
+        // passed them and with the leg that each starts. The walls stacked on each tile go from the highest down.
+        let mut slices: Vec<(usize, WallSlice)> = Vec::new();
+        // The tops of the walls that the ray was over end where it crosses out of them, unless there is another
+        // wall stacked right on top.
+        let top_ends = |slices: &mut Vec<(usize, WallSlice)>, from: Option<Tile>, dist: f32|
         {
-            let block_on = f32::floor((ray_pos.1 + 1.0) * (GAME_WIDTH as f32) / 2.0);
-            let pos = (ray_pos.1 + 1.0 - block_on * (2.0/GAME_WIDTH as f32)) / (2.0/GAME_WIDTH as f32);
-            if f32::cos(ray_ang) < 0.0 {1.0 - pos} else {pos}
+            let from = match from { Some(from) => from, None => return };
+            let leg = match slices.last() { Some(&(leg, _)) => leg, None => return };
+            for (_, slice) in slices.iter_mut().rev().take_while(|&&mut (j, _)| j == leg)
+            {
+                if level.wall_on(slice.bottom as usize + 1, from) == 0
+                {
+                    slice.far = Some(dist);
+                }
+            }
         };
-        let slice_width = f32::sin(FOV/RAYS as f32)*dist/(2.0/GAME_HEIGHT as f32);
+        // The walls stacked on a tile, with the one on the ground floor given by `ground` if it is set.
+        let column = |tile: Option<Tile>, ground: Option<(u8, f32)>, start: PlayerPos, horz: bool, dist: f32,
+            mul: f32|
+        {
+            let mut blocks: Vec<(u8, f32, f32)> = tile.map_or(Vec::new(), |tile| level.blocks(tile).collect());
+            if let Some((wall, height)) = ground
+            {
+                blocks.retain(|&(_, bottom, _)| bottom > 0.0);
+                blocks.push((wall, 0.0, height));
+            }
+            blocks.into_iter().map(move |(wall, bottom, height)| WallSlice
+            {
+                wall,
+                horz,
+                ang: start.ang,
+                ray_pos: (start.position[0], start.position[1]),
+                dist,
+                bottom,
+                height,
+                far: None,
+                mul,
+                ground: level.ground_at(start.position),
+            })
+        };
+        // The walls under the player on their own tile are right in front of them, so only their tops are seen.
+        if let Some(&(col, row, _, exit_dist)) = tiles.first()
+        {
+            let under = column(Some((col, row)), None, legs[0].0, false, NEAR_WALL, bounce_mul(0))
+                .filter(|slice| slice.ground + slice.height <= eye && !level::is_line(slice.wall))
+                .map(|slice| (0, slice));
+            slices.extend(under);
+            top_ends(&mut slices, Some((col, row)), exit_dist * dist_mul);
+        }
+        for (j, &(start, start_dist, how)) in legs.iter().enumerate()
+        {
+            let horz = match how { LegStart::SeeThrough(_, horz) | LegStart::Over(horz) => horz, _ => continue };
+            let (from, to) = crossing(level, start.ang, horz, (start.position[0], start.position[1]));
+            top_ends(&mut slices, from, start_dist * dist_mul);
+            // The far face of a see through wall is seen as well as the near one.
+            let ground = match how
+            {
+                LegStart::SeeThrough(wall, _) => Some((wall, to.or(from).map_or(1.0, |face| level.wall_height(face)))),
+                _ => None,
+            };
+            let crossed = column(to, ground, start, horz, start_dist * dist_mul, bounce_mul(j));
+            slices.extend(crossed.map(|slice| (j, slice)));
+        }
 
-        let tl = Pos { position: [(rays-i) as f32 * 2.0 / rays as f32 - 1.0, 0.0 + height] };
-        let br = Pos { position: [(rays-i-1) as f32 * 2.0 / rays as f32 - 1.0, 0.0 - height] };
-        
+        let last = legs.len() - 1;
+        // I want to make the walls look more linear but I cant seem to figure out how.
+        let dist = ray_dist*dist_mul;//f32::cos(f32::abs(ray_ang - player_pos.dir)/10.0);
+        let (from, to) = if level::is_line(wall) { (None, line_tile(level, wall, ray_pos)) }
+            else { crossing(level, legs[last].0.ang, horz, ray_pos) };
+        if wall > 0
+        {
+            top_ends(&mut slices, from, dist);
+        }
+        // The wall that stopped the ray is drawn as the type it was hit as, with any others stacked on it.
+        let height = to.map(|to| level.wall_height(to)).filter(|&height| height > 0.0).unwrap_or(1.0);
+        let end = PlayerPos { position: [ray_pos.0, ray_pos.1], ang: legs[last].0.ang };
+        let hit: Vec<WallSlice> = column(to, Some((wall, height)), end, horz, dist, bounce_mul(last)).collect();
+
+        // Walls hidden behind nearer ones are left out, as every solid wall standing on the ground floor hides
+        // everything below its top.
+        let mut cover = f32::NEG_INFINITY;
+        slices.retain(|(_, slice)|
+        {
+            let top = slice.top_y(level, eye);
+            let seen = top > cover;
+            if !level::is_see_through(slice.wall) && slice.bottom == 0.0
+            {
+                cover = f32::max(cover, top);
+            }
+            seen
+        });
+
+        // The walls up to the first portal or mirror can have sprites behind them, so they are drawn once the
+        // sprites are. The ones past it are drawn straight over the wall.
+        let split = legs.iter().skip(1).position(|leg| !matches!(leg.2, LegStart::SeeThrough(..) | LegStart::Over(_)))
+            .map_or(legs.len(), |j| j + 1);
+        let behind = slices.split_off(slices.iter().position(|&(j, _)| j >= split).unwrap_or(slices.len()));
+        fronts[i] = slices.into_iter().map(|(_, slice)| slice).collect();
+
+        if ray_dist > 100.0 || wall == 0 { continue; }
+        // Sprites are only drawn where they really are, so anything past the first portal or mirror hides them.
+        depths[i] = legs.get(split).map_or(dist, |leg| leg.1 * dist_mul);
+        if let Some(&(start, start_dist, LegStart::Mirror(mirror_horz))) = legs.get(split)
+        {
+            let line = mirror_line(level, mirror_horz, start.position);
+            let index = match mirrors.iter().position(|&(key, _)| key == line)
+            {
+                Some(index) => index,
+                None =>
+                {
+                    mirrors.push((line, vec![(0.0, 0.0); rays]));
+                    mirrors.len() - 1
+                },
+            };
+            // Only up to whatever the reflection reaches next, past that it is not the mirror that is seen.
+            mirrors[index].1[i] = (start_dist * dist_mul, legs.get(split + 1).map_or(dist, |leg| leg.1 * dist_mul));
+        }
+
+        for slice in hit.iter().filter(|slice| slice.top_y(level, eye) > cover)
+        {
+            draw_wall_slice(canvas, level, textures, (col_left, col_right), slice, eye);
+        }
+        if bounces(last) > 0 && hit.iter().any(|slice| slice.top_y(level, eye) > cover)
+        {
+            let view = canvas.view;
+            canvas.view.alpha = 1.0 - (1.0 - MIRROR_TINT_ALPHA).powi(bounces(last) as i32);
+            let eye = eye - level.ground_at(end.position);
+            draw_rect(Pos { position: [col_right, wall_y(level, eye, height, dist)] },
+                Pos { position: [col_left, wall_y(level, eye, 0.0, dist)] },
+                ColorTex::Color(&textures.empty, MIRROR_TINT), 1.0, canvas);
+            canvas.view = view;
+        }
+        for (_, slice) in behind.iter().rev()
+        {
+            draw_wall_slice(canvas, level, textures, (col_left, col_right), slice, eye);
+        }
+    }
+
+    // On outdoor levels the terrain is drawn in place of the floor, in front of the walls up to where sprites stop
+    // being drawn in each column.
+    let ground = level.terrain.as_ref().map_or(Vec::new(), |terrain|
+        terrain.draw_3d(canvas, level, player_pos, eye, view_bottom, &depths, &textures.empty));
+
+    // The rendering system for sprites, every entity with one other than the player is drawn.
+    let billboards: Vec<(Entity, Billboard)> = world.sprites.iter()
+        .filter_map(|(entity, sprite)|
+        {
+            let texture = match sprite.sheet
+            {
+                Sheet::Enemy => &textures.enemy,
+                Sheet::Projectiles => &textures.projectiles,
+                Sheet::Items => &textures.items,
+                Sheet::Player => &textures.player,
+            };
+            let frames = sprite.sheet.frames() as f32;
+            let position = world.transforms.get(entity)?.position;
+            Some((entity, Billboard
+            {
+                position,
+                texture,
+                frame: (sprite.frame as f32 / frames, (sprite.frame + 1) as f32 / frames),
+                size: sprite.size,
+                height: level.ground_at(position) + sprite.height,
+            }))
+        })
+        .collect();
+
+    // Mirrors show the reflection of every sprite on the player's side of them, the player's own included, drawn
+    // where the reflection would be standing and flipped. They go first so the see through walls in front of the
+    // mirrors are drawn over them.
+    let (tile_w, tile_h) = level.tile_size();
+    for ((horz, line), windows) in mirrors
+    {
+        let (axis, line_pos) = if horz { (1, line as f32 * tile_h - 1.0) } else { (0, line as f32 * tile_w - 1.0) };
+        let player_side = player_pos.position[axis] < line_pos;
+        let reflected: Vec<Billboard> = billboards.iter()
+            .filter(|(_, billboard)| (billboard.position[axis] < line_pos) == player_side)
+            .map(|&(_, billboard)|
+            {
+                let mut position = billboard.position;
+                position[axis] = 2.0 * line_pos - position[axis];
+                Billboard { position, frame: (billboard.frame.1, billboard.frame.0), ..billboard }
+            })
+            .collect();
+        draw_sprites(canvas, level, player_pos, &reflected, &windows, &[], eye);
+    }
+
+    // The sprites between each of the see through walls in front of them, from the back to the front, with each
+    // wall drawn over the sprites behind it.
+    let in_front: Vec<Billboard> = billboards.iter()
+        .filter(|&&(entity, _)| entity != world.player)
+        .map(|&(_, billboard)| billboard)
+        .collect();
+    let most = fronts.iter().map(Vec::len).max().unwrap_or(0);
+    for layer in (0..=most).rev()
+    {
+        let windows: Vec<Window> = fronts.iter().zip(depths.iter()).map(|(front, &depth)|
+        {
+            let near = if layer == 0 { 0.0 } else { front.get(layer - 1).map_or(f32::INFINITY, |slice| slice.dist) };
+            (near, front.get(layer).map_or(depth, |slice| slice.dist))
+        }).collect();
+        draw_sprites(canvas, level, player_pos, &in_front, &windows, &ground, eye);
+        if layer == 0 { continue; }
+        for (i, front) in fronts.iter().enumerate()
+        {
+            if let Some(slice) = front.get(layer - 1)
+            {
+                draw_wall_slice(canvas, level, textures, column(i), slice, eye);
+            }
+        }
+    }
+    canvas.view = view;
+}
+
+/// A column of a wall that a ray cast hit, see [`draw_wall_slice`].
+/// 
+/// [`draw_wall_slice`]: fn.draw_wall_slice.html
+struct WallSlice
+{
+    wall: u8,
+    /// The `if_on_horz` of the cast.
+    horz: bool,
+    /// The way the ray was going when it hit the wall.
+    ang: f32,
+    /// Where the ray hit the wall.
+    ray_pos: (f32, f32),
+    /// The corrected distance to the wall.
+    dist: f32,
+    /// How high the bottom of the wall is off of the ground floor compared to a tile, it is only above the ground
+    /// floor for walls on upper storeys.
+    bottom: f32,
+    /// How high the top of the wall is off of the ground floor compared to a tile.
+    height: f32,
+    /// The corrected distance to where the ray left the top of the wall, if it went over it.
+    far: Option<f32>,
+    /// How bright the wall is drawn.
+    mul: f32,
+    /// How high the ground the wall stands on is, see [`Level::ground_at`].
+    ///
+    /// [`Level::ground_at`]: level/struct.Level.html#method.ground_at
+    ground: f32,
+}
+
+impl WallSlice
+{
+    /// The highest point on the screen of the wall's face or top, seen from `eye` tiles off of the floor.
+    fn top_y(&self, level: &Level, eye: f32) -> f32
+    {
+        let eye = eye - self.ground;
+        let face = wall_y(level, eye, self.height, self.dist);
+        self.far.map_or(face, |far| f32::max(face, wall_y(level, eye, self.height, far)))
+    }
+}
+
+/// Where on the screen a point `height` tiles off of the floor is drawn, at the corrected distance `dist` and seen
+/// from `eye` tiles off of the floor.
+fn wall_y(level: &Level, eye: f32, height: f32, dist: f32) -> f32
+{
+    (height - eye) * 2.0 * level.tile_size().1 / dist
+}
+
+/// Draws a column of a wall into the screen column `column`, given as `(left, right)`, with any decals on it.
+/// Windows are blended over whatever is already drawn there.
+fn draw_wall_slice(canvas: &mut Canvas, level: &Level, textures: &Textures, column: (f32, f32), slice: &WallSlice,
+    eye: f32)
+{
+    // The wall is drawn as if the ground it stands on was the ground floor.
+    let eye = eye - slice.ground;
+    let (tile_w, tile_h) = level.tile_size();
+    // Diagonal walls are not on a grid line, the texture runs along them from left to right instead.
+    let pos_on_wall = if level::is_diagonal(slice.wall) { ((slice.ray_pos.0 + 1.0) / tile_w).fract() }
+        else { pos_on_wall(level, slice.ang, slice.horz, slice.ray_pos) };
+    let slice_width = f32::sin(FOV/RAYS as f32)*slice.dist/tile_h;
+    let mul = if slice.horz {0.8} else {1.0};
+
+    let view = canvas.view;
+    if slice.wall == GLASS
+    {
+        canvas.view.alpha *= GLASS_ALPHA;
+    }
+    // Walls taller than a tile repeat the texture a tile at a time from the bottom up, shorter ones cut it off.
+    let mut bottom = slice.bottom;
+    while bottom < slice.height
+    {
+        let piece = f32::min(slice.height - bottom, 1.0);
+        let tl = Pos { position: [column.1, wall_y(level, eye, bottom + piece, slice.dist)] };
+        let br = Pos { position: [column.0, wall_y(level, eye, bottom, slice.dist)] };
+        let tex_coords = ([pos_on_wall,piece],[pos_on_wall+slice_width,piece],
+            [pos_on_wall+slice_width,0.0],[pos_on_wall, 0.0]);
+        let color_tex = get_colortex_for_wall(slice.wall, COLORS, textures, tex_coords);
+        draw_rect(tl, br, color_tex, mul * slice.mul, canvas);
+        bottom += piece;
+    }
+    // The top is shaded with the wall's flat color, and can only be seen when it is below the player's eyes.
+    if let Some(far) = slice.far.filter(|_| slice.height < eye)
+    {
         let tex_coords = ([pos_on_wall,1.0],[pos_on_wall+slice_width,1.0],
             [pos_on_wall+slice_width,0.0],[pos_on_wall, 0.0]);
+        draw_rect(Pos { position: [column.1, wall_y(level, eye, slice.height, far)] },
+            Pos { position: [column.0, wall_y(level, eye, slice.height, slice.dist)] },
+            get_colortex_for_wall(slice.wall, true, textures, tex_coords), TOP_SHADE * slice.mul, canvas);
+    }
+    canvas.view = view;
+    draw_decals(canvas, level, slice, column, eye, textures);
+}
+
+/// The grid line that a mirror is on, from a point on or just off of it. Of the form `(if_on_horz, line)`, where
+/// `line` counts grid lines from the top or left of the level.
+fn mirror_line(level: &Level, horz: bool, position: [f32; 2]) -> (bool, i32)
+{
+    let (tile_w, tile_h) = level.tile_size();
+    if horz
+    {
+        (true, f32::round((position[1] + 1.0) / tile_h) as i32)
+    }
+    else
+    {
+        (false, f32::round((position[0] + 1.0) / tile_w) as i32)
+    }
+}
 
-        let color_tex = get_colortex_for_wall(wall, COLORS, main_wall_texture, wall2_texture, wall3_texture, empty_tex, tex_coords);
-        let mul = if horz {0.8} else {1.0};
+/// Draws the slice of any decals that are on the wall a ray hit, on top of the wall's column.
+/// 
+/// `column` is the `(left, right)` of the column in screen space and `eye` is how far off of the floor it is seen
+/// from.
+fn draw_decals(canvas: &mut Canvas, level: &Level, slice: &WallSlice, column: (f32, f32), eye: f32,
+    textures: &Textures)
+{
+    // Decals are only ever on grid lines, so none of them are on diagonal walls.
+    if level::is_diagonal(slice.wall) { return; }
+    let (tile_w, tile_h) = level.tile_size();
+    // Which axis runs along the face of the wall and how big a tile is across it.
+    let (along, across, across_size) = if slice.horz { (0, 1, tile_h) } else { (1, 0, tile_w) };
+    let ray_pos = [slice.ray_pos.0, slice.ray_pos.1];
+
+    // A shot fired over the top of a low wall still hits it, but there is nothing above the top to mark.
+    let on_slice = |decal: &&level::Decal| decal.horz == slice.horz
+        && (slice.bottom..slice.height).contains(&decal.height);
+    for decal in level.decals.iter().filter(on_slice)
+    {
+        let line = |pos: [f32; 2]| f32::round((pos[across] + 1.0) / across_size);
+        if line(decal.position) != line(ray_pos) { continue; }
+
+        let u = (ray_pos[along] - decal.position[along]) / (DECAL_SIZE * tile_w) + 0.5;
+        if !(0.0..1.0).contains(&u) { continue; }
+
+        let top = wall_y(level, eye, decal.height + DECAL_SIZE, slice.dist);
+        let bottom = wall_y(level, eye, decal.height - DECAL_SIZE, slice.dist);
+        let tex_coords = ([u, 1.0], [u, 1.0], [u, 0.0], [u, 0.0]);
+        draw_rect(Pos { position: [column.0, top] }, Pos { position: [column.1, bottom] },
+            ColorTex::Mask(&textures.bullet_hole, tex_coords, (0.05, 0.05, 0.05)), 1.0, canvas);
+    }
+}
+
+/// The range of corrected distances that sprites can be seen at in a column of the 3d view, of the form
+/// `(near, far)`. Usually this is from the player to the wall drawn in the column.
+type Window = (f32, f32);
+
+/// Draws billboards into the 3d view seen from `eye` tiles off of the floor, furthest first, hiding the parts of
+/// them that are outside of the [`Window`] of each column, indexed by ray. The parts behind the terrain drawn in a
+/// column are hidden as well, given its [`Profile`] in `ground` if there is one.
+/// 
+/// [`Window`]: type.Window.html
+/// [`Profile`]: terrain/type.Profile.html
+fn draw_sprites(canvas: &mut Canvas, level: &Level, player_pos: &PlayerPos, sprites: &[Billboard], windows: &[Window],
+    ground: &[terrain::Profile], eye: f32)
+{
+    let tile_w = level.tile_size().0;
+    let rays = windows.len();
+
+    // Find where each sprite is relative to the player, and skip the ones behind them.
+    let mut in_view: Vec<(f32, f32, &Billboard)> = sprites.iter().filter_map(|sprite|
+    {
+        let (dx, dy) = (sprite.position[0] - player_pos.position[0], sprite.position[1] - player_pos.position[1]);
+        let rel_ang = enemy::angle_diff(f32::atan2(dy, dx), player_pos.ang);
+        let forward = f32::sqrt(dx * dx + dy * dy) * f32::cos(rel_ang);
+        if forward > 0.01 { Some((forward, rel_ang, sprite)) } else { None }
+    }).collect();
+    in_view.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    for (forward, rel_ang, sprite) in in_view
+    {
+        // The same projection as the walls, the sprite is `size` tiles tall and floats `height` tiles up.
+        let center = -rel_ang / (FOV / 2.0);
+        let half_width = f32::atan2(tile_w * sprite.size / 2.0, forward) / (FOV / 2.0);
+        let bottom = wall_y(level, eye, sprite.height, forward);
+        let top = wall_y(level, eye, sprite.height + sprite.size, forward);
+        let (left, right) = (center - half_width, center + half_width);
+        if right < -1.0 || left > 1.0 { continue; }
+
+        // Draw the columns that are in front of the walls, joining neighbouring ones into a single quad.
+        let column = |i: usize| 1.0 - (i + 1) as f32 * 2.0 / rays as f32;
+        let first = f32::max(0.0, f32::floor((1.0 - right) * rays as f32 / 2.0)) as usize;
+        let last = usize::min(f32::max(0.0, f32::ceil((1.0 - left) * rays as f32 / 2.0)) as usize, rays);
+        let u = |x: f32| sprite.frame.0 + (sprite.frame.1 - sprite.frame.0) * (x - left) / (right - left);
+        // Runs are of the form `(left, right, bottom)`, the bottom being cut off where the terrain hides the sprite.
+        let draw_run = |(x_left, x_right, cut): (f32, f32, f32), canvas: &mut Canvas|
+        {
+            if x_right <= x_left { return; }
+            let v = (cut - bottom) / (top - bottom);
+            let tex_coords = ([u(x_left), 1.0], [u(x_right), 1.0], [u(x_right), v], [u(x_left), v]);
+            draw_rect(Pos { position: [x_left, top] }, Pos { position: [x_right, cut] },
+                ColorTex::Texture(sprite.texture, tex_coords), level.light, canvas);
+        };
 
-        draw_rect(tl, br, color_tex, mul, &mut target, display, program);
+        let mut run: Option<(f32, f32, f32)> = None;
+        for (i, &(near, far)) in windows.iter().enumerate().take(last).skip(first)
+        {
+            let cut = ground.get(i).map_or(bottom, |profile| f32::max(terrain::ground_top(profile, forward), bottom));
+            if near < forward && forward < far && cut < top
+            {
+                let (x_left, x_right) = (f32::max(column(i), left), f32::min(column(i) + 2.0 / rays as f32, right));
+                match run
+                {
+                    Some(ref mut run) if run.2 == cut => run.0 = x_left,
+                    _ =>
+                    {
+                        if let Some(run) = run.take() { draw_run(run, canvas); }
+                        run = Some((x_left, x_right, cut));
+                    },
+                }
+            }
+            else if let Some(run) = run.take()
+            {
+                draw_run(run, canvas);
+            }
+        }
+        if let Some(run) = run
+        {
+            draw_run(run, canvas);
+        }
     }
+}
 
-    let _ = target.finish();
+/// The color that the floor of a tile is drawn with, if it is drawn differently from plain floor.
+fn floor_color(level: &Level, col: usize, row: usize) -> Option<(f32, f32, f32)>
+{
+    if teleport::is_pad(level, (col, row)) { return Some(teleport::PAD_COLOR); }
+    level.floor(col, row).color()
 }
 
 /// Renders the game in 2d mode.
-fn draw_2d_game(display: &Display, program: &Program, player_pos: &PlayerPos, main_wall_texture: &Texture2d, 
-    wall2_texture: &Texture2d, wall3_texture: &Texture2d, empty_tex: &Texture2d)
+/// 
+/// If `show_debug` is set, the paths that the enemies are following and the areas of the triggers are drawn on top.
+fn draw_2d_game(canvas: &mut Canvas, level: &Level, player_pos: &PlayerPos, textures: &Textures, world: &World,
+    show_debug: bool)
 {
-    let mut target = display.draw();
-    target.clear_color(0.5, 0.5, 0.5, 1.0);
+    canvas.clear((0.5, 0.5, 0.5));
+    draw_2d_map(canvas, level, player_pos, textures, world, None);
+    teleport::draw_2d(level, canvas, &textures.empty);
+    if show_debug
+    {
+        trigger::draw_2d(world, level, canvas, &textures.empty);
+        for (entity, ai) in world.ais.iter()
+        {
+            if let Some(me) = world.transforms.get(entity)
+            {
+                ai.draw_path(me, canvas, &textures.empty);
+            }
+        }
+    }
+}
 
+/// Draws the board, the entities, the player and the rays from above, in play space coordinates.
+/// 
+/// If `explored` is given, only the tiles marked in it are drawn. The board is the storey that the player is on,
+/// with anywhere that they would fall through left dark.
+fn draw_2d_map(canvas: &mut Canvas, level: &Level, player_pos: &PlayerPos, textures: &Textures, world: &World,
+    explored: Option<&[bool]>)
+{
+    let storey = eye::storey(world);
+    if let Some(terrain) = &level.terrain
+    {
+        terrain.draw_2d(canvas);
+    }
+    // Thin and diagonal walls are drawn as lines over the floor of their tiles, once the whole board is drawn.
+    let mut lines = Vec::new();
     // draw board
-    for row in 0..GAME_HEIGHT
+    for row in 0..level.height
     {
-        for col in 0..GAME_WIDTH
+        for col in 0..level.width
         {
-            let tile = GAME[row * GAME_WIDTH + col];
-            let padding_h = 0.02 / (GAME_HEIGHT as f32);
-            let padding_w = 0.02 / (GAME_HEIGHT as f32);
+            if explored.is_some_and(|explored| !explored[row * level.width + col]) { continue; }
+
+            let tile = match level.wall_on(storey, (col, row))
+            {
+                wall if level::is_line(wall) =>
+                {
+                    lines.extend(level.line_wall(wall, (col, row)));
+                    0
+                },
+                wall => wall,
+            };
+            // The height map shows through everywhere that there is nothing else to draw.
+            if level.terrain.is_some() && tile == 0 && floor_color(level, col, row).is_none() { continue; }
+            let padding_h = 0.02 / (level.height as f32);
+            let padding_w = 0.02 / (level.height as f32);
             let this_tl = Pos { position: [
-                (col as f32 * 2.0 / (GAME_WIDTH as f32)) - 1.0 + padding_w, 
-                (row as f32 * 2.0 / (GAME_HEIGHT as f32)) - 1.0 + padding_h
+                (col as f32 * 2.0 / (level.width as f32)) - 1.0 + padding_w, 
+                (row as f32 * 2.0 / (level.height as f32)) - 1.0 + padding_h
             ] };
             let this_br = Pos { position: [
-                ((col + 1) as f32 * 2.0 / (GAME_WIDTH as f32)) - 1.0 - padding_w, 
-                ((row + 1) as f32 * 2.0 / (GAME_HEIGHT as f32)) - 1.0 - padding_h
+                ((col + 1) as f32 * 2.0 / (level.width as f32)) - 1.0 - padding_w, 
+                ((row + 1) as f32 * 2.0 / (level.height as f32)) - 1.0 - padding_h
             ] };
 
             let tex_coords = ([0.0,1.0],[1.0,1.0],[1.0,0.0],[0.0, 0.0]);
 
-            let color_tex = get_colortex_for_wall(tile, COLORS, main_wall_texture, wall2_texture, wall3_texture, empty_tex, tex_coords);
+            let color_tex = match floor_color(level, col, row)
+            {
+                _ if tile == 0 && !level.can_stand(storey, (col, row)) =>
+                    ColorTex::Color(&textures.empty, (0.05, 0.05, 0.05)),
+                Some(color) if tile == 0 && (storey == 0 || level.shaft_top((col, row)) >= storey) =>
+                    ColorTex::Color(&textures.empty, color),
+                _ => get_colortex_for_wall(tile, COLORS, textures, tex_coords),
+            };
             
-            draw_rect(this_tl, this_br, color_tex, 1.0, &mut target, display, program);
+            draw_rect(this_tl, this_br, color_tex, 1.0, canvas);
         }
     }
+    for (start, end) in lines
+    {
+        draw_line(Pos { position: start }, Pos { position: end }, (0.9, 0.9, 0.9), 1.0, &textures.empty, canvas);
+    }
+
+    // Nothing in the tiles that have not been explored yet is given away.
+    let seen = |position: [f32; 2]| explored.is_none_or(|explored|
+        level.tile_at(position).is_some_and(|(col, row)| explored[row * level.width + col]));
+    for (entity, ai) in world.ais.iter()
+    {
+        if let Some(me) = world.transforms.get(entity).filter(|me| seen(me.position))
+        {
+            ai.draw_2d(me, canvas, &textures.empty);
+        }
+    }
+    portal::draw_2d(level, canvas, &textures.empty);
+    pickup::draw_2d(world, canvas, &textures.empty, &seen);
+    projectile::draw_2d(world, level, canvas, &textures.empty, &seen);
 
     // draw player
     let player_size = 0.05;
@@ -468,11 +1703,11 @@ fn draw_2d_game(display: &Display, program: &Program, player_pos: &PlayerPos, ma
     let player_tl = Pos { position: [player_pos.position[0] - player_size/2.0, player_pos.position[1] - player_size/2.0] };
     let player_br = Pos { position: [player_pos.position[0] + player_size/2.0, player_pos.position[1] + player_size/2.0] };
     let player_dir = Pos { position: [player_pos.position[0] + 0.1*f32::cos(player_pos.ang), player_pos.position[1] + 0.1*f32::sin(player_pos.ang)] };
-    draw_rect(player_tl, player_br, ColorTex::Color(empty_tex, (0.1, 0.9, 0.1)), 1.0, &mut target, display, program);
-    draw_line(player_ver, player_dir, (1.0,1.0,0.0), 1.0, empty_tex, &mut target, display, program);
+    draw_rect(player_tl, player_br, ColorTex::Color(&textures.empty, (0.1, 0.9, 0.1)), 1.0, canvas);
+    draw_line(player_ver, player_dir, (1.0,1.0,0.0), 1.0, &textures.empty, canvas);
 
     // draw rays
-    for (_, ray_ang, ray_dist, _, wall, _) in ray_casts_in_view(player_pos, RAYS, FOV)
+    for (_, _, ray_dist, _, wall, _, legs) in ray_casts_in_view(level, player_pos, RAYS, FOV)
     {
         let color = match wall
         {
@@ -481,38 +1716,86 @@ fn draw_2d_game(display: &Display, program: &Program, player_pos: &PlayerPos, ma
             1 => (1.0, 0.0, 0.0),
             _ => (0.0, 0.0, 0.0)
         };
-        let ray_dir_ver = Pos { position: [player_pos.position[0] + ray_dist*f32::cos(ray_ang), player_pos.position[1] + ray_dist*f32::sin(ray_ang)] };
-        
-        draw_line(player_ver, ray_dir_ver, color, 1.0, empty_tex, &mut target, display, program);
+        // Each leg is drawn from where it really is, so rays that go through portals carry on from the other face
+        // and rays that bounce off of mirrors turn back, getting darker with each bounce.
+        let mut mul = 1.0;
+        for (i, (start, start_dist, how)) in legs.iter().enumerate()
+        {
+            if let LegStart::Mirror(_) = how { mul *= MIRROR_DARKEN; }
+            let leg_dist = legs.get(i + 1).map_or(ray_dist, |next| next.1) - start_dist;
+            let ray_dir_ver = Pos { position: [start.position[0] + leg_dist*f32::cos(start.ang), start.position[1] + leg_dist*f32::sin(start.ang)] };
+            draw_line(Pos { position: start.position }, ray_dir_ver, color, mul, &textures.empty, canvas);
+        }
     }
-
-    let _ = target.finish();
 }
 
 /// Renders a single frame for the game.
-fn main_loop(display: &Display, program: &Program, player_pos: &PlayerPos, draw_3d: bool, 
-    main_wall_texture: &Texture2d, wall2_texture: &Texture2d, wall3_texture: &Texture2d, empty_tex: &Texture2d)
+/// 
+/// Returns how many draws it took.
+/// 
+/// The `intermission` screen is drawn over the game if there is one.
+fn main_loop(mut canvas: Canvas, level: &Level, world: &World, view_mode: ViewMode, textures: &Textures, 
+    overlays: &Overlays, intermission: Option<&Intermission>) -> usize
 {
-    if draw_3d
+    let player_pos = &world.player_pos();
+    let weapon = world.weapons.get(world.player);
+    let (width, height) = canvas.dimensions();
+    let view_3d = match view_mode
     {
-        draw_3d_game(display, program, player_pos, main_wall_texture, wall2_texture, wall3_texture, empty_tex);
-    }
-    else
+        ViewMode::ThreeD =>
+        {
+            draw_3d_game(&mut canvas, level, player_pos, textures, world);
+            if let Some(weapon) = weapon { weapon.draw(&mut canvas, textures); }
+            overlays.hud.draw(&mut canvas, level, world, textures);
+            overlays.minimap.draw(&mut canvas, level, player_pos, textures, world);
+            player::draw(world, &mut canvas, textures);
+            Some(glium::Rect { left: 0, bottom: 0, width, height })
+        },
+        ViewMode::TwoD =>
+        {
+            draw_2d_game(&mut canvas, level, player_pos, textures, world, overlays.debug.shown);
+            player::draw(world, &mut canvas, textures);
+            None
+        },
+        ViewMode::Split =>
+        {
+            let half = width / 2;
+            let view_3d = glium::Rect { left: half, bottom: 0, width: width - half, height };
+
+            canvas.view.viewport = Some(glium::Rect { left: 0, bottom: 0, width: half, height });
+            draw_2d_game(&mut canvas, level, player_pos, textures, world, overlays.debug.shown);
+            canvas.view.viewport = Some(view_3d);
+            draw_3d_game(&mut canvas, level, player_pos, textures, world);
+            if let Some(weapon) = weapon { weapon.draw(&mut canvas, textures); }
+            overlays.hud.draw(&mut canvas, level, world, textures);
+            player::draw(world, &mut canvas, textures);
+            canvas.view.viewport = None;
+            Some(view_3d)
+        },
+    };
+    if let Some(intermission) = intermission
     {
-        draw_2d_game(display, program, player_pos, main_wall_texture, wall2_texture, wall3_texture, empty_tex);
+        intermission.draw(&mut canvas, textures);
+        overlays.hud.draw_messages(&mut canvas, textures);
     }
+    overlays.debug.draw(&mut canvas, level, world, textures, view_3d);
+    canvas.finish()
 }
 
-/// Moves the player based on what keys are pressed and what walls are near by.
+/// Moves the player based on what keys are pressed and what walls are near by on the `storey` they are on, taking
+/// them through any portal they walk into on the ground floor.
+/// 
+/// Returns how far they walked in tiles.
 fn move_player(keys: &HashMap<glutin::event::VirtualKeyCode,glutin::event::VirtualKeyCode>, 
-    player_pos: &mut PlayerPos, frame_time: f32)
-{
-    let rays = [calc_dist_to_wall(player_pos, 0.0).0,
-        calc_dist_to_wall(player_pos, std::f32::consts::PI / 2.0).0,
-        calc_dist_to_wall(player_pos, std::f32::consts::PI).0, 
-        calc_dist_to_wall(player_pos, - std::f32::consts::PI / 2.0).0];
-    let min_dist = 0.2/GAME_HEIGHT as f32;
-    let move_speed = MOVE_SPEED * frame_time;
+    level: &Level, storey: usize, player_pos: &mut PlayerPos, frame_time: f32) -> f32
+{
+    let walls = |tile| level.wall_on(storey, tile);
+    let rays = [cast_on(level, &walls, player_pos, 0.0).0,
+        cast_on(level, &walls, player_pos, std::f32::consts::PI / 2.0).0,
+        cast_on(level, &walls, player_pos, std::f32::consts::PI).0, 
+        cast_on(level, &walls, player_pos, - std::f32::consts::PI / 2.0).0];
+    let min_dist = 0.1 * level.tile_size().1;
+    let move_speed = MOVE_SPEED * level.tile_size().1 * frame_time;
     let look_speed = LOOK_SPEED * frame_time;
 
     let mut x_move = 0.0;
@@ -539,17 +1822,111 @@ fn move_player(keys: &HashMap<glutin::event::VirtualKeyCode,glutin::event::Virtu
         y_move -= move_speed * f32::cos(player_pos.ang);
     }
 
-    if rays[0] >= min_dist && x_move > 0.0 || rays[2] >= min_dist && x_move < 0.0
+    let mut walked = (0.0, 0.0);
+    let through = if storey == 0 { portal::walk(level, player_pos, (x_move, y_move), min_dist) } else { None };
+    if let Some(through) = through
     {
-        player_pos.position[0] += x_move;
+        *player_pos = through;
+        walked = (x_move, y_move);
     }
-    if rays[1] >= min_dist && y_move > 0.0 || rays[3] >= min_dist && y_move < 0.0
+    else
     {
-        player_pos.position[1] += y_move;
+        if rays[0] >= min_dist && x_move > 0.0 || rays[2] >= min_dist && x_move < 0.0
+        {
+            player_pos.position[0] += x_move;
+            walked.0 = x_move;
+        }
+        if rays[1] >= min_dist && y_move > 0.0 || rays[3] >= min_dist && y_move < 0.0
+        {
+            player_pos.position[1] += y_move;
+            walked.1 = y_move;
+        }
     }
 
     if keys.contains_key(&glutin::event::VirtualKeyCode::Left) { player_pos.ang += look_speed }
     if keys.contains_key(&glutin::event::VirtualKeyCode::Right) { player_pos.ang -= look_speed }
+    f32::sqrt(walked.0 * walked.0 + walked.1 * walked.1) / level.tile_size().0
+}
+
+/// Runs every system for a single frame, telling the player about anything that happened to them on the HUD.
+/// 
+/// Returns whether the player reached the exit.
+fn update_game(keys: &HashMap<glutin::event::VirtualKeyCode,glutin::event::VirtualKeyCode>, level: &mut Level,
+    world: &mut World, flow: &mut FlowField, stats: &mut LevelStats, hud: &mut Hud, frame_time: f32) -> bool
+{
+    stats.time += frame_time;
+    let storey = eye::storey(world);
+    let walked = move_player(keys, level, storey, world.player_pos_mut(), frame_time);
+    let look = keys.contains_key(&glutin::event::VirtualKeyCode::PageUp) as i32
+        - keys.contains_key(&glutin::event::VirtualKeyCode::PageDown) as i32;
+    eye::update(world, level, keys.contains_key(&glutin::event::VirtualKeyCode::Q),
+        keys.contains_key(&glutin::event::VirtualKeyCode::C), walked, look as f32, frame_time);
+    trigger::update(world, level, keys.contains_key(&glutin::event::VirtualKeyCode::E));
+    script::update(world, level, frame_time);
+    trigger::process(world, level, flow, hud);
+    teleport::update(world, level, frame_time);
+    for item in pickup::update(world, level)
+    {
+        hud.show_message(&format!("PICKED UP {}", item.name()));
+    }
+    for event in pickup::open_doors(world, level)
+    {
+        match event
+        {
+            DoorEvent::Opened(key) =>
+            {
+                hud.show_message(&format!("{} DOOR OPENED", key.name()));
+                // The enemies can walk through where the door was now.
+                *flow = FlowField::new(level, flow.target);
+            },
+            DoorEvent::Locked(key) => hud.show_message(&format!("YOU NEED THE {} KEY", key.name())),
+        }
+    }
+    let firing = keys.contains_key(&glutin::event::VirtualKeyCode::Space);
+    weapon::update(world, level, firing, frame_time);
+    enemy::update(world, level, flow, frame_time);
+    projectile::update(world, level, frame_time);
+    let mut exited = false;
+    for event in player::update(world, level, frame_time)
+    {
+        match event
+        {
+            PlayerEvent::Checkpoint => hud.show_message("CHECKPOINT"),
+            PlayerEvent::Secret =>
+            {
+                stats.secrets += 1;
+                hud.show_message("YOU FOUND A SECRET");
+            },
+            PlayerEvent::Exit => exited = true,
+        }
+    }
+    exited
+}
+
+/// Loads the map that the campaign is on and fills it with its entities.
+/// 
+/// If the player is coming from the level before, what they have is carried over from the `carried` world, which is
+/// left as it is if the map can not be loaded.
+/// 
+/// Returns a message saying what is wrong with the map if it can not be loaded.
+fn start_level(campaign: &Campaign, carried: Option<&mut World>)
+    -> Result<(Level, World, FlowField, LevelStats), String>
+{
+    let level = Level::load(campaign.map_path())?;
+    let world = campaign::new_world(&level, carried);
+    let flow = FlowField::new(&level, level.tile_at(level.start.position).unwrap_or((0, 0)));
+    let stats = LevelStats::new(&level, &world);
+    Ok((level, world, flow, stats))
+}
+
+/// Grabs and hides the cursor for looking with the mouse, or lets it go.
+fn grab_cursor(display: &Display, grab: bool)
+{
+    let gl_window = display.gl_window();
+    let window = gl_window.window();
+    // Not every platform can grab the cursor, looking with the mouse still works without it.
+    let _ = window.set_cursor_grab(grab);
+    window.set_cursor_visible(!grab);
 }
 
 /// Loads a texture from a image file.
@@ -564,7 +1941,25 @@ fn load_texture(file_path: &str, display: &Display) -> Result<Texture2d, glium::
 }
 
 fn main() {
-    let draw_3d = std::env::args().nth(1).unwrap_or_else(|| String::from("3d")).to_lowercase() != "2d";
+    let mut view_mode = match std::env::args().nth(1).unwrap_or_else(|| String::from("3d")).to_lowercase().as_str()
+    {
+        "2d" => ViewMode::TwoD,
+        "split" => ViewMode::Split,
+        _ => ViewMode::ThreeD,
+    };
+
+    // There is nothing to play without the campaign and its first map, so the game stops before it opens a window.
+    let loaded = Campaign::load(std::path::Path::new(CAMPAIGN))
+        .and_then(|campaign| start_level(&campaign, None).map(|started| (campaign, started)));
+    let (mut campaign, (mut level, mut world, mut flow, mut stats)) = match loaded
+    {
+        Ok(loaded) => loaded,
+        Err(message) =>
+        {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        },
+    };
 
     let event_loop = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
@@ -573,10 +1968,24 @@ fn main() {
     let display = glium::Display::new(wb, cb, &event_loop).unwrap();
 
     // load textures
-    let main_wall_texture = load_texture(r"textures\stone.jpg", &display).unwrap();
-    let wall2_texture = load_texture(r"textures\brick.png", &display).unwrap();
-    let wall3_texture = load_texture(r"textures\mossy.jpg", &display).unwrap();
-    let empty_tex = Texture2d::empty(&display, 1,1).unwrap();
+    let textures = Textures
+    {
+        main_wall: load_texture(r"textures\stone.jpg", &display).unwrap(),
+        wall2: load_texture(r"textures\brick.png", &display).unwrap(),
+        wall3: load_texture(r"textures\mossy.jpg", &display).unwrap(),
+        sky: load_texture(r"textures\sky.png", &display).unwrap(),
+        enemy: load_texture(r"textures\enemy.png", &display).unwrap(),
+        weapon: load_texture(r"textures\pistol.png", &display).unwrap(),
+        bullet_hole: load_texture(r"textures\bullet_hole.png", &display).unwrap(),
+        projectiles: load_texture(r"textures\projectiles.png", &display).unwrap(),
+        items: load_texture(r"textures\items.png", &display).unwrap(),
+        doors: load_texture(r"textures\doors.png", &display).unwrap(),
+        switch: load_texture(r"textures\switch.png", &display).unwrap(),
+        grate: load_texture(r"textures\grate.png", &display).unwrap(),
+        player: load_texture(r"textures\player.png", &display).unwrap(),
+        empty: Texture2d::new(&display, vec![vec![(0u8, 0u8, 0u8, 255u8)]]).unwrap(),
+        font: Font::new(&display).unwrap(),
+    };
 
     let vertex_shader_src = r#"
         #version 140
@@ -595,22 +2004,34 @@ fn main() {
         out vec4 color;
         uniform vec3 rgb_color;
         uniform bool use_texture;
+        uniform bool use_mask;
         uniform sampler2D tex;
         uniform float mult;
+        uniform float alpha;
         void main() {
-            if(use_texture) {
-                color = texture(tex, v_tex_coords) * mult * 0.5;
+            if(use_mask) {
+                color = vec4(rgb_color * mult, texture(tex, v_tex_coords).a * alpha);
+            } else if(use_texture) {
+                vec4 tex_color = texture(tex, v_tex_coords);
+                // Fully see through pixels are left out so sprites can be drawn over the walls.
+                if(tex_color.a == 0.0) discard;
+                color = vec4(tex_color.rgb * mult * 0.5, tex_color.a * alpha);
             } else {
-                color = vec4(rgb_color, 1.0) * mult;
+                color = vec4(rgb_color * mult, alpha);
             }
         }
     "#;
 
     let program = glium::Program::from_source(&display, vertex_shader_src, fragment_shader_src, None).unwrap();
 
-    let mut player_pos = START_POS;
+    let mut intermission: Option<Intermission> = None;
+    let mut overlays = Overlays { minimap: Minimap::new(&level), hud: Hud::default(), debug: DebugOverlay::default() };
+    overlays.hud.show_message(&level.name);
 
     let mut keys_down = HashMap::<glutin::event::VirtualKeyCode, glutin::event::VirtualKeyCode>::new();
+    // The cursor is grabbed for looking with the mouse while the window has focus and the game is being played.
+    let mut focused = true;
+    let mut grabbed = false;
 
     let mut start = time::Instant::now();
 
@@ -620,6 +2041,8 @@ fn main() {
         start = time::Instant::now();
         let next_frame_time = time::Instant::now() + time::Duration::from_nanos(33_333_333); // 60fps
         *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
+        // Every event is handled and drawn, but the events waiting each time round the loop make up one frame.
+        let frame_end = matches!(event, glutin::event::Event::MainEventsCleared);
 
         match event 
         {
@@ -630,29 +2053,165 @@ fn main() {
                     *control_flow = glutin::event_loop::ControlFlow::Exit;
                     return;
                 },
+                glutin::event::WindowEvent::CursorMoved { position, .. } =>
+                {
+                    overlays.debug.set_cursor((position.x, position.y));
+                },
+                glutin::event::WindowEvent::Focused(focus) => focused = focus,
                 _ => return,
             },
-            glutin::event::Event::DeviceEvent {event, ..} => 
+            glutin::event::Event::DeviceEvent { event: glutin::event::DeviceEvent::MouseMotion { delta }, .. } =>
             {
-                if let glutin::event::DeviceEvent::Key(key) = event
-                {
-                    if let Some(letter) = key.virtual_keycode  
+                // Moving the mouse up looks up, and the window's y goes down the screen.
+                if grabbed { eye::look(&mut world, -delta.1 as f32 * MOUSE_PITCH); }
+                return;
+            },
+            glutin::event::Event::DeviceEvent { event: glutin::event::DeviceEvent::Key(key), .. } => 
+            {
+                if let Some(letter) = key.virtual_keycode  
+                { 
+                    if glutin::event::ElementState::Pressed == key.state
                     { 
-                        if glutin::event::ElementState::Pressed == key.state
-                        { 
-                            keys_down.entry(letter).or_insert(letter);
-                        }
-                        else
+                        if keys_down.insert(letter, letter).is_none()
                         {
-                            let _ = keys_down.remove(&letter);
+                            let dead = player::is_dead(&world) || intermission.is_some();
+                            match letter
+                            {
+                                glutin::event::VirtualKeyCode::V => view_mode = view_mode.next(),
+                                glutin::event::VirtualKeyCode::B => overlays.hud.show_message(
+                                    if eye::toggle_head_bob(&mut world) { "HEAD BOB ON" } else { "HEAD BOB OFF" }),
+                                glutin::event::VirtualKeyCode::Space if intermission.is_some() =>
+                                {
+                                    // A finished campaign starts over from scratch, otherwise the player carries on.
+                                    let carry = intermission.as_ref()
+                                        .is_some_and(|intermission| intermission.advance != Advance::Finished);
+                                    match start_level(&campaign, if carry { Some(&mut world) } else { None })
+                                    {
+                                        Ok((next_level, next_world, next_flow, next_stats)) =>
+                                        {
+                                            intermission = None;
+                                            level = next_level;
+                                            world = next_world;
+                                            flow = next_flow;
+                                            stats = next_stats;
+                                            overlays.minimap.forget(&level);
+                                            overlays.hud.show_message(&level.name);
+                                            // Space also fires, so it has to be pressed again to shoot.
+                                            keys_down.remove(&letter);
+                                        },
+                                        // The intermission stays up, there is no level to move on to.
+                                        Err(message) => overlays.hud.show_message(&message),
+                                    }
+                                },
+                                glutin::event::VirtualKeyCode::Space if dead =>
+                                {
+                                    player::respawn(&mut world);
+                                    keys_down.remove(&letter);
+                                },
+                                glutin::event::VirtualKeyCode::R if !dead =>
+                                    weapon::launch(&mut world, &level, ProjectileKind::Rocket),
+                                glutin::event::VirtualKeyCode::G if !dead =>
+                                    weapon::launch(&mut world, &level, ProjectileKind::Grenade),
+                                _ => (),
+                            }
+                            overlays.on_key_pressed(letter);
                         }
                     }
+                    else
+                    {
+                        let _ = keys_down.remove(&letter);
+                    }
                 }
             },
             _ => (),
         }
-        move_player(&keys_down, &mut player_pos, frame_time);
-        main_loop(&display, &program, &player_pos, draw_3d, &main_wall_texture, &wall2_texture, 
-            &wall3_texture, &empty_tex);
+        // The debug overlay's ray inspector needs the cursor.
+        let grab = focused && intermission.is_none() && !player::is_dead(&world) && !overlays.debug.shown;
+        if grab != grabbed
+        {
+            grab_cursor(&display, grab);
+            grabbed = grab;
+        }
+
+        let update_start = time::Instant::now();
+        if intermission.is_some()
+        {
+            // The game waits on the intermission screen until the player moves on.
+        }
+        else if player::is_dead(&world)
+        {
+            // Nothing else happens while the death screen shows.
+            player::update_dead(&mut world, frame_time);
+        }
+        else if update_game(&keys_down, &mut level, &mut world, &mut flow, &mut stats, &mut overlays.hud, frame_time)
+        {
+            let episode_name = campaign.episode_name().to_string();
+            let advance = campaign.advance();
+            intermission = Some(stats.finish(&level, &world, advance, &episode_name));
+        }
+        if frame_end
+        {
+            overlays.minimap.explore(&level, &world.player_pos());
+        }
+        overlays.hud.update(frame_time);
+        let update_time = update_start.elapsed().as_secs_f32();
+
+        let render_start = time::Instant::now();
+        let draw_calls = main_loop(Canvas::new(&display, &program), &level, &world, view_mode, &textures, &overlays,
+            intermission.as_ref());
+        overlays.debug.record_frame(frame_time, update_time, render_start.elapsed().as_secs_f32(), draw_calls);
     });
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn close(a: Option<f32>, b: f32) -> bool
+    {
+        a.is_some_and(|a| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn ray_hits_line_across()
+    {
+        assert!(close(ray_hits_line([0.0, 0.0], 0.0, [2.0, -1.0], [2.0, 1.0]), 2.0));
+        let diagonal = ray_hits_line([0.0, 0.0], std::f32::consts::FRAC_PI_2, [-1.0, 0.0], [1.0, 2.0]);
+        assert!(close(diagonal, 1.0));
+    }
+
+    #[test]
+    fn ray_hits_line_parallel()
+    {
+        assert_eq!(ray_hits_line([0.0, 0.0], 0.0, [1.0, 1.0], [3.0, 1.0]), None);
+        assert_eq!(ray_hits_line([0.0, 0.0], 0.0, [1.0, 0.0], [3.0, 0.0]), None);
+    }
+
+    #[test]
+    fn ray_hits_line_ends()
+    {
+        assert!(close(ray_hits_line([0.0, 0.0], 0.0, [2.0, 0.0], [2.0, 1.0]), 2.0));
+        assert!(close(ray_hits_line([0.0, 0.0], 0.0, [2.0, -1.0], [2.0, 0.0]), 2.0));
+        assert_eq!(ray_hits_line([0.0, 0.0], 0.0, [2.0, 0.1], [2.0, 1.0]), None);
+    }
+
+    #[test]
+    fn ray_hits_line_behind()
+    {
+        assert_eq!(ray_hits_line([0.0, 0.0], 0.0, [-2.0, -1.0], [-2.0, 1.0]), None);
+        assert!(close(ray_hits_line([0.0, 0.0], std::f32::consts::PI, [-2.0, -1.0], [-2.0, 1.0]), 2.0));
+    }
+
+    #[test]
+    fn start_level_missing_map()
+    {
+        let path = std::env::temp_dir().join(format!("start-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "episode Missing\nmissing.map\n").unwrap();
+        let campaign = Campaign::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let message = start_level(&campaign.unwrap(), None).err().unwrap();
+        assert!(message.starts_with(&path.with_file_name("missing.map").display().to_string()));
+    }
+}
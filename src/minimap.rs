@@ -0,0 +1,126 @@
+//! A small top down map that is drawn over a corner of the 3d view.
+
+use glium::glutin::event::VirtualKeyCode;
+use crate::level::Level;
+use crate::world::World;
+use crate::{Canvas, ColorTex, PlayerPos, Pos, Textures, View, FOV, RAYS};
+
+/// Side length of the minimap as a fraction of the smaller side of the window.
+const MINIMAP_SIZE: f32 = 0.35;
+/// How far the minimap starts zoomed in, at `1.0` the minimap is as wide as the whole level.
+const MINIMAP_ZOOM: f32 = 2.0;
+/// How much the zoom changes with each key press.
+const MINIMAP_ZOOM_STEP: f32 = 1.25;
+/// How opaque the minimap is.
+const MINIMAP_ALPHA: f32 = 0.7;
+/// Space between the minimap and the corner of the window in pixels.
+const MINIMAP_MARGIN: u32 = 10;
+
+/// The minimap's settings along with which tiles the player has seen so far.
+pub struct Minimap
+{
+    /// Whether the minimap is drawn at all.
+    pub shown: bool,
+    /// If set the map turns with the player so forward is always up, otherwise north is always up.
+    pub rotate: bool,
+    /// Side length as a fraction of the smaller side of the window.
+    pub size: f32,
+    /// At `1.0` the minimap is as wide as the whole level.
+    pub zoom: f32,
+    /// If set only tiles the player has seen are shown.
+    pub fog_of_war: bool,
+    explored: Vec<bool>,
+}
+
+impl Minimap
+{
+    pub fn new(level: &Level) -> Minimap
+    {
+        Minimap
+        {
+            shown: true,
+            rotate: true,
+            size: MINIMAP_SIZE,
+            zoom: MINIMAP_ZOOM,
+            fog_of_war: true,
+            explored: vec![false; level.width * level.height],
+        }
+    }
+
+    /// Forgets every tile that has been explored, for when a new level starts.
+    pub fn forget(&mut self, level: &Level)
+    {
+        self.explored = vec![false; level.width * level.height];
+    }
+
+    /// Changes the settings for the minimap keys, any other key is ignored.
+    pub fn on_key_pressed(&mut self, key: VirtualKeyCode)
+    {
+        match key
+        {
+            VirtualKeyCode::M => self.shown = !self.shown,
+            VirtualKeyCode::N => self.rotate = !self.rotate,
+            VirtualKeyCode::F => self.fog_of_war = !self.fog_of_war,
+            VirtualKeyCode::Equals | VirtualKeyCode::Add => self.zoom = f32::min(self.zoom * MINIMAP_ZOOM_STEP, 8.0),
+            VirtualKeyCode::Minus | VirtualKeyCode::Subtract => self.zoom = f32::max(self.zoom / MINIMAP_ZOOM_STEP, 0.5),
+            _ => (),
+        }
+    }
+
+    /// Marks every tile that can currently be seen by the player as explored, including the walls at the end of
+    /// each ray. Nothing is explored while fog of war is off, as every tile is shown anyway.
+    pub fn explore(&mut self, level: &Level, player_pos: &PlayerPos)
+    {
+        if !self.fog_of_war { return; }
+        let past_wall = level.tile_size().0 * 0.01;
+        for (_, _, ray_dist, _, _, _, legs) in crate::ray_casts_in_view(level, player_pos, RAYS, FOV)
+        {
+            for (col, row, _, _) in crate::tiles_on_legs(level, &legs, ray_dist + past_wall)
+            {
+                self.explored[row * level.width + col] = true;
+            }
+        }
+    }
+
+    /// Draws the minimap into the top right corner of the window, centered on the player.
+    pub fn draw(&self, canvas: &mut Canvas, level: &Level, player_pos: &PlayerPos, textures: &Textures,
+        world: &World)
+    {
+        if !self.shown { return; }
+
+        let (width, height) = canvas.dimensions();
+        let size = (self.size * u32::min(width, height) as f32) as u32;
+        let scissor = glium::Rect
+        {
+            left: width.saturating_sub(size + MINIMAP_MARGIN),
+            bottom: height.saturating_sub(size + MINIMAP_MARGIN),
+            width: size,
+            height: size,
+        };
+        let center = [(scissor.left as f32 + size as f32 / 2.0) / width as f32 * 2.0 - 1.0,
+            (scissor.bottom as f32 + size as f32 / 2.0) / height as f32 * 2.0 - 1.0];
+
+        let screen_view = canvas.view;
+        canvas.view = View { scissor: Some(scissor), alpha: MINIMAP_ALPHA, ..View::full_screen() };
+        crate::draw_rect(Pos { position: [-1.0, 1.0] }, Pos { position: [1.0, -1.0] },
+            ColorTex::Color(&textures.empty, (0.2, 0.2, 0.2)), 1.0, canvas);
+
+        // Move the player to the center, turn, then scale play space units to pixels and pixels to the window.
+        let turn = if self.rotate { std::f32::consts::PI / 2.0 - player_pos.ang } else { 0.0 };
+        let pixels_per_unit = self.zoom * size as f32 / 2.0;
+        let (scale_x, scale_y) = (2.0 * pixels_per_unit / width as f32, 2.0 * pixels_per_unit / height as f32);
+        let (a, b) = (scale_x * f32::cos(turn), -scale_x * f32::sin(turn));
+        let (c, d) = (scale_y * f32::sin(turn), scale_y * f32::cos(turn));
+        let [x, y] = player_pos.position;
+        canvas.view.transform = [
+            [a, b, center[0] - a * x - b * y],
+            [c, d, center[1] - c * x - d * y],
+            [0.0, 0.0, 1.0],
+        ];
+
+        let explored = if self.fog_of_war { Some(&self.explored[..]) } else { None };
+        crate::draw_2d_map(canvas, level, player_pos, textures, world, explored);
+
+        canvas.view = screen_view;
+    }
+}
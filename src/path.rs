@@ -0,0 +1,287 @@
+//! Finding ways around the walls of a level, moving from tile to tile.
+//!
+//! Moves can be made to any of the 8 tiles around a tile, but a diagonal move is only allowed if both of the tiles
+//! it passes between are walkable too, so paths never cut the corner of a wall.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use glium::texture::Texture2d;
+use crate::level::Level;
+use crate::{Canvas, Pos};
+
+/// A tile of the level as `(col, row)`.
+pub type Tile = (usize, usize);
+
+/// The cost of moving to a tile next to this one.
+const STRAIGHT_COST: u32 = 10;
+/// The cost of moving to a tile diagonal to this one, `√2` times [`STRAIGHT_COST`] rounded.
+///
+/// [`STRAIGHT_COST`]: constant.STRAIGHT_COST.html
+const DIAGONAL_COST: u32 = 14;
+/// Cost of tiles that can not be reached.
+const UNREACHABLE: u32 = u32::MAX;
+
+/// The tiles that can be moved to from `tile` along with the cost of each move.
+fn neighbours(level: &Level, tile: Tile) -> Vec<(Tile, u32)>
+{
+    let (col, row) = (tile.0 as isize, tile.1 as isize);
+    let walkable = |dc: isize, dr: isize| col + dc >= 0 && row + dr >= 0 &&
+        level.is_walkable((col + dc) as usize, (row + dr) as usize);
+
+    let mut tiles = Vec::with_capacity(8);
+    for &(dc, dr) in [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)].iter()
+    {
+        if !walkable(dc, dr) { continue; }
+        let diagonal = dc != 0 && dr != 0;
+        if diagonal && !(walkable(dc, 0) && walkable(0, dr)) { continue; }
+
+        let next = ((col + dc) as usize, (row + dr) as usize);
+        tiles.push((next, if diagonal { DIAGONAL_COST } else { STRAIGHT_COST }));
+    }
+    tiles
+}
+
+/// The cost of the cheapest way between two tiles if there were no walls at all.
+fn estimate(from: Tile, to: Tile) -> u32
+{
+    let dc = (from.0 as isize - to.0 as isize).unsigned_abs() as u32;
+    let dr = (from.1 as isize - to.1 as isize).unsigned_abs() as u32;
+    let (short, long) = (u32::min(dc, dr), u32::max(dc, dr));
+    short * DIAGONAL_COST + (long - short) * STRAIGHT_COST
+}
+
+/// Finds the cheapest path between two tiles with A*.
+///
+/// Returns the tiles to walk through in order, not including `from` but including `to`, or `None` if there is no
+/// way to get there.
+pub fn find_path(level: &Level, from: Tile, to: Tile) -> Option<Vec<Tile>>
+{
+    if !level.is_walkable(to.0, to.1) { return None; }
+
+    let index = |tile: Tile| tile.1 * level.width + tile.0;
+    let mut costs = vec![UNREACHABLE; level.width * level.height];
+    let mut came_from: Vec<Option<Tile>> = vec![None; level.width * level.height];
+    let mut open = BinaryHeap::new();
+
+    costs[index(from)] = 0;
+    open.push(Reverse((estimate(from, to), from)));
+    while let Some(Reverse((_, tile))) = open.pop()
+    {
+        if tile == to
+        {
+            let mut path = vec![to];
+            while let Some(prev) = came_from[index(*path.last().unwrap())]
+            {
+                if prev == from { break; }
+                path.push(prev);
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for (next, step_cost) in neighbours(level, tile)
+        {
+            let cost = costs[index(tile)] + step_cost;
+            if cost < costs[index(next)]
+            {
+                costs[index(next)] = cost;
+                came_from[index(next)] = Some(tile);
+                open.push(Reverse((cost + estimate(next, to), next)));
+            }
+        }
+    }
+    None
+}
+
+/// The cost of getting from every tile to a single target tile, found with Dijkstra's algorithm.
+///
+/// This is shared by every enemy going after the same target, so a whole crowd can find its way with a single
+/// search instead of one each.
+pub struct FlowField
+{
+    /// The tile everything flows towards.
+    pub target: Tile,
+    width: usize,
+    /// The cost of getting to [`target`] from each tile, row major.
+    ///
+    /// [`target`]: #structfield.target
+    costs: Vec<u32>,
+}
+
+impl FlowField
+{
+    /// Finds the cost from every tile to `target`.
+    pub fn new(level: &Level, target: Tile) -> FlowField
+    {
+        let index = |tile: Tile| tile.1 * level.width + tile.0;
+        let mut costs = vec![UNREACHABLE; level.width * level.height];
+        let mut open = BinaryHeap::new();
+
+        if level.is_walkable(target.0, target.1)
+        {
+            costs[index(target)] = 0;
+            open.push(Reverse((0, target)));
+        }
+        while let Some(Reverse((cost, tile))) = open.pop()
+        {
+            if cost > costs[index(tile)] { continue; }
+
+            // Moves are the same both ways, so the cost of moving in to a tile is the cost of moving out of it.
+            for (next, step_cost) in neighbours(level, tile)
+            {
+                if cost + step_cost < costs[index(next)]
+                {
+                    costs[index(next)] = cost + step_cost;
+                    open.push(Reverse((cost + step_cost, next)));
+                }
+            }
+        }
+
+        FlowField { target, width: level.width, costs }
+    }
+
+    /// Searches again if the target has moved to a different tile.
+    pub fn retarget(&mut self, level: &Level, target: Tile)
+    {
+        if target != self.target
+        {
+            *self = FlowField::new(level, target);
+        }
+    }
+
+    /// The cost of getting to the target from `tile`, or `None` if it can not get there.
+    fn cost(&self, tile: Tile) -> Option<u32>
+    {
+        let cost = *self.costs.get(tile.1 * self.width + tile.0)?;
+        if cost == UNREACHABLE { None } else { Some(cost) }
+    }
+
+    /// The tile to move to next from `tile` to get closer to the target.
+    pub fn next_step(&self, level: &Level, tile: Tile) -> Option<Tile>
+    {
+        let here = self.cost(tile)?;
+        neighbours(level, tile).into_iter()
+            .filter_map(|(next, _)| self.cost(next).map(|cost| (cost, next)))
+            .filter(|&(cost, _)| cost < here)
+            .min()
+            .map(|(_, next)| next)
+    }
+
+    /// Follows the field from `tile` to the target.
+    ///
+    /// Returns the tiles passed through in order in the same form as [`find_path`], or `None` if it can not get
+    /// there.
+    ///
+    /// [`find_path`]: fn.find_path.html
+    pub fn path_from(&self, level: &Level, tile: Tile) -> Option<Vec<Tile>>
+    {
+        self.cost(tile)?;
+        let mut path = Vec::new();
+        let mut current = tile;
+        while let Some(next) = self.next_step(level, current)
+        {
+            path.push(next);
+            current = next;
+        }
+        Some(path)
+    }
+}
+
+/// Draws a path from `start` through each of the points of `path`, in play space coordinates.
+pub fn draw_path(start: [f32; 2], path: &[[f32; 2]], color: (f32, f32, f32), empty_tex: &Texture2d,
+    canvas: &mut Canvas)
+{
+    let mut from = start;
+    for &point in path
+    {
+        crate::draw_line(Pos { position: from }, Pos { position: point }, color, 1.0, empty_tex, canvas);
+        from = point;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn find_path_straight()
+    {
+        let level = Level::from_rows(&["11111", "10001", "11111"]);
+        assert_eq!(find_path(&level, (1, 1), (3, 1)), Some(vec![(2, 1), (3, 1)]));
+        assert_eq!(find_path(&level, (1, 1), (1, 1)), Some(vec![(1, 1)]));
+    }
+
+    #[test]
+    fn find_path_does_not_cut_corners()
+    {
+        // Going from the bottom left to the top right has to go around the wall's corner, not through it.
+        let level = Level::from_rows(&["1111", "1001", "1101", "1111"]);
+        assert_eq!(find_path(&level, (1, 1), (2, 2)), Some(vec![(2, 1), (2, 2)]));
+        let path = find_path(&level, (2, 2), (1, 1)).unwrap();
+        assert_eq!(path, vec![(2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn find_path_goes_diagonally_in_the_open()
+    {
+        let level = Level::from_rows(&["1111", "1001", "1001", "1111"]);
+        assert_eq!(find_path(&level, (1, 1), (2, 2)), Some(vec![(2, 2)]));
+    }
+
+    #[test]
+    fn find_path_around_doors_and_walls()
+    {
+        // The red door on the short way is shut, so the path goes the long way round.
+        let level = Level::from_rows(&["11111", "10401", "10101", "10001", "11111"]);
+        let path = find_path(&level, (1, 1), (3, 1)).unwrap();
+        assert_eq!(path, vec![(1, 2), (1, 3), (2, 3), (3, 3), (3, 2), (3, 1)]);
+        assert!(path.iter().all(|&(col, row)| level.is_walkable(col, row)));
+    }
+
+    #[test]
+    fn find_path_unreachable()
+    {
+        let level = Level::from_rows(&["11111", "10101", "11111"]);
+        assert_eq!(find_path(&level, (1, 1), (3, 1)), None);
+        // Walls and tiles off of the level can not be walked to at all.
+        assert_eq!(find_path(&level, (1, 1), (2, 1)), None);
+        assert_eq!(find_path(&level, (1, 1), (9, 9)), None);
+    }
+
+    #[test]
+    fn flow_field_leads_to_target()
+    {
+        let level = Level::from_rows(&["111111", "100001", "101101", "100001", "111111"]);
+        let flow = FlowField::new(&level, (4, 3));
+        for (col, row) in [(1, 1), (4, 1), (1, 3), (2, 1)]
+        {
+            let path = flow.path_from(&level, (col, row)).unwrap();
+            assert_eq!(path.last(), Some(&(4, 3)), "from {},{}", col, row);
+            // Every step gets cheaper, so it never wanders off.
+            let costs: Vec<u32> = path.iter().map(|&tile| flow.cost(tile).unwrap()).collect();
+            assert!(costs.windows(2).all(|pair| pair[1] < pair[0]));
+        }
+        assert_eq!(flow.next_step(&level, (4, 2)), Some((4, 3)));
+        assert_eq!(flow.path_from(&level, (4, 3)), Some(Vec::new()));
+    }
+
+    #[test]
+    fn flow_field_unreachable()
+    {
+        let level = Level::from_rows(&["11111", "10101", "11111"]);
+        let flow = FlowField::new(&level, (3, 1));
+        assert_eq!(flow.path_from(&level, (1, 1)), None);
+        assert_eq!(flow.next_step(&level, (1, 1)), None);
+    }
+
+    #[test]
+    fn flow_field_retarget()
+    {
+        let level = Level::from_rows(&["11111", "10001", "11111"]);
+        let mut flow = FlowField::new(&level, (1, 1));
+        flow.retarget(&level, (3, 1));
+        assert_eq!(flow.target, (3, 1));
+        assert_eq!(flow.path_from(&level, (1, 1)), Some(vec![(2, 1), (3, 1)]));
+    }
+}
@@ -0,0 +1,397 @@
+//! Items lying around the level that are picked up by walking into them, and the colored doors that keys unlock.
+//!
+//! Levels place items through an item layout, which uses the codes of [`Item::from_code`]. Doors are wall tiles
+//! whose type is the same code as their key.
+//!
+//! [`Item::from_code`]: enum.Item.html#method.from_code
+
+use glium::texture::Texture2d;
+use crate::enemy::dist_between;
+use crate::eye;
+use crate::level::Level;
+use crate::player::MAX_ARMOR;
+use crate::world::{Entity, Sheet, Sprite, Transform, World};
+use crate::{Canvas, ColorTex, Pos};
+
+/// Health given back by a medkit.
+const HEALTH_AMOUNT: i32 = 25;
+/// Armor given by a vest.
+const ARMOR_AMOUNT: i32 = 50;
+/// Bullets in a box of ammo.
+const AMMO_AMOUNT: u32 = 10;
+/// How close in tiles an item has to be to the edge of a collector's collider for it to be picked up.
+const PICKUP_REACH: f32 = 0.2;
+/// How close in tiles the player has to be to the face of a door to try to open it.
+const DOOR_REACH: f32 = 0.3;
+
+/// The colors of keys, and of the doors that they open.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum KeyColor
+{
+    Red,
+    Blue,
+    Yellow,
+}
+
+impl KeyColor
+{
+    /// The wall type of the doors that the key opens.
+    pub fn door(self) -> u8
+    {
+        match self
+        {
+            KeyColor::Red => 4,
+            KeyColor::Blue => 5,
+            KeyColor::Yellow => 6,
+        }
+    }
+
+    /// The key that opens a door of the given wall type, if it is a door.
+    pub fn from_door(wall: u8) -> Option<KeyColor>
+    {
+        [KeyColor::Red, KeyColor::Blue, KeyColor::Yellow].iter().copied().find(|key| key.door() == wall)
+    }
+
+    /// Its place among the keys, the frames of the key sprites and the door texture are in this order.
+    pub fn index(self) -> usize
+    {
+        self as usize
+    }
+
+    pub fn color(self) -> (f32, f32, f32)
+    {
+        match self
+        {
+            KeyColor::Red => (0.9, 0.1, 0.1),
+            KeyColor::Blue => (0.2, 0.3, 0.9),
+            KeyColor::Yellow => (0.95, 0.85, 0.1),
+        }
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            KeyColor::Red => "RED",
+            KeyColor::Blue => "BLUE",
+            KeyColor::Yellow => "YELLOW",
+        }
+    }
+}
+
+/// The different things that can be picked up.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Item
+{
+    /// Gives back some health, it is left where it is if the player is not hurt.
+    Health,
+    /// Gives some armor, it is left where it is if the player's armor is full.
+    Armor,
+    /// Bullets for the gun.
+    Ammo,
+    /// Only counts towards the player's score.
+    Treasure,
+    /// Opens the doors of its color.
+    Key(KeyColor),
+}
+
+impl Item
+{
+    /// The item for a code in a level's item layout. `1` is health, `2` is ammo, `3` is treasure, `7` is armor and
+    /// the keys use the wall type of their doors. Anything else, like `0`, is no item.
+    pub fn from_code(code: u8) -> Option<Item>
+    {
+        match code
+        {
+            1 => Some(Item::Health),
+            2 => Some(Item::Ammo),
+            3 => Some(Item::Treasure),
+            7 => Some(Item::Armor),
+            _ => KeyColor::from_door(code).map(Item::Key),
+        }
+    }
+
+    /// Which frame of the [`Sheet::Items`] sprite sheet it is drawn with.
+    ///
+    /// [`Sheet::Items`]: ../world/enum.Sheet.html#variant.Items
+    pub fn frame(self) -> usize
+    {
+        match self
+        {
+            Item::Health => 0,
+            Item::Ammo => 1,
+            Item::Treasure => 2,
+            Item::Key(key) => 3 + key.index(),
+            Item::Armor => 6,
+        }
+    }
+
+    /// What the player is told when they pick it up.
+    pub fn name(self) -> String
+    {
+        match self
+        {
+            Item::Health => String::from("MEDKIT"),
+            Item::Armor => String::from("ARMOR"),
+            Item::Ammo => String::from("AMMO"),
+            Item::Treasure => String::from("TREASURE"),
+            Item::Key(key) => format!("{} KEY", key.name()),
+        }
+    }
+
+    /// Its color in the 2d view.
+    fn color(self) -> (f32, f32, f32)
+    {
+        match self
+        {
+            Item::Health => (1.0, 1.0, 1.0),
+            Item::Armor => (0.2, 0.6, 0.2),
+            Item::Ammo => (0.4, 0.5, 0.2),
+            Item::Treasure => (1.0, 0.8, 0.2),
+            Item::Key(key) => key.color(),
+        }
+    }
+}
+
+/// The pickup component, for an entity that is an item waiting to be picked up.
+pub struct Pickup
+{
+    pub item: Item,
+}
+
+/// The inventory component, for an entity that picks up items. Ammo goes into its weapon and health into its
+/// health, so this only holds what has nowhere else to go.
+#[derive(Default)]
+pub struct Inventory
+{
+    /// How many pieces of treasure have been picked up.
+    pub treasure: u32,
+    /// The keys that have been picked up, in the order they were found.
+    pub keys: Vec<KeyColor>,
+    /// The locked door that is being touched, so the player is only told that it is locked once.
+    locked_door: Option<(usize, usize)>,
+}
+
+/// Places an item on the floor at the center of a tile.
+pub fn spawn(world: &mut World, level: &Level, tile: (usize, usize), item: Item) -> Entity
+{
+    let entity = world.spawn();
+    world.transforms.insert(entity, Transform { position: level.tile_center(tile), ang: 0.0 });
+    world.sprites.insert(entity, Sprite { sheet: Sheet::Items, frame: item.frame(), size: 0.4, height: 0.0 });
+    world.pickups.insert(entity, Pickup { item });
+    entity
+}
+
+/// The pickup system. Gives every item that an entity with an inventory walks into to that entity, leaving it
+/// where it is if the entity has no use for it.
+///
+/// Returns the items that were picked up.
+pub fn update(world: &mut World, level: &Level) -> Vec<Item>
+{
+    let mut picked_up = Vec::new();
+    for collector in world.inventories.entities()
+    {
+        let position = match world.transforms.get(collector) { Some(me) => me.position, None => continue };
+        // Items lie on the ground floor, out of reach from the storeys above.
+        if collector == world.player && eye::storey(world) > 0 { continue; }
+        let reach = world.collider_radius(level, collector).unwrap_or(0.0) + PICKUP_REACH * level.tile_size().0;
+        let touching: Vec<(Entity, Item)> = world.pickups.iter()
+            .filter(|&(entity, _)|
+                world.transforms.get(entity).is_some_and(|item| dist_between(item.position, position) < reach))
+            .map(|(entity, pickup)| (entity, pickup.item))
+            .collect();
+
+        for (entity, item) in touching
+        {
+            if collect(world, collector, item)
+            {
+                world.despawn(entity);
+                picked_up.push(item);
+            }
+        }
+    }
+    picked_up
+}
+
+/// Puts an item where it belongs on the collector. Returns whether it was taken.
+fn collect(world: &mut World, collector: Entity, item: Item) -> bool
+{
+    match item
+    {
+        Item::Health => match world.healths.get_mut(collector)
+        {
+            Some(health) if health.current > 0 && health.current < health.max =>
+            {
+                health.current = i32::min(health.current + HEALTH_AMOUNT, health.max);
+                true
+            },
+            _ => false,
+        },
+        Item::Armor => match world.player_states.get_mut(collector)
+        {
+            Some(state) if state.armor < MAX_ARMOR =>
+            {
+                state.armor = i32::min(state.armor + ARMOR_AMOUNT, MAX_ARMOR);
+                true
+            },
+            _ => false,
+        },
+        Item::Ammo => world.weapons.get_mut(collector).map(|weapon| weapon.ammo += AMMO_AMOUNT).is_some(),
+        Item::Treasure => world.inventories.get_mut(collector).map(|inventory| inventory.treasure += 1).is_some(),
+        Item::Key(key) => world.inventories.get_mut(collector).map(|inventory|
+        {
+            if !inventory.keys.contains(&key)
+            {
+                inventory.keys.push(key);
+            }
+        }).is_some(),
+    }
+}
+
+/// What happened when the player walked up to a door.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DoorEvent
+{
+    /// The door opened, it is gone from the level.
+    Opened(KeyColor),
+    /// The player does not have the key.
+    Locked(KeyColor),
+}
+
+/// Opens the doors that the player is walking into, if they have the key, by turning them into open floor. Doors
+/// are on the ground floor, so they can not be opened from the storeys above.
+///
+/// Returns what happened, a locked door is only reported when the player first walks up to it.
+pub fn open_doors(world: &mut World, level: &mut Level) -> Vec<DoorEvent>
+{
+    if eye::storey(world) > 0 { return Vec::new(); }
+    let player = world.player;
+    let position = world.player_pos().position;
+    let inventory = match world.inventories.get_mut(player) { Some(inventory) => inventory, None => return Vec::new() };
+
+    let mut events = Vec::new();
+    let mut locked_door = None;
+    for (col, row) in level.walls_touched(position, DOOR_REACH * level.tile_size().0)
+    {
+        let key = match KeyColor::from_door(level.tile(col, row)) { Some(key) => key, None => continue };
+        if inventory.keys.contains(&key)
+        {
+            level.tiles[row * level.width + col] = 0;
+            events.push(DoorEvent::Opened(key));
+        }
+        else
+        {
+            if inventory.locked_door != Some((col, row))
+            {
+                events.push(DoorEvent::Locked(key));
+            }
+            locked_door = Some((col, row));
+        }
+    }
+    inventory.locked_door = locked_door;
+    events
+}
+
+/// Draws every item waiting to be picked up from above as a small square, leaving out any where `seen` is false.
+pub fn draw_2d(world: &World, canvas: &mut Canvas, empty_tex: &Texture2d, seen: &dyn Fn([f32; 2]) -> bool)
+{
+    for (entity, pickup) in world.pickups.iter()
+    {
+        let [x, y] = match world.transforms.get(entity) { Some(me) if seen(me.position) => me.position, _ => continue };
+        let half = 0.015;
+        crate::draw_rect(Pos { position: [x - half, y + half] }, Pos { position: [x + half, y - half] },
+            ColorTex::Color(empty_tex, pickup.item.color()), 1.0, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn items_are_out_of_reach_from_the_storey_above()
+    {
+        let level = Level::from_rows(&["111", "101", "111"]);
+        let mut world = World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 });
+        let key = spawn(&mut world, &level, (1, 1), Item::Key(KeyColor::Red));
+        eye::set_storey(&mut world, 1);
+        assert!(update(&mut world, &level).is_empty());
+
+        eye::set_storey(&mut world, 0);
+        assert_eq!(update(&mut world, &level), vec![Item::Key(KeyColor::Red)]);
+        assert!(world.pickups.get(key).is_none());
+        assert_eq!(world.inventories.get(world.player).unwrap().keys, vec![KeyColor::Red]);
+    }
+
+    #[test]
+    fn item_codes()
+    {
+        assert_eq!(Item::from_code(0), None);
+        assert_eq!(Item::from_code(1), Some(Item::Health));
+        assert_eq!(Item::from_code(7), Some(Item::Armor));
+        for &key in &[KeyColor::Red, KeyColor::Blue, KeyColor::Yellow]
+        {
+            assert_eq!(Item::from_code(key.door()), Some(Item::Key(key)));
+            assert_eq!(KeyColor::from_door(key.door()), Some(key));
+        }
+        assert_eq!(KeyColor::from_door(1), None);
+    }
+
+    #[test]
+    fn only_useful_items_are_taken()
+    {
+        let level = Level::from_rows(&["1111", "1001", "1001", "1111"]);
+        let mut world = World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 });
+        let player = world.player;
+        spawn(&mut world, &level, (1, 1), Item::Health);
+        assert!(update(&mut world, &level).is_empty(), "health is already full");
+
+        world.healths.get_mut(player).unwrap().current -= HEALTH_AMOUNT / 2;
+        assert_eq!(update(&mut world, &level), vec![Item::Health]);
+        let health = world.healths.get(player).unwrap();
+        assert_eq!(health.current, health.max, "healed no further than full");
+
+        let ammo = world.weapons.get(player).unwrap().ammo;
+        spawn(&mut world, &level, (1, 1), Item::Ammo);
+        spawn(&mut world, &level, (1, 1), Item::Treasure);
+        spawn(&mut world, &level, (1, 1), Item::Armor);
+        spawn(&mut world, &level, (2, 2), Item::Treasure);
+        assert_eq!(update(&mut world, &level), vec![Item::Ammo, Item::Treasure, Item::Armor]);
+        assert_eq!(world.weapons.get(player).unwrap().ammo, ammo + AMMO_AMOUNT);
+        assert_eq!(world.inventories.get(player).unwrap().treasure, 1);
+        assert_eq!(world.player_states.get(player).unwrap().armor, ARMOR_AMOUNT);
+        assert_eq!(world.pickups.iter().count(), 1, "the one out of reach is left");
+    }
+
+    #[test]
+    fn doors_open_with_their_key()
+    {
+        let mut level = Level::from_rows(&["1111", "1041", "1001", "1111"]);
+        // Right up against the face of the door.
+        let position = [level.tile_center((1, 1))[0] + 0.4 * level.tile_size().0, level.tile_center((1, 1))[1]];
+        let mut world = World::new(Transform { position, ang: 0.0 });
+
+        assert_eq!(open_doors(&mut world, &mut level), vec![DoorEvent::Locked(KeyColor::Red)]);
+        assert!(open_doors(&mut world, &mut level).is_empty(), "only told once");
+        assert_eq!(level.tile(2, 1), 4);
+
+        world.inventories.get_mut(world.player).unwrap().keys.push(KeyColor::Blue);
+        assert!(open_doors(&mut world, &mut level).is_empty());
+        world.inventories.get_mut(world.player).unwrap().keys.push(KeyColor::Red);
+        assert_eq!(open_doors(&mut world, &mut level), vec![DoorEvent::Opened(KeyColor::Red)]);
+        assert_eq!(level.tile(2, 1), 0);
+    }
+
+    #[test]
+    fn doors_are_out_of_reach_from_the_storey_above()
+    {
+        let mut level = Level::from_rows(&["1111", "1041", "1001", "1111"]);
+        let position = [level.tile_center((1, 1))[0] + 0.4 * level.tile_size().0, level.tile_center((1, 1))[1]];
+        let mut world = World::new(Transform { position, ang: 0.0 });
+        world.inventories.get_mut(world.player).unwrap().keys.push(KeyColor::Red);
+        eye::set_storey(&mut world, 1);
+        assert!(open_doors(&mut world, &mut level).is_empty());
+        assert_eq!(level.tile(2, 1), 4);
+    }
+}
@@ -0,0 +1,343 @@
+//! What the player has on top of what every entity has: armor, the flashes when they are hurt or teleported, the
+//! special floors and switches of the level, and dying and coming back.
+
+use crate::eye::{self, Eye};
+use crate::level::{Floor, Level, EXIT_SWITCH};
+use crate::text::{self, Anchor};
+use crate::world::{self, Collider, Transform, World};
+use crate::{Canvas, ColorTex, Pos, Textures};
+
+/// How far the player reaches out from their position in tiles.
+pub const RADIUS: f32 = 0.2;
+/// The most armor the player can wear.
+pub const MAX_ARMOR: i32 = 100;
+/// The fraction of any damage that armor takes instead of health, while it lasts.
+const ARMOR_ABSORB: f32 = 0.5;
+/// Seconds that the screen flashes red for after the player is hurt.
+const FLASH_TIME: f32 = 0.4;
+/// How see through the red flash is when it starts, it fades out from there.
+const FLASH_ALPHA: f32 = 0.5;
+/// Seconds that the screen flashes for after the player goes through a teleporter.
+const WARP_TIME: f32 = 0.3;
+/// How see through the teleporter flash is when it starts.
+const WARP_ALPHA: f32 = 0.8;
+const WARP_COLOR: (f32, f32, f32) = (0.8, 1.0, 1.0);
+/// Damage done by standing on a hazard.
+const HAZARD_DAMAGE: i32 = 5;
+/// Seconds between each time that a hazard hurts the player.
+const HAZARD_TIME: f32 = 0.5;
+/// How close in tiles the player has to be to the face of a switch to press it.
+const SWITCH_REACH: f32 = 0.3;
+/// Seconds that the screen takes to fade to red after the player dies, they can not respawn until it has.
+const DEATH_TIME: f32 = 1.5;
+/// How see through the death screen is once it has faded in.
+const DEATH_ALPHA: f32 = 0.7;
+const DEATH_COLOR: (f32, f32, f32) = (0.6, 0.0, 0.0);
+const TEXT_COLOR: (f32, f32, f32) = (1.0, 1.0, 1.0);
+
+/// The player component, everything about the player that other entities do not have.
+pub struct PlayerState
+{
+    pub armor: i32,
+    /// Where the player comes back after dying, the start of the level or the last checkpoint they stood on.
+    pub checkpoint: Transform,
+    /// Seconds since the player died, if they are dead.
+    pub dead_for: Option<f32>,
+    /// Seconds left of the red flash.
+    flash: f32,
+    /// Seconds left of the teleporter flash.
+    warp_flash: f32,
+    /// Seconds until the hazard being stood on hurts the player again.
+    hazard_cooldown: f32,
+}
+
+impl PlayerState
+{
+    /// A player without armor that comes back at `start` until they reach a checkpoint.
+    pub fn new(start: Transform) -> PlayerState
+    {
+        PlayerState { armor: 0, checkpoint: start, dead_for: None, flash: 0.0, warp_flash: 0.0, hazard_cooldown: 0.0 }
+    }
+
+    /// Flashes the screen and lets the armor take its share of some damage.
+    ///
+    /// Returns the damage left over for the player's health.
+    pub fn absorb(&mut self, amount: i32) -> i32
+    {
+        self.flash = FLASH_TIME;
+        let absorbed = i32::min((amount as f32 * ARMOR_ABSORB) as i32, self.armor);
+        self.armor -= absorbed;
+        amount - absorbed
+    }
+
+    /// Flashes the screen for the player going through a teleporter.
+    pub fn warped(&mut self)
+    {
+        self.warp_flash = WARP_TIME;
+    }
+
+    /// Whether the death screen can be left yet.
+    pub fn can_respawn(&self) -> bool
+    {
+        self.dead_for.is_some_and(|dead_for| dead_for >= DEATH_TIME)
+    }
+}
+
+/// Something that the player set off by where they went.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PlayerEvent
+{
+    /// They stood on a checkpoint that they were not coming back to yet.
+    Checkpoint,
+    /// They found a secret.
+    Secret,
+    /// They stood on an exit or walked into an exit switch, the level is over.
+    Exit,
+}
+
+/// The player system. Hurts the player while they stand on a hazard, moves their checkpoint to any checkpoint
+/// they stand on, marks secrets as found and starts the death screen once they run out of health.
+///
+/// Returns what the player set off.
+pub fn update(world: &mut World, level: &mut Level, frame_time: f32) -> Vec<PlayerEvent>
+{
+    let player = world.player;
+    let player_pos = world.player_pos();
+    let tile = level.tile_at(player_pos.position);
+    // The floors only work from the ground floor, not from the storeys above them.
+    let on_ground = eye::storey(world) == 0;
+    let floor = tile.filter(|_| on_ground).map_or(Floor::Plain, |(col, row)| level.floor(col, row));
+    let state = match world.player_states.get_mut(player) { Some(state) => state, None => return Vec::new() };
+    state.flash = f32::max(state.flash - frame_time, 0.0);
+    state.warp_flash = f32::max(state.warp_flash - frame_time, 0.0);
+    state.hazard_cooldown = f32::max(state.hazard_cooldown - frame_time, 0.0);
+
+    let mut events = Vec::new();
+    let mut hurt = false;
+    match (floor, tile)
+    {
+        (Floor::Hazard, _) if state.hazard_cooldown == 0.0 =>
+        {
+            state.hazard_cooldown = HAZARD_TIME;
+            hurt = true;
+        },
+        (Floor::Checkpoint, _) if level.tile_at(state.checkpoint.position) != tile =>
+        {
+            state.checkpoint = player_pos;
+            events.push(PlayerEvent::Checkpoint);
+        },
+        (Floor::Secret, Some((col, row))) =>
+        {
+            level.set_floor(col, row, Floor::Plain);
+            events.push(PlayerEvent::Secret);
+        },
+        (Floor::Exit, _) => events.push(PlayerEvent::Exit),
+        _ => (),
+    }
+    let switches = level.walls_touched(player_pos.position, SWITCH_REACH * level.tile_size().0);
+    if on_ground && switches.iter().any(|&(col, row)| level.tile(col, row) == EXIT_SWITCH)
+    {
+        events.push(PlayerEvent::Exit);
+    }
+    if hurt
+    {
+        world::damage(world, player, HAZARD_DAMAGE, player_pos.position);
+    }
+
+    let dead = world.healths.get(player).is_some_and(|health| health.current <= 0);
+    if let Some(state) = world.player_states.get_mut(player)
+    {
+        if dead && state.dead_for.is_none()
+        {
+            state.dead_for = Some(0.0);
+        }
+    }
+    events
+}
+
+/// Runs the death screen for a single frame, this is all that happens while the player is dead.
+pub fn update_dead(world: &mut World, frame_time: f32)
+{
+    if let Some(dead_for) = world.player_states.get_mut(world.player).and_then(|state| state.dead_for.as_mut())
+    {
+        *dead_for += frame_time;
+    }
+}
+
+/// Whether the player is dead, which pauses the game.
+pub fn is_dead(world: &World) -> bool
+{
+    world.player_states.get(world.player).is_some_and(|state| state.dead_for.is_some())
+}
+
+/// Brings the player back at their checkpoint with full health and no armor, standing on the ground floor, if the
+/// death screen has been shown for long enough.
+pub fn respawn(world: &mut World)
+{
+    let player = world.player;
+    let state = match world.player_states.get_mut(player)
+    {
+        Some(state) if state.can_respawn() => state,
+        _ => return,
+    };
+    state.dead_for = None;
+    state.flash = 0.0;
+    state.armor = 0;
+    let checkpoint = state.checkpoint;
+
+    *world.player_pos_mut() = checkpoint;
+    world.colliders.insert(player, Collider { radius: RADIUS });
+    if let Some(health) = world.healths.get_mut(player)
+    {
+        health.current = health.max;
+    }
+    // Checkpoints are on the ground floor, and nothing of how they were moving when they died carries over.
+    if let Some(eye) = world.eyes.get_mut(player)
+    {
+        let head_bob = eye.head_bob;
+        *eye = Eye::default();
+        eye.head_bob = head_bob;
+    }
+}
+
+/// Draws the red flash after the player is hurt, the flash after they are teleported, or the death screen if they
+/// are dead, over the whole of the current viewport.
+pub fn draw(world: &World, canvas: &mut Canvas, textures: &Textures)
+{
+    let state = match world.player_states.get(world.player) { Some(state) => state, None => return };
+    let (alpha, color) = match state.dead_for
+    {
+        Some(dead_for) => (f32::min(dead_for / DEATH_TIME, 1.0) * DEATH_ALPHA, DEATH_COLOR),
+        None if state.flash > 0.0 => (state.flash / FLASH_TIME * FLASH_ALPHA, (1.0, 0.0, 0.0)),
+        None if state.warp_flash > 0.0 => (state.warp_flash / WARP_TIME * WARP_ALPHA, WARP_COLOR),
+        None => return,
+    };
+
+    let view = canvas.view;
+    canvas.view.alpha = alpha;
+    crate::draw_rect(Pos { position: [-1.0, 1.0] }, Pos { position: [1.0, -1.0] },
+        ColorTex::Color(&textures.empty, color), 1.0, canvas);
+    canvas.view = view;
+
+    if state.dead_for.is_some()
+    {
+        text::draw_text("YOU DIED", Pos { position: [0.0, 0.1] }, Anchor::Bottom, 6.0, TEXT_COLOR, &textures.font,
+            canvas);
+        if state.can_respawn()
+        {
+            text::draw_text("PRESS SPACE TO TRY AGAIN", Pos { position: [0.0, -0.1] }, Anchor::Top, 3.0, TEXT_COLOR,
+                &textures.font, canvas);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn standing_on(floor: Floor) -> (World, Level)
+    {
+        let mut level = Level::from_rows(&["111", "101", "111"]);
+        level.set_floor(1, 1, floor);
+        (World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 }), level)
+    }
+
+    #[test]
+    fn floors_work_on_the_ground_floor()
+    {
+        let (mut world, mut level) = standing_on(Floor::Secret);
+        assert_eq!(update(&mut world, &mut level, 0.02), vec![PlayerEvent::Secret]);
+        assert_eq!(level.floor(1, 1), Floor::Plain);
+
+        let (mut world, mut level) = standing_on(Floor::Hazard);
+        update(&mut world, &mut level, 0.02);
+        let health = world.healths.get(world.player).unwrap();
+        assert_eq!(health.current, health.max - HAZARD_DAMAGE);
+    }
+
+    #[test]
+    fn floors_do_nothing_from_the_storey_above()
+    {
+        for &floor in &[Floor::Hazard, Floor::Checkpoint, Floor::Secret, Floor::Exit]
+        {
+            let (mut world, mut level) = standing_on(floor);
+            world.player_states.get_mut(world.player).unwrap().checkpoint.position = [0.9, 0.9];
+            eye::set_storey(&mut world, 1);
+            assert!(update(&mut world, &mut level, 0.02).is_empty());
+            assert_eq!(level.floor(1, 1), floor);
+            let health = world.healths.get(world.player).unwrap();
+            assert_eq!(health.current, health.max);
+        }
+    }
+
+    #[test]
+    fn armor_takes_half_while_it_lasts()
+    {
+        let (mut world, _) = standing_on(Floor::Plain);
+        let player = world.player;
+        world.player_states.get_mut(player).unwrap().armor = 6;
+        world::damage(&mut world, player, 10, [0.0, 0.0]);
+        assert_eq!(world.player_states.get(player).unwrap().armor, 1);
+        world::damage(&mut world, player, 10, [0.0, 0.0]);
+        assert_eq!(world.player_states.get(player).unwrap().armor, 0);
+        let health = world.healths.get(player).unwrap();
+        assert_eq!(health.current, health.max - 5 - 9);
+    }
+
+    #[test]
+    fn hazards_hurt_once_each_cooldown()
+    {
+        let (mut world, mut level) = standing_on(Floor::Hazard);
+        for _ in 0..(HAZARD_TIME / 0.02) as usize
+        {
+            update(&mut world, &mut level, 0.02);
+        }
+        let health = world.healths.get(world.player).unwrap();
+        assert_eq!(health.current, health.max - HAZARD_DAMAGE);
+        update(&mut world, &mut level, 0.04);
+        let health = world.healths.get(world.player).unwrap();
+        assert_eq!(health.current, health.max - 2 * HAZARD_DAMAGE);
+    }
+
+    #[test]
+    fn checkpoints_are_only_set_once()
+    {
+        let (mut world, mut level) = standing_on(Floor::Checkpoint);
+        world.player_states.get_mut(world.player).unwrap().checkpoint.position = [0.9, 0.9];
+        assert_eq!(update(&mut world, &mut level, 0.02), vec![PlayerEvent::Checkpoint]);
+        assert_eq!(world.player_states.get(world.player).unwrap().checkpoint.position, world.player_pos().position);
+        assert!(update(&mut world, &mut level, 0.02).is_empty());
+    }
+
+    #[test]
+    fn dying_and_coming_back_at_the_checkpoint()
+    {
+        let (mut world, mut level) = standing_on(Floor::Plain);
+        let player = world.player;
+        let checkpoint = world.player_pos();
+        world.player_pos_mut().position = [0.1, 0.2];
+        world.player_states.get_mut(player).unwrap().armor = 10;
+        world.eyes.get_mut(player).unwrap().head_bob = false;
+        eye::set_storey(&mut world, 1);
+
+        world::damage(&mut world, player, 1000, [0.0, 0.0]);
+        assert!(!is_dead(&world));
+        update(&mut world, &mut level, 0.02);
+        assert!(is_dead(&world));
+        assert!(world.colliders.get(player).is_none());
+
+        respawn(&mut world);
+        assert!(is_dead(&world), "the death screen has not been shown for long enough");
+        update_dead(&mut world, DEATH_TIME);
+        respawn(&mut world);
+        assert!(!is_dead(&world));
+        assert_eq!(world.player_pos().position, checkpoint.position);
+        let health = world.healths.get(player).unwrap();
+        assert_eq!(health.current, health.max);
+        assert_eq!(world.player_states.get(player).unwrap().armor, 0);
+        assert!(world.colliders.get(player).is_some());
+        assert_eq!(eye::storey(&world), 0);
+        assert!(!world.eyes.get(player).unwrap().head_bob, "settings are kept");
+    }
+}
@@ -0,0 +1,234 @@
+//! Portals, pairs of wall faces that are joined together so that anything going into one comes out of the other.
+//! They let a level be bigger on the inside, loop back on itself or have rooms in impossible places.
+//!
+//! Portals are declared in the map file with a `portal` line, giving the wall tile and the side of it for each of
+//! the two faces:
+//!
+//! ```text
+//! portal <col,row> <top|bottom|left|right> <col,row> <top|bottom|left|right>
+//! ```
+//!
+//! The sides are as the map is written out, so `top` is the face towards the row above. Whatever goes into one
+//! face comes out of the other turned by however much the faces are turned from each other, so faces looking any
+//! way can be joined. The tile in front of each face has to be open floor.
+
+use glium::texture::Texture2d;
+use crate::level::{self, Level};
+use crate::path::Tile;
+use crate::{Canvas, PlayerPos, Pos};
+
+/// The color of portal faces in the 2d view.
+const PORTAL_COLOR: (f32, f32, f32) = (1.0, 0.5, 0.0);
+/// How far past a face in tiles a ray or the player comes out, so they are not on the face itself.
+const EXIT_GAP: f32 = 0.001;
+
+/// The sides of a tile, as the map is written out.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Side
+{
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Side
+{
+    fn parse(value: Option<&&str>) -> Result<Side, String>
+    {
+        match value
+        {
+            Some(&"top") => Ok(Side::Top),
+            Some(&"bottom") => Ok(Side::Bottom),
+            Some(&"left") => Ok(Side::Left),
+            Some(&"right") => Ok(Side::Right),
+            _ => Err(String::from("expected top, bottom, left or right")),
+        }
+    }
+
+    /// The direction that a face on this side looks out in, in play space.
+    fn normal(self) -> [f32; 2]
+    {
+        match self
+        {
+            Side::Top => [0.0, -1.0],
+            Side::Bottom => [0.0, 1.0],
+            Side::Left => [-1.0, 0.0],
+            Side::Right => [1.0, 0.0],
+        }
+    }
+}
+
+/// A pair of joined wall faces.
+#[derive(Clone, Debug)]
+pub struct Portal
+{
+    /// The wall tile and side of each face.
+    faces: [(Tile, Side); 2],
+}
+
+impl Portal
+{
+    /// Reads the values of a `portal` line in a map file, see the [module docs](index.html) for the format.
+    ///
+    /// Returns a message saying what is wrong if the line can not be read.
+    pub fn parse(values: &[&str]) -> Result<Portal, String>
+    {
+        let tile = |value: Option<&&str>| -> Result<Tile, String>
+        {
+            level::parse_tile(value.ok_or("expected a tile")?)
+        };
+        let faces = [(tile(values.first())?, Side::parse(values.get(1))?),
+            (tile(values.get(2))?, Side::parse(values.get(3))?)];
+        if faces[0] == faces[1] { return Err(String::from("a face can not be joined to itself")); }
+        Ok(Portal { faces })
+    }
+
+    /// Checks that both faces are on walls of the level with open floor in front of them.
+    ///
+    /// Returns a message saying which face is wrong if one is.
+    pub fn check(&self, level: &Level) -> Result<(), String>
+    {
+        for &((col, row), side) in self.faces.iter()
+        {
+            if level.tile(col, row) == 0 { return Err(format!("the face on {},{} is not on a wall", col, row)); }
+            let [nx, ny] = side.normal();
+            let (front_col, front_row) = (col as isize + nx as isize, row as isize + ny as isize);
+            if front_col < 0 || front_row < 0 || !level.is_walkable(front_col as usize, front_row as usize)
+            {
+                return Err(format!("the face on {},{} does not face open floor", col, row));
+            }
+        }
+        Ok(())
+    }
+
+    /// The wall tiles that both faces are on.
+    pub fn tiles(&self) -> Vec<Tile>
+    {
+        self.faces.iter().map(|&(tile, _)| tile).collect()
+    }
+}
+
+/// The center of a face in play space.
+fn face_center(level: &Level, (tile, side): (Tile, Side)) -> [f32; 2]
+{
+    let (tile_w, tile_h) = level.tile_size();
+    let [x, y] = level.tile_center(tile);
+    let [nx, ny] = side.normal();
+    [x + nx * tile_w / 2.0, y + ny * tile_h / 2.0]
+}
+
+/// The wall face that a ray cast going in the direction `ang` hit, from the `if_on_horz` and `ray_end_pos` of the
+/// cast.
+fn face_hit(level: &Level, ang: f32, horz: bool, ray_pos: (f32, f32)) -> Option<(Tile, Side)>
+{
+    let (tile_w, tile_h) = level.tile_size();
+    let (grid_x, grid_y) = ((ray_pos.0 + 1.0) / tile_w, (ray_pos.1 + 1.0) / tile_h);
+    // The ray hits the face of the tile past the grid line that looks back at it.
+    let (col, row, side) = if horz
+    {
+        let line = f32::round(grid_y) as isize;
+        let (row, side) = if f32::sin(ang) > 0.0 { (line, Side::Top) } else { (line - 1, Side::Bottom) };
+        (f32::floor(grid_x) as isize, row, side)
+    }
+    else
+    {
+        let line = f32::round(grid_x) as isize;
+        let (col, side) = if f32::cos(ang) > 0.0 { (line, Side::Left) } else { (line - 1, Side::Right) };
+        (col, f32::floor(grid_y) as isize, side)
+    };
+
+    if col < 0 || row < 0 || col as usize >= level.width || row as usize >= level.height { return None; }
+    Some(((col as usize, row as usize), side))
+}
+
+/// Where a ray cast going in the direction `ang` comes out if the wall face it hit is a portal, and the direction
+/// it goes from there. `horz` and `ray_pos` are the `if_on_horz` and `ray_end_pos` of the cast.
+pub fn through(level: &Level, ang: f32, horz: bool, ray_pos: (f32, f32)) -> Option<PlayerPos>
+{
+    let face = face_hit(level, ang, horz, ray_pos)?;
+    let (from, to) = level.portals.iter().find_map(|portal|
+    {
+        let i = portal.faces.iter().position(|&other| other == face)?;
+        Some((portal.faces[i], portal.faces[1 - i]))
+    })?;
+
+    // Turn so that going into the first face is coming out of the second.
+    let [in_x, in_y] = from.1.normal();
+    let [out_x, out_y] = to.1.normal();
+    let turn = f32::atan2(out_y, out_x) - f32::atan2(-in_y, -in_x);
+    let (sin, cos) = f32::sin_cos(turn);
+
+    let [from_x, from_y] = face_center(level, from);
+    let [to_x, to_y] = face_center(level, to);
+    let (dx, dy) = (ray_pos.0 - from_x, ray_pos.1 - from_y);
+    let gap = EXIT_GAP * level.tile_size().0;
+    Some(PlayerPos
+    {
+        position: [to_x + dx * cos - dy * sin + out_x * gap, to_y + dx * sin + dy * cos + out_y * gap],
+        ang: ang + turn,
+    })
+}
+
+/// Moves the player through a portal if the move `(x_move, y_move)` takes them into one. `min_dist` is how close
+/// the player can get to a wall, they go through once they would be stopped by the portal.
+///
+/// Returns where they end up on the other side and which way they face, with the rest of the move done from where
+/// they came out.
+pub fn walk(level: &Level, player_pos: &PlayerPos, (x_move, y_move): (f32, f32), min_dist: f32) -> Option<PlayerPos>
+{
+    let move_dist = f32::sqrt(x_move * x_move + y_move * y_move);
+    if move_dist == 0.0 { return None; }
+    let move_ang = f32::atan2(y_move, x_move);
+    let (wall_dist, horz, wall, ray_pos) = crate::cast_straight(level, player_pos, move_ang);
+    if wall == 0 || wall_dist > move_dist + min_dist { return None; }
+
+    let exit = through(level, move_ang, horz, ray_pos)?;
+    let rest = move_dist + min_dist - wall_dist;
+    let position = [exit.position[0] + rest * f32::cos(exit.ang), exit.position[1] + rest * f32::sin(exit.ang)];
+    let (col, row) = level.tile_at(position)?;
+    if !level.is_walkable(col, row) { return None; }
+    Some(PlayerPos { position, ang: player_pos.ang + exit.ang - move_ang })
+}
+
+/// Draws every portal face from above as a line along the face.
+pub fn draw_2d(level: &Level, canvas: &mut Canvas, empty_tex: &Texture2d)
+{
+    let (tile_w, tile_h) = level.tile_size();
+    for &face in level.portals.iter().flat_map(|portal| portal.faces.iter())
+    {
+        let [x, y] = face_center(level, face);
+        let [nx, ny] = face.1.normal();
+        // Along the face is across its normal.
+        let (half_x, half_y) = (ny.abs() * tile_w / 2.0, nx.abs() * tile_h / 2.0);
+        crate::draw_line(Pos { position: [x - half_x, y - half_y] }, Pos { position: [x + half_x, y + half_y] },
+            PORTAL_COLOR, 1.0, empty_tex, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parse_faces()
+    {
+        let portal = Portal::parse(&["4,1", "left", "6,2", "bottom"]).unwrap();
+        assert_eq!(portal.faces, [((4, 1), Side::Left), ((6, 2), Side::Bottom)]);
+        assert_eq!(portal.tiles(), vec![(4, 1), (6, 2)]);
+        let portal = Portal::parse(&["0,3", "top", "0,3", "right"]).unwrap();
+        assert_eq!(portal.faces, [((0, 3), Side::Top), ((0, 3), Side::Right)]);
+    }
+
+    #[test]
+    fn parse_errors()
+    {
+        let error = |values: &[&str]| Portal::parse(values).unwrap_err();
+        assert_eq!(error(&[]), "expected a tile");
+        assert_eq!(error(&["4,1"]), "expected top, bottom, left or right");
+        assert_eq!(error(&["4,1", "up", "6,2", "bottom"]), "expected top, bottom, left or right");
+        assert_eq!(error(&["4,1", "left", "6,x", "bottom"]), "expected a tile as col,row but found `6,x`");
+        assert_eq!(error(&["4,1", "left", "4,1", "left"]), "a face can not be joined to itself");
+    }
+}
@@ -0,0 +1,371 @@
+//! Projectiles, things like rockets and fireballs that fly through the level until they hit something.
+//!
+//! They move a little every frame and bump into walls through the tile grid and into other entities through their
+//! colliders. Some bounce off of walls and all of them explode in the end, hurting anything nearby that
+//! can see the explosion.
+
+use glium::texture::Texture2d;
+use crate::enemy::dist_between;
+use crate::level::Level;
+use crate::world::{self, Collider, Entity, Sheet, Sprite, Transform, World};
+use crate::{Canvas, ColorTex, Pos};
+
+/// Seconds that an explosion is shown for.
+const EXPLOSION_TIME: f32 = 0.3;
+/// The frame of the sprite sheet that explosions start with, the next frame is used for the second half.
+const EXPLOSION_FRAME: usize = 3;
+/// How much speed is kept when bouncing off of a wall.
+const BOUNCINESS: f32 = 0.7;
+
+/// The different types of projectile.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ProjectileKind
+{
+    /// Fired by the player, flies straight and explodes on whatever it hits.
+    Rocket,
+    /// Thrown by enemies, flies straight and explodes on whatever it hits.
+    Fireball,
+    /// Thrown by the player, bounces off of walls and slows down until its fuse runs out.
+    Grenade,
+}
+
+/// How a type of projectile behaves. All sizes are in tiles and all times are in seconds.
+struct KindInfo
+{
+    /// Tiles per second when launched.
+    speed: f32,
+    /// Radius for bumping into walls and other entities.
+    radius: f32,
+    /// Size of its sprite.
+    size: f32,
+    /// How far off of the floor the center of its sprite is.
+    height: f32,
+    /// Damage done to whatever it flies into, on top of the explosion.
+    damage: i32,
+    /// How far its explosion reaches.
+    blast_radius: f32,
+    /// Damage its explosion does right at the center, this falls off to nothing at the edge.
+    blast_damage: i32,
+    /// How many times it bounces off of walls before it explodes on one instead.
+    bounces: u32,
+    /// It explodes by itself after this long.
+    fuse: f32,
+    /// The fraction of its speed it loses every second.
+    drag: f32,
+    /// Which frame of the sprite sheet it is drawn with.
+    frame: usize,
+    /// Its color in the 2d view.
+    color: (f32, f32, f32),
+}
+
+impl ProjectileKind
+{
+    fn info(self) -> KindInfo
+    {
+        match self
+        {
+            ProjectileKind::Rocket => KindInfo { speed: 6.0, radius: 0.1, size: 0.3, height: 0.5, damage: 20,
+                blast_radius: 1.5, blast_damage: 30, bounces: 0, fuse: 10.0, drag: 0.0, frame: 0, color: (1.0, 0.5, 0.0) },
+            ProjectileKind::Fireball => KindInfo { speed: 4.0, radius: 0.15, size: 0.35, height: 0.5, damage: 10,
+                blast_radius: 0.8, blast_damage: 10, bounces: 0, fuse: 10.0, drag: 0.0, frame: 1, color: (1.0, 0.2, 0.0) },
+            ProjectileKind::Grenade => KindInfo { speed: 3.0, radius: 0.1, size: 0.2, height: 0.1, damage: 0,
+                blast_radius: 2.0, blast_damage: 40, bounces: 3, fuse: 2.0, drag: 0.8, frame: 2, color: (0.2, 0.5, 0.1) },
+        }
+    }
+}
+
+/// The projectile component, for an entity that flies until it hits something and then explodes.
+pub struct Projectile
+{
+    pub kind: ProjectileKind,
+    /// Play space units per second.
+    velocity: [f32; 2],
+    /// Whoever launched it, it does not hit them.
+    owner: Entity,
+    /// Where it was launched from, enemies hurt by it go looking there.
+    origin: [f32; 2],
+    /// Seconds since it was launched.
+    age: f32,
+    bounces_left: u32,
+}
+
+/// The explosion component, for an entity that only shows an explosion that has gone off.
+pub struct Explosion
+{
+    /// How far it reaches in tiles.
+    blast_radius: f32,
+    /// Seconds since it went off.
+    age: f32,
+}
+
+/// Launches a projectile from `position` in the direction `ang`.
+pub fn spawn(world: &mut World, level: &Level, kind: ProjectileKind, position: [f32; 2], ang: f32, owner: Entity)
+    -> Entity
+{
+    let info = kind.info();
+    let speed = info.speed * level.tile_size().0;
+    let entity = world.spawn();
+    world.transforms.insert(entity, Transform { position, ang });
+    world.sprites.insert(entity, Sprite { sheet: Sheet::Projectiles, frame: info.frame, size: info.size,
+        height: info.height - info.size / 2.0 });
+    world.colliders.insert(entity, Collider { radius: info.radius });
+    world.projectiles.insert(entity, Projectile
+    {
+        kind,
+        velocity: [speed * f32::cos(ang), speed * f32::sin(ang)],
+        owner,
+        origin: position,
+        age: 0.0,
+        bounces_left: info.bounces,
+    });
+    entity
+}
+
+impl Projectile
+{
+    /// Moves the projectile for a single frame, bouncing it off of walls. `this` is its own entity and `me` its
+    /// transform.
+    ///
+    /// Returns whether it should explode, because it hit a wall it can not bounce off of or its fuse ran out. The
+    /// second value is the entity it flew into, if any, which also means it should explode.
+    fn update(&mut self, this: Entity, me: &mut Transform, level: &Level, world: &World, frame_time: f32)
+        -> (bool, Option<Entity>)
+    {
+        let info = self.kind.info();
+        let radius = info.radius * level.tile_size().0;
+
+        self.age += frame_time;
+        if self.age >= info.fuse { return (true, None); }
+
+        let drag = f32::max(1.0 - info.drag * frame_time, 0.0);
+        self.velocity = [self.velocity[0] * drag, self.velocity[1] * drag];
+
+        // Move in steps no longer than the projectile is wide, so it can not pass through anything.
+        let step_len = f32::sqrt(self.velocity[0].powi(2) + self.velocity[1].powi(2)) * frame_time;
+        let steps = usize::max(f32::ceil(step_len / radius) as usize, 1);
+        for _ in 0..steps
+        {
+            for axis in 0..2
+            {
+                let mut moved = me.position;
+                moved[axis] += self.velocity[axis] * frame_time / steps as f32;
+                if level.is_open(moved, radius)
+                {
+                    me.position = moved;
+                }
+                else if self.bounces_left > 0
+                {
+                    self.bounces_left -= 1;
+                    self.velocity[axis] *= -BOUNCINESS;
+                }
+                else
+                {
+                    return (true, None);
+                }
+            }
+
+            // Anything solid other than itself, whoever launched it and other projectiles.
+            let hit = world.colliders.iter()
+                .filter(|&(entity, _)| entity != this && entity != self.owner && world.projectiles.get(entity).is_none())
+                .find(|&(entity, _)|
+                {
+                    let reach = world.collider_radius(level, entity).unwrap_or(0.0) + radius;
+                    world.transforms.get(entity).is_some_and(|other| dist_between(other.position, me.position) < reach)
+                });
+            if let Some((entity, _)) = hit
+            {
+                return (true, Some(entity));
+            }
+        }
+        (false, None)
+    }
+}
+
+/// The movement system for projectiles. Moves every projectile, setting off the ones that hit something, and
+/// clears away explosions once they have been shown.
+pub fn update(world: &mut World, level: &Level, frame_time: f32)
+{
+    for entity in world.explosions.entities()
+    {
+        let (explosion, sprite) = match (world.explosions.get_mut(entity), world.sprites.get_mut(entity))
+        {
+            (Some(explosion), Some(sprite)) => (explosion, sprite),
+            _ => continue,
+        };
+        explosion.age += frame_time;
+        if explosion.age >= EXPLOSION_TIME
+        {
+            world.despawn(entity);
+            continue;
+        }
+
+        let second_half = explosion.age >= EXPLOSION_TIME / 2.0;
+        sprite.frame = EXPLOSION_FRAME + second_half as usize;
+        sprite.size = explosion.blast_radius * if second_half { 0.8 } else { 0.5 };
+        sprite.height = 0.5 - sprite.size / 2.0;
+    }
+
+    for entity in world.projectiles.entities()
+    {
+        let (mut projectile, mut me) = match (world.projectiles.remove(entity), world.transforms.get(entity))
+        {
+            (Some(projectile), Some(&me)) => (projectile, me),
+            _ => continue,
+        };
+        let (explode, hit) = projectile.update(entity, &mut me, level, world, frame_time);
+        world.transforms.insert(entity, me);
+
+        if let Some(hit) = hit
+        {
+            world::damage(world, hit, projectile.kind.info().damage, projectile.origin);
+        }
+        if explode || hit.is_some()
+        {
+            world.despawn(entity);
+            explode_at(world, level, &projectile, me.position);
+        }
+        else
+        {
+            world.projectiles.insert(entity, projectile);
+        }
+    }
+}
+
+/// Hurts every entity in reach of a projectile's explosion that it can see, less the further away they are, and
+/// leaves an explosion to be shown.
+fn explode_at(world: &mut World, level: &Level, projectile: &Projectile, position: [f32; 2])
+{
+    let info = projectile.kind.info();
+    let reach = info.blast_radius * level.tile_size().0;
+    let hurt: Vec<(Entity, f32)> = world.healths.iter()
+        .filter_map(|(entity, _)| world.transforms.get(entity).map(|other| (entity, dist_between(other.position, position))))
+        .filter(|&(entity, dist)| dist < reach &&
+            crate::line_of_sight(level, position, world.transforms.get(entity).unwrap().position))
+        .collect();
+    for (entity, dist) in hurt
+    {
+        let damage = (info.blast_damage as f32 * (1.0 - dist / reach)).ceil() as i32;
+        world::damage(world, entity, damage, projectile.origin);
+    }
+
+    let explosion = world.spawn();
+    world.transforms.insert(explosion, Transform { position, ang: 0.0 });
+    world.sprites.insert(explosion, Sprite { sheet: Sheet::Projectiles, frame: EXPLOSION_FRAME, size: 0.0, height: 0.5 });
+    world.explosions.insert(explosion, Explosion { blast_radius: info.blast_radius, age: 0.0 });
+}
+
+/// Draws every projectile from above as a dot, and every explosion as the outline of a square as big as its
+/// reach. Any that are where `seen` is false are left out.
+pub fn draw_2d(world: &World, level: &Level, canvas: &mut Canvas, empty_tex: &Texture2d,
+    seen: &dyn Fn([f32; 2]) -> bool)
+{
+    let tile_w = level.tile_size().0;
+    for (entity, explosion) in world.explosions.iter()
+    {
+        let [x, y] = match world.transforms.get(entity) { Some(me) if seen(me.position) => me.position, _ => continue };
+        let reach = explosion.blast_radius * tile_w;
+        let corners = [[x - reach, y + reach], [x + reach, y + reach], [x + reach, y - reach], [x - reach, y - reach]];
+        for i in 0..corners.len()
+        {
+            crate::draw_line(Pos { position: corners[i] }, Pos { position: corners[(i + 1) % corners.len()] },
+                (1.0, 0.8, 0.2), 1.0, empty_tex, canvas);
+        }
+    }
+    for (entity, projectile) in world.projectiles.iter()
+    {
+        let [x, y] = match world.transforms.get(entity) { Some(me) if seen(me.position) => me.position, _ => continue };
+        let half = 0.01;
+        crate::draw_rect(Pos { position: [x - half, y + half] }, Pos { position: [x + half, y - half] },
+            ColorTex::Color(empty_tex, projectile.kind.info().color), 1.0, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::enemy;
+
+    /// An open room that is an even number of tiles across, like the maps, for the ray casts.
+    fn room() -> Level
+    {
+        Level::from_rows(&["11111111", "10000001", "10010001", "10000001", "10000001", "10000001", "10000001",
+            "11111111"])
+    }
+
+    fn run(world: &mut World, level: &Level, seconds: f32)
+    {
+        for _ in 0..(seconds / 0.02) as usize
+        {
+            update(world, level, 0.02);
+        }
+    }
+
+    fn health(world: &World, entity: Entity) -> i32
+    {
+        world.healths.get(entity).unwrap().current
+    }
+
+    #[test]
+    fn explosions_hurt_what_they_can_see()
+    {
+        let level = room();
+        let mut world = World::new(Transform { position: level.tile_center((6, 6)), ang: 0.0 });
+        let player = world.player;
+        // Right by the pillar's left face.
+        let position = [level.tile_center((2, 2))[0] + 0.45 * level.tile_size().0, level.tile_center((2, 2))[1]];
+        let center = enemy::spawn(&mut world, &level, (2, 2), 0.0, &[]);
+        world.transforms.get_mut(center).unwrap().position = position;
+        let near = enemy::spawn(&mut world, &level, (2, 1), 0.0, &[]);
+        let behind_pillar = enemy::spawn(&mut world, &level, (4, 2), 0.0, &[]);
+        let far = enemy::spawn(&mut world, &level, (2, 5), 0.0, &[]);
+        let start = health(&world, near);
+        let projectile = Projectile { kind: ProjectileKind::Rocket, velocity: [0.0, 0.0], owner: player,
+            origin: level.tile_center((6, 6)), age: 0.0, bounces_left: 0 };
+
+        explode_at(&mut world, &level, &projectile, position);
+        assert_eq!(health(&world, center), start - ProjectileKind::Rocket.info().blast_damage);
+        assert!((start - ProjectileKind::Rocket.info().blast_damage + 1..start).contains(&health(&world, near)));
+        assert_eq!(health(&world, behind_pillar), start);
+        assert_eq!(health(&world, far), start);
+        assert_eq!(health(&world, player), world.healths.get(player).unwrap().max);
+        assert_eq!(world.explosions.iter().count(), 1);
+
+        run(&mut world, &level, EXPLOSION_TIME + 0.02);
+        assert_eq!(world.explosions.iter().count(), 0);
+    }
+
+    #[test]
+    fn rockets_hit_entities_but_not_their_owner()
+    {
+        let level = room();
+        let mut world = World::new(Transform { position: level.tile_center((1, 5)), ang: 0.0 });
+        let player = world.player;
+        let target = enemy::spawn(&mut world, &level, (6, 5), 0.0, &[]);
+        let rocket = spawn(&mut world, &level, ProjectileKind::Rocket, level.tile_center((1, 5)), 0.0, player);
+
+        run(&mut world, &level, 0.1);
+        assert!(world.projectiles.get(rocket).is_some(), "launched from inside its owner");
+        run(&mut world, &level, 1.0);
+        assert!(world.projectiles.get(rocket).is_none());
+        assert!(health(&world, target) <= 0);
+        assert!(world.colliders.get(target).is_none(), "the dead have no colliders");
+    }
+
+    #[test]
+    fn grenades_bounce_until_the_fuse_runs_out()
+    {
+        let level = room();
+        let mut world = World::new(Transform { position: level.tile_center((1, 5)), ang: 0.0 });
+        let player = world.player;
+        let grenade = spawn(&mut world, &level, ProjectileKind::Grenade, level.tile_center((5, 4)), 0.0, player);
+
+        run(&mut world, &level, 1.0);
+        let projectile = world.projectiles.get(grenade).unwrap();
+        assert!(projectile.velocity[0] < 0.0);
+        assert_eq!(projectile.bounces_left, ProjectileKind::Grenade.info().bounces - 1);
+        run(&mut world, &level, ProjectileKind::Grenade.info().fuse - 1.0 + 0.04);
+        assert!(world.projectiles.get(grenade).is_none());
+        assert_eq!(world.explosions.iter().count(), 1);
+    }
+}
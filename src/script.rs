@@ -0,0 +1,350 @@
+//! Level logic written in [Rhai](https://rhai.rs) scripts, so levels can do more than their triggers without the
+//! game being rebuilt.
+//!
+//! A map's script is the file next to it with the same name and a `.rhai` extension, `cellar.rhai` for
+//! `cellar.map`. Only the functions in the script are run, never the code outside of them: `start` when the level
+//! starts, if it is there, and any function named by a trigger's `script` event or a timer. The functions take no
+//! arguments and can keep anything they need between calls in `this`, which starts out as an empty map.
+//!
+//! ```text
+//! fn start() { this.waves = 0; every(10.0, "wave"); }
+//! fn wave() { this.waves += 1; spawn_enemy(5, 5, 0.0); message("WAVE " + this.waves); }
+//! ```
+//!
+//! The functions that scripts can call are:
+//!
+//! * `width()` and `height()`, the size of the level in tiles.
+//! * `tile(col, row)`, the wall type of a tile, or `-1` if it is off the map.
+//! * `set_tile(col, row, wall)`, changes the wall type of a tile, `0` is open floor.
+//! * `player_col()`, `player_row()` and `player_angle()`, where the player is in tiles and which way they face.
+//! * `teleport(col, row, angle)`, moves the player to the center of a tile.
+//! * `spawn_enemy(col, row, angle)` and `spawn_item(col, row, item)`, the item uses the codes of the item layout.
+//! * `message(text)`, shows the text on the HUD.
+//! * `light(brightness)`, changes how bright the level is, `1.0` is full brightness.
+//! * `after(seconds, function)` and `every(seconds, function)`, run a function once or over and over.
+//! * `cancel(function)`, stops every timer that runs the function.
+//!
+//! Everything a script does goes into the world's event queue, the same as triggers. Scripts are sandboxed, they
+//! can only reach the game through these functions and they are stopped if they run for too long. A script that
+//! does not compile stops its level from loading, and any error while one runs is logged and the game carries on
+//! without it.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST};
+use crate::level::Level;
+use crate::path::Tile;
+use crate::pickup::Item;
+use crate::trigger::Event;
+use crate::world::World;
+
+/// The most operations a single call into a script can take before it is stopped.
+const MAX_OPERATIONS: u64 = 100_000;
+/// How deep functions in a script can call each other.
+const MAX_CALL_LEVELS: usize = 32;
+/// The longest a string or array in a script can get.
+const MAX_SIZE: usize = 1024;
+/// The most timers a script can have running at once.
+const MAX_TIMERS: usize = 64;
+/// The shortest time in seconds between each run of an `every` timer.
+const MIN_INTERVAL: f32 = 0.1;
+
+type ScriptResult = Result<(), Box<EvalAltResult>>;
+
+/// A timer waiting to run a function of the script.
+struct Timer
+{
+    function: String,
+    /// Seconds until it runs.
+    left: f32,
+    /// Seconds between each run if it repeats.
+    every: Option<f32>,
+}
+
+/// What the functions that scripts call share with the game.
+#[derive(Default)]
+struct Shared
+{
+    /// A copy of the level's tiles, taken before each call.
+    tiles: Vec<u8>,
+    width: usize,
+    height: usize,
+    /// Where the player is in tiles and which way they face, as `[col, row, angle]`.
+    player: [f32; 3],
+    /// Events fired by the call, they go into the world's event queue once it is over.
+    events: Vec<Event>,
+    timers: Vec<Timer>,
+}
+
+impl Shared
+{
+    /// The tile at `(col, row)` as given by a script, or an error if it is off the map.
+    fn tile(&self, col: i64, row: i64) -> Result<Tile, Box<EvalAltResult>>
+    {
+        match (usize::try_from(col), usize::try_from(row))
+        {
+            (Ok(col), Ok(row)) if col < self.width && row < self.height => Ok((col, row)),
+            _ => Err(format!("tile {},{} is off the map", col, row).into()),
+        }
+    }
+
+    fn add_timer(&mut self, timer: Timer) -> ScriptResult
+    {
+        if self.timers.len() >= MAX_TIMERS { return Err("too many timers".into()); }
+        self.timers.push(timer);
+        Ok(())
+    }
+}
+
+/// A level's script, ready to have its functions called.
+pub struct Script
+{
+    /// Where the script was loaded from, for the logs.
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    /// The script's `this`, kept between calls.
+    state: Dynamic,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Script
+{
+    /// Loads and compiles a script.
+    ///
+    /// Returns a message saying what is wrong with the script if it can not be loaded.
+    pub fn load(path: &Path) -> Result<Script, String>
+    {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let engine = new_engine(path, &shared);
+        let ast = engine.compile(text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Script { path: path.to_path_buf(), engine, ast, state: Dynamic::from_map(Default::default()), shared })
+    }
+
+    /// Whether the script has a function that takes no arguments with the given name.
+    pub fn has_function(&self, function: &str) -> bool
+    {
+        self.ast.iter_functions().any(|f| f.name == function && f.params.is_empty())
+    }
+
+    /// Runs a function of the script, putting the events it fires into the world's event queue. Any error is
+    /// logged.
+    pub fn call(&mut self, function: &str, world: &mut World, level: &Level)
+    {
+        {
+            let mut shared = self.shared.borrow_mut();
+            shared.tiles.clone_from(&level.tiles);
+            shared.width = level.width;
+            shared.height = level.height;
+            let (tile_w, tile_h) = level.tile_size();
+            let player_pos = world.player_pos();
+            shared.player = [(player_pos.position[0] + 1.0) / tile_w, (player_pos.position[1] + 1.0) / tile_h,
+                player_pos.ang];
+        }
+
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, function, ());
+        if let Err(error) = result
+        {
+            eprintln!("{}: {}", self.path.display(), error);
+        }
+        world.events.extend(self.shared.borrow_mut().events.drain(..));
+    }
+}
+
+/// Makes an engine that can only run the functions in the [module docs](index.html), which work on `shared`.
+fn new_engine(path: &Path, shared: &Rc<RefCell<Shared>>) -> Engine
+{
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_string_size(MAX_SIZE)
+        .set_max_array_size(MAX_SIZE)
+        .set_max_map_size(MAX_SIZE);
+    engine.disable_symbol("eval");
+    let name = path.display().to_string();
+    engine.on_print(move |text| println!("{}: {}", name, text));
+
+    let s = shared.clone();
+    engine.register_fn("width", move || s.borrow().width as i64);
+    let s = shared.clone();
+    engine.register_fn("height", move || s.borrow().height as i64);
+    let s = shared.clone();
+    engine.register_fn("tile", move |col: i64, row: i64|
+    {
+        let shared = s.borrow();
+        shared.tile(col, row).map_or(-1, |(col, row)| shared.tiles[row * shared.width + col] as i64)
+    });
+    let s = shared.clone();
+    engine.register_fn("set_tile", move |col: i64, row: i64, wall: i64| -> ScriptResult
+    {
+        let mut shared = s.borrow_mut();
+        let tile = shared.tile(col, row)?;
+        let wall = u8::try_from(wall).map_err(|_| format!("{} is not a wall type", wall))?;
+        // So the script sees the change straight away, the level's tiles change once the event is handled.
+        let width = shared.width;
+        shared.tiles[tile.1 * width + tile.0] = wall;
+        shared.events.push(Event::SetTile(tile, wall));
+        Ok(())
+    });
+
+    let s = shared.clone();
+    engine.register_fn("player_col", move || s.borrow().player[0] as f64);
+    let s = shared.clone();
+    engine.register_fn("player_row", move || s.borrow().player[1] as f64);
+    let s = shared.clone();
+    engine.register_fn("player_angle", move || s.borrow().player[2] as f64);
+    let s = shared.clone();
+    engine.register_fn("teleport", move |col: i64, row: i64, ang: f64| -> ScriptResult
+    {
+        let mut shared = s.borrow_mut();
+        let tile = shared.tile(col, row)?;
+        shared.events.push(Event::Teleport(tile, ang as f32));
+        Ok(())
+    });
+
+    let s = shared.clone();
+    engine.register_fn("spawn_enemy", move |col: i64, row: i64, ang: f64| -> ScriptResult
+    {
+        let mut shared = s.borrow_mut();
+        let tile = shared.tile(col, row)?;
+        shared.events.push(Event::Spawn(tile, ang as f32));
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("spawn_item", move |col: i64, row: i64, code: i64| -> ScriptResult
+    {
+        let mut shared = s.borrow_mut();
+        let tile = shared.tile(col, row)?;
+        let item = u8::try_from(code).ok().and_then(Item::from_code).ok_or_else(|| format!("{} is not an item", code))?;
+        shared.events.push(Event::Item(tile, item));
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("message", move |text: &str| s.borrow_mut().events.push(Event::Message(text.to_uppercase())));
+    let s = shared.clone();
+    engine.register_fn("light", move |light: f64| s.borrow_mut().events.push(Event::Light(light as f32)));
+
+    let s = shared.clone();
+    engine.register_fn("after", move |seconds: f64, function: &str| -> ScriptResult
+    {
+        s.borrow_mut().add_timer(Timer { function: function.to_string(), left: seconds as f32, every: None })
+    });
+    let s = shared.clone();
+    engine.register_fn("every", move |seconds: f64, function: &str| -> ScriptResult
+    {
+        let every = f32::max(seconds as f32, MIN_INTERVAL);
+        s.borrow_mut().add_timer(Timer { function: function.to_string(), left: every, every: Some(every) })
+    });
+    let s = shared.clone();
+    engine.register_fn("cancel", move |function: &str|
+        s.borrow_mut().timers.retain(|timer| timer.function != function));
+    engine
+}
+
+/// The script system. Counts down the level script's timers, firing a [`Event::Script`] for each one that is up.
+///
+/// [`Event::Script`]: ../trigger/enum.Event.html#variant.Script
+pub fn update(world: &mut World, level: &Level, frame_time: f32)
+{
+    let script = match level.script.as_ref() { Some(script) => script, None => return };
+    let mut shared = script.shared.borrow_mut();
+    for timer in shared.timers.iter_mut()
+    {
+        timer.left -= frame_time;
+        if timer.left <= 0.0
+        {
+            world.events.push_back(Event::Script(timer.function.clone()));
+            if let Some(every) = timer.every
+            {
+                timer.left = every;
+            }
+        }
+    }
+    shared.timers.retain(|timer| timer.left > 0.0);
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::world::Transform;
+
+    /// Loads a script from a temp file, with a small open level and a world to call it in.
+    fn load(name: &str, text: &str) -> (Script, Level, World)
+    {
+        let path = std::env::temp_dir().join(format!("script-test-{}-{}.rhai", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let script = Script::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let level = Level::from_rows(&["111", "101", "111"]);
+        let world = World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 });
+        (script.unwrap(), level, world)
+    }
+
+    #[test]
+    fn call_fires_events()
+    {
+        let text = "fn open() { set_tile(1, 1, 2); message(\"hi \" + tile(1, 1)); }";
+        let (mut script, level, mut world) = load("events", text);
+        script.call("open", &mut world, &level);
+        assert_eq!(world.events.drain(..).collect::<Vec<_>>(),
+            vec![Event::SetTile((1, 1), 2), Event::Message(String::from("HI 2"))]);
+        assert_eq!(level.tiles[4], 0, "the level only changes once the event is handled");
+    }
+
+    #[test]
+    fn endless_loop_is_stopped()
+    {
+        let (mut script, level, mut world) = load("loop", "fn forever() { let n = 0; loop { n += 1; } }\n\
+            fn after_it() { message(\"still here\"); }");
+        script.call("forever", &mut world, &level);
+        assert!(world.events.is_empty());
+        script.call("after_it", &mut world, &level);
+        assert_eq!(world.events.pop_front(), Some(Event::Message(String::from("STILL HERE"))));
+    }
+
+    #[test]
+    fn off_the_map_is_an_error()
+    {
+        let text = "fn wall() { set_tile(3, 1, 1); message(\"set\"); }\n\
+            fn warp() { teleport(1, -1, 0.0); message(\"warped\"); }\n\
+            fn caught() { try { teleport(9, 9, 0.0); } catch (error) { message(\"caught\"); } }";
+        let (mut script, level, mut world) = load("off-map", text);
+        script.call("wall", &mut world, &level);
+        script.call("warp", &mut world, &level);
+        assert!(world.events.is_empty());
+        script.call("caught", &mut world, &level);
+        assert_eq!(world.events.pop_front(), Some(Event::Message(String::from("CAUGHT"))));
+    }
+
+    #[test]
+    fn timers_are_limited()
+    {
+        let text = "fn start() { for i in 0..100 { every(1.0, \"tick\"); } }";
+        let (mut script, level, mut world) = load("timers", text);
+        script.call("start", &mut world, &level);
+        assert_eq!(script.shared.borrow().timers.len(), MAX_TIMERS);
+    }
+
+    #[test]
+    fn after_runs_once_and_every_repeats()
+    {
+        let text = "fn start() { after(0.5, \"once\"); every(0.4, \"tick\"); }";
+        let (mut script, mut level, mut world) = load("after", text);
+        script.call("start", &mut world, &level);
+        level.script = Some(script);
+
+        update(&mut world, &level, 0.45);
+        assert_eq!(world.events.drain(..).collect::<Vec<_>>(), vec![Event::Script(String::from("tick"))]);
+        update(&mut world, &level, 0.1);
+        assert_eq!(world.events.drain(..).collect::<Vec<_>>(), vec![Event::Script(String::from("once"))]);
+        let timers: Vec<String> = level.script.as_ref().unwrap().shared.borrow().timers.iter()
+            .map(|timer| timer.function.clone())
+            .collect();
+        assert_eq!(timers, vec![String::from("tick")]);
+    }
+}
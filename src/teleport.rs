@@ -0,0 +1,193 @@
+//! Teleporters, pairs of floor tiles that send whoever steps on one of them over to the other.
+//!
+//! Teleporters are declared in the map file with a `teleporter` line, giving each of the two pads and the angle
+//! faced when arriving on it:
+//!
+//! ```text
+//! teleporter <col,row> <angle> <col,row> <angle> [everyone]
+//! ```
+//!
+//! Only the player uses a teleporter unless it is marked `everyone`, then enemies go through it as well.
+
+use glium::texture::Texture2d;
+use crate::eye;
+use crate::level::{self, Level};
+use crate::path::Tile;
+use crate::world::{Entity, Transform, World};
+use crate::{Canvas, Pos};
+
+/// Seconds after going through a teleporter before the same entity can go through one again.
+const COOLDOWN: f32 = 1.0;
+/// The color of the pads' floors, and of the lines between them in the 2d view.
+pub const PAD_COLOR: (f32, f32, f32) = (0.3, 0.9, 0.9);
+
+/// A pair of teleporter pads.
+#[derive(Clone, Debug)]
+pub struct Teleporter
+{
+    /// The tile of each pad and the angle faced when arriving on it.
+    pads: [(Tile, f32); 2],
+    /// If set enemies go through it as well as the player.
+    everyone: bool,
+}
+
+impl Teleporter
+{
+    /// Reads the values of a `teleporter` line in a map file, see the [module docs](index.html) for the format.
+    ///
+    /// Returns a message saying what is wrong if the line can not be read.
+    pub fn parse(values: &[&str]) -> Result<Teleporter, String>
+    {
+        let pad = |tile: Option<&&str>, ang: Option<&&str>| -> Result<(Tile, f32), String>
+        {
+            let tile = level::parse_tile(tile.ok_or("expected a tile")?)?;
+            let ang = ang.and_then(|ang| ang.parse().ok()).ok_or("expected an angle")?;
+            Ok((tile, ang))
+        };
+
+        let pads = [pad(values.first(), values.get(1))?, pad(values.get(2), values.get(3))?];
+        let everyone = match values.get(4)
+        {
+            None => false,
+            Some(&"everyone") => true,
+            Some(value) => return Err(format!("expected everyone but found `{}`", value)),
+        };
+        Ok(Teleporter { pads, everyone })
+    }
+
+    /// The tiles of both pads.
+    pub fn tiles(&self) -> Vec<Tile>
+    {
+        self.pads.iter().map(|&(tile, _)| tile).collect()
+    }
+
+    /// Where an entity on a tile is sent, if the tile is one of the pads.
+    fn destination(&self, tile: Tile) -> Option<(Tile, f32)>
+    {
+        self.pads.iter().position(|&(pad, _)| pad == tile).map(|i| self.pads[1 - i])
+    }
+}
+
+/// The teleported component, for an entity that has just gone through a teleporter. It can not go through
+/// another one until the component is gone, which is once the cooldown is over and it has stepped off the pad.
+pub struct Teleported
+{
+    /// Seconds left of the cooldown.
+    cooldown: f32,
+}
+
+/// Whether a tile is one of the pads of any teleporter.
+pub fn is_pad(level: &Level, tile: Tile) -> bool
+{
+    level.teleporters.iter().any(|teleporter| teleporter.destination(tile).is_some())
+}
+
+/// The teleporter system. Sends the player or any enemy that is standing on a pad to the other one, if it is
+/// allowed to use that teleporter and its cooldown is over. Only those with colliders go through, so the dead stay
+/// where they fell, and projectiles never do.
+pub fn update(world: &mut World, level: &Level, frame_time: f32)
+{
+    let walkers = std::iter::once(world.player).chain(world.ais.entities());
+    for entity in walkers.filter(|&entity| world.colliders.get(entity).is_some()).collect::<Vec<_>>()
+    {
+        let position = match world.transforms.get(entity) { Some(me) => me.position, None => continue };
+        let tile = level.tile_at(position);
+        let on_pad = tile.is_some_and(|tile| is_pad(level, tile));
+
+        if let Some(teleported) = world.teleported.get_mut(entity)
+        {
+            teleported.cooldown -= frame_time;
+            if teleported.cooldown > 0.0 || on_pad { continue; }
+            world.teleported.remove(entity);
+        }
+
+        let destination = tile.and_then(|tile| level.teleporters.iter()
+            .filter(|teleporter| teleporter.everyone || entity == world.player)
+            .find_map(|teleporter| teleporter.destination(tile)));
+        if let Some((tile, ang)) = destination
+        {
+            send(world, level, entity, tile, ang);
+        }
+    }
+}
+
+/// Moves an entity onto a tile and starts its cooldown, so it is not sent straight back if the tile is a pad. The
+/// player lands on the ground floor with a flash.
+pub fn send(world: &mut World, level: &Level, entity: Entity, tile: Tile, ang: f32)
+{
+    world.transforms.insert(entity, Transform { position: level.tile_center(tile), ang });
+    world.teleported.insert(entity, Teleported { cooldown: COOLDOWN });
+    // The path it was following is from the other pad.
+    if let Some(ai) = world.ais.get_mut(entity)
+    {
+        ai.clear_path();
+    }
+    if let Some(state) = world.player_states.get_mut(entity)
+    {
+        state.warped();
+    }
+    if entity == world.player
+    {
+        eye::land(world);
+    }
+}
+
+/// Draws a line between the two pads of every teleporter from above.
+pub fn draw_2d(level: &Level, canvas: &mut Canvas, empty_tex: &Texture2d)
+{
+    for teleporter in level.teleporters.iter()
+    {
+        let [(from, _), (to, _)] = teleporter.pads;
+        crate::draw_line(Pos { position: level.tile_center(from) }, Pos { position: level.tile_center(to) },
+            PAD_COLOR, 1.0, empty_tex, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parse_pads()
+    {
+        let teleporter = Teleporter::parse(&["1,1", "0", "9,9", "1.5"]).unwrap();
+        assert_eq!(teleporter.pads, [((1, 1), 0.0), ((9, 9), 1.5)]);
+        assert!(!teleporter.everyone);
+        assert_eq!(teleporter.tiles(), vec![(1, 1), (9, 9)]);
+        assert_eq!(teleporter.destination((1, 1)), Some(((9, 9), 1.5)));
+        assert_eq!(teleporter.destination((9, 9)), Some(((1, 1), 0.0)));
+        assert_eq!(teleporter.destination((2, 2)), None);
+    }
+
+    #[test]
+    fn parse_everyone()
+    {
+        assert!(Teleporter::parse(&["1,1", "0", "9,9", "1.5", "everyone"]).unwrap().everyone);
+    }
+
+    #[test]
+    fn parse_errors()
+    {
+        let error = |values: &[&str]| Teleporter::parse(values).unwrap_err();
+        assert_eq!(error(&[]), "expected a tile");
+        assert_eq!(error(&["1,1"]), "expected an angle");
+        assert_eq!(error(&["1,1", "north", "9,9", "0"]), "expected an angle");
+        assert_eq!(error(&["1,1", "0", "9"]), "expected a tile as col,row but found `9`");
+        assert_eq!(error(&["1,1", "0", "9,9", "0", "enemies"]), "expected everyone but found `enemies`");
+    }
+
+    #[test]
+    fn send_lands_the_player_on_the_ground_floor()
+    {
+        let level = Level::from_rows(&["1111", "1001", "1111"]);
+        let mut world = World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 });
+        eye::set_storey(&mut world, 1);
+        let player = world.player;
+        send(&mut world, &level, player, (2, 1), 1.5);
+        assert_eq!(world.player_pos().position, level.tile_center((2, 1)));
+        assert_eq!(world.player_pos().ang, 1.5);
+        assert_eq!(eye::storey(&world), 0);
+        assert!(world.teleported.get(player).is_some());
+    }
+}
@@ -0,0 +1,260 @@
+//! Outdoor terrain made from a height map and a color map, drawn like the voxel space renderers of old flight and
+//! tank games instead of with walls.
+//!
+//! A map with a `terrain` setting, followed by the height map and color map images next to it, is drawn as the
+//! terrain instead of its tiles. The two images are stretched over the whole level, the brightness of the height
+//! map is how high the ground is and the color map is what color it is. The player walks on top of the ground.
+
+use std::cell::OnceCell;
+use std::path::Path;
+use glium::texture::{RawImage2d, Texture2d};
+use image::RgbaImage;
+use crate::level::Level;
+use crate::{Canvas, ColorTex, PlayerPos, Pos};
+
+/// How high the brightest spots of the height map are, compared to a tile.
+const MAX_HEIGHT: f32 = 3.0;
+/// How far away the nearest ground that is drawn is, compared to a tile.
+const NEAR: f32 = 0.1;
+/// How far apart the nearest steps along each column are, compared to a tile.
+const FIRST_STEP: f32 = 0.02;
+/// How much further apart each step along a column is than the one before, so far off ground is drawn coarser.
+const STEP_GROWTH: f32 = 1.02;
+
+/// Where the ground drawn in a column of the 3d view rises, nearest first, as the corrected distance to each rise and
+/// how high on the screen the ground is drawn up to from there on.
+pub type Profile = Vec<(f32, f32)>;
+
+/// A height map and color map that cover a level.
+pub struct Terrain
+{
+    /// How high the ground is at each pixel, row major from the top of the level.
+    heights: Vec<f32>,
+    /// What color the ground is.
+    colors: RgbaImage,
+    /// The height map as it was loaded, for drawing from above.
+    height_image: RgbaImage,
+    /// The color map and height map as textures, made the first time they are drawn.
+    textures: OnceCell<(Texture2d, Texture2d)>,
+}
+
+impl Terrain
+{
+    /// Loads a terrain from a height map and a color map of the same size.
+    ///
+    /// Returns a message saying what is wrong if they can not be loaded.
+    pub fn load(height_path: &Path, color_path: &Path) -> Result<Terrain, String>
+    {
+        let open = |path: &Path| image::open(path).map(|image| image.to_rgba())
+            .map_err(|e| format!("{}: {}", path.display(), e));
+        let height_image = open(height_path)?;
+        let colors = open(color_path)?;
+        if height_image.dimensions() != colors.dimensions()
+        {
+            return Err(format!("{}: the color map is not the same size as the height map", color_path.display()));
+        }
+
+        Ok(Terrain
+        {
+            heights: height_image.pixels().map(|pixel| pixel[0] as f32 / 255.0 * MAX_HEIGHT).collect(),
+            colors,
+            height_image,
+            textures: OnceCell::new(),
+        })
+    }
+
+    /// The pixel of the maps that a play space position is over as `(x, y)`, or `None` if it is outside of the
+    /// level.
+    fn pixel_at(&self, position: [f32; 2]) -> Option<(u32, u32)>
+    {
+        let (width, height) = self.colors.dimensions();
+        let x = f32::floor((position[0] + 1.0) / 2.0 * width as f32);
+        let y = f32::floor((position[1] + 1.0) / 2.0 * height as f32);
+        if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 { return None; }
+        Some((x as u32, y as u32))
+    }
+
+    /// How high the ground is at a play space position compared to a tile, anywhere outside of the level is flat.
+    pub fn height_at(&self, position: [f32; 2]) -> f32
+    {
+        self.pixel_at(position).map_or(0.0, |(x, y)| self.heights[(y * self.colors.width() + x) as usize])
+    }
+
+    /// The color map and height map as textures, making them the first time.
+    fn textures(&self, canvas: &Canvas) -> &(Texture2d, Texture2d)
+    {
+        self.textures.get_or_init(||
+        {
+            let texture = |image: &RgbaImage|
+            {
+                let raw = RawImage2d::from_raw_rgba_reversed(image, image.dimensions());
+                Texture2d::new(canvas.display, raw).unwrap()
+            };
+            (texture(&self.colors), texture(&self.height_image))
+        })
+    }
+
+    /// Draws the terrain into the 3d view, seen from `player_pos` with the eyes `eye` tiles off of the ground
+    /// floor, in place of the floor.
+    ///
+    /// Each column of the view walks out from the player along its ray, drawing the ground from the top of what has
+    /// been drawn so far up to the height of the ground at each step. Nearer ground is drawn first, so anything that
+    /// would be drawn lower than it is hidden behind it. Special floors are drawn in their color on top of the
+    /// ground. `view_bottom` is where the bottom of the view is, and each column stops at the corrected distance in
+    /// `depths`, indexed by ray, so the walls behind the ground are left showing.
+    ///
+    /// Returns the [`Profile`] of the ground drawn in each column.
+    ///
+    /// [`Profile`]: type.Profile.html
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_3d(&self, canvas: &mut Canvas, level: &Level, player_pos: &PlayerPos, eye: f32, view_bottom: f32,
+        depths: &[f32], empty: &Texture2d) -> Vec<Profile>
+    {
+        let (width, height) = self.colors.dimensions();
+        let tile_w = level.tile_size().0;
+        let rays = crate::RAYS;
+        let mut rects = Vec::new();
+        let mut pads: Vec<(Pos, Pos, (f32, f32, f32))> = Vec::new();
+        let mut profiles = Vec::with_capacity(rays);
+        for i in 0..rays
+        {
+            let ray_ang = player_pos.ang - crate::FOV / 2.0 + i as f32 * crate::FOV / rays as f32;
+            let col_left = (rays - i - 1) as f32 * 2.0 / rays as f32 - 1.0;
+            let col_right = (rays - i) as f32 * 2.0 / rays as f32 - 1.0;
+            let dist_mul = f32::cos(ray_ang - player_pos.ang);
+            let (dir_x, dir_y) = (f32::cos(ray_ang), f32::sin(ray_ang));
+            let depth = depths.get(i).copied().unwrap_or(f32::INFINITY);
+
+            let mut drawn_to = view_bottom;
+            let mut profile = Vec::new();
+            let (mut dist, mut step) = (NEAR * tile_w, FIRST_STEP * tile_w);
+            let position = |dist: f32| [player_pos.position[0] + dir_x * dist, player_pos.position[1] + dir_y * dist];
+            while dist * dist_mul < depth
+            {
+                let (x, y) = match self.pixel_at(position(dist)) { Some(pixel) => pixel, None => break };
+                let top = crate::wall_y(level, eye, self.heights[(y * width + x) as usize], dist * dist_mul);
+                if top > drawn_to
+                {
+                    let (tl, br) = (Pos { position: [col_right, top] }, Pos { position: [col_left, drawn_to] });
+                    let pad = level.tile_at(position(dist)).and_then(|(col, row)| crate::floor_color(level, col, row));
+                    match (pad, pads.last_mut())
+                    {
+                        // Steps of the same pad in a column are joined into one rect.
+                        (Some(color), Some(last)) if last.2 == color && last.0.position[0] == col_right
+                            && last.0.position[1] == drawn_to => last.0.position[1] = top,
+                        (Some(color), _) => pads.push((tl, br, color)),
+                        // The middle of the pixel, with the rows upside down as the texture is.
+                        (None, _) => rects.push((tl, br,
+                            [(x as f32 + 0.5) / width as f32, 1.0 - (y as f32 + 0.5) / height as f32])),
+                    }
+                    profile.push((dist * dist_mul, top));
+                    drawn_to = top;
+                }
+                dist += step;
+                step *= STEP_GROWTH;
+            }
+            profiles.push(profile);
+        }
+
+        // Textures are drawn at half brightness.
+        let (colors, _) = self.textures(canvas);
+        crate::draw_rects(&rects, colors, 2.0 * level.light, canvas);
+        for (tl, br, color) in pads
+        {
+            crate::draw_rect(tl, br, ColorTex::Color(empty, color), 0.7 * level.light, canvas);
+        }
+        profiles
+    }
+
+    /// Draws the height map from above over the whole level, in play space coordinates.
+    pub fn draw_2d(&self, canvas: &mut Canvas)
+    {
+        let (_, heights) = self.textures(canvas);
+        let tex_coords = ([0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]);
+        crate::draw_rect(Pos { position: [-1.0, -1.0] }, Pos { position: [1.0, 1.0] },
+            ColorTex::Texture(heights, tex_coords), 2.0, canvas);
+    }
+}
+
+/// How high on the screen the ground drawn in a column hides things at the corrected distance `dist` up to, given the
+/// column's [`Profile`]. It is negative infinity if none of the ground is in front of them.
+///
+/// [`Profile`]: type.Profile.html
+pub fn ground_top(profile: &[(f32, f32)], dist: f32) -> f32
+{
+    match profile.partition_point(|&(rise, _)| rise < dist)
+    {
+        0 => f32::NEG_INFINITY,
+        rises => profile[rises - 1].1,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// A terrain 4 pixels wide and 2 high, where each pixel is as high as its index, row major.
+    fn terrain() -> Terrain
+    {
+        let colors = RgbaImage::new(4, 2);
+        Terrain
+        {
+            heights: (0..8).map(|i| i as f32).collect(),
+            height_image: colors.clone(),
+            colors,
+            textures: OnceCell::new(),
+        }
+    }
+
+    #[test]
+    fn pixel_at_corners()
+    {
+        let terrain = terrain();
+        assert_eq!(terrain.pixel_at([-1.0, -1.0]), Some((0, 0)));
+        assert_eq!(terrain.pixel_at([0.99, -1.0]), Some((3, 0)));
+        assert_eq!(terrain.pixel_at([-1.0, 0.99]), Some((0, 1)));
+        assert_eq!(terrain.pixel_at([0.99, 0.99]), Some((3, 1)));
+        assert_eq!(terrain.pixel_at([0.0, 0.0]), Some((2, 1)));
+    }
+
+    #[test]
+    fn pixel_at_outside()
+    {
+        let terrain = terrain();
+        assert_eq!(terrain.pixel_at([1.0, 0.0]), None);
+        assert_eq!(terrain.pixel_at([0.0, 1.0]), None);
+        assert_eq!(terrain.pixel_at([-1.01, 0.0]), None);
+        assert_eq!(terrain.pixel_at([0.0, -1.01]), None);
+    }
+
+    #[test]
+    fn height_at_edges()
+    {
+        let terrain = terrain();
+        assert_eq!(terrain.height_at([-1.0, -1.0]), 0.0);
+        assert_eq!(terrain.height_at([0.99, -1.0]), 3.0);
+        assert_eq!(terrain.height_at([-1.0, 0.99]), 4.0);
+        assert_eq!(terrain.height_at([0.99, 0.99]), 7.0);
+    }
+
+    #[test]
+    fn height_at_outside_is_flat()
+    {
+        let terrain = terrain();
+        assert_eq!(terrain.height_at([1.0, 0.99]), 0.0);
+        assert_eq!(terrain.height_at([-2.0, 0.0]), 0.0);
+        assert_eq!(terrain.height_at([0.0, 5.0]), 0.0);
+    }
+
+    #[test]
+    fn ground_top_is_the_nearest_rise_in_front()
+    {
+        let profile = [(0.5, -0.8), (1.0, -0.2), (2.0, 0.1)];
+        assert_eq!(ground_top(&profile, 0.2), f32::NEG_INFINITY);
+        assert_eq!(ground_top(&profile, 0.5), f32::NEG_INFINITY);
+        assert_eq!(ground_top(&profile, 1.5), -0.2);
+        assert_eq!(ground_top(&profile, 9.0), 0.1);
+        assert_eq!(ground_top(&[], 1.0), f32::NEG_INFINITY);
+    }
+}
@@ -0,0 +1,201 @@
+//! Text drawing with a small built in bitmap font.
+//!
+//! The glyphs are packed into a single texture that is used as a [`ColorTex::Mask`], so text goes through the
+//! same shader as everything else and can be drawn in any color.
+//!
+//! [`ColorTex::Mask`]: ../enum.ColorTex.html
+
+use glium::Display;
+use glium::texture::{RawImage2d, Texture2d, TextureCreationError};
+use crate::{Canvas, ColorTex, Pos};
+
+/// Width of a glyph in font pixels.
+const GLYPH_WIDTH: usize = 5;
+/// Height of a glyph in font pixels.
+const GLYPH_HEIGHT: usize = 7;
+/// Space left after each glyph and between lines in font pixels.
+const GLYPH_SPACING: usize = 1;
+
+/// Every glyph in the font, drawn top row first. Lowercase letters are drawn with the uppercase glyphs and
+/// anything that is missing is drawn as a `?`.
+const GLYPHS: &[(char, [&str; GLYPH_HEIGHT])] = &[
+    (' ', [".....", ".....", ".....", ".....", ".....", ".....", "....."]),
+    ('0', [".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###."]),
+    ('1', ["..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('2', [".###.", "#...#", "....#", "...#.", "..#..", ".#...", "#####"]),
+    ('3', ["#####", "...#.", "..#..", "...#.", "....#", "#...#", ".###."]),
+    ('4', ["...#.", "..##.", ".#.#.", "#..#.", "#####", "...#.", "...#."]),
+    ('5', ["#####", "#....", "####.", "....#", "....#", "#...#", ".###."]),
+    ('6', ["..##.", ".#...", "#....", "####.", "#...#", "#...#", ".###."]),
+    ('7', ["#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#..."]),
+    ('8', [".###.", "#...#", "#...#", ".###.", "#...#", "#...#", ".###."]),
+    ('9', [".###.", "#...#", "#...#", ".####", "....#", "...#.", ".##.."]),
+    ('A', [".###.", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('B', ["####.", "#...#", "#...#", "####.", "#...#", "#...#", "####."]),
+    ('C', [".###.", "#...#", "#....", "#....", "#....", "#...#", ".###."]),
+    ('D', ["###..", "#..#.", "#...#", "#...#", "#...#", "#..#.", "###.."]),
+    ('E', ["#####", "#....", "#....", "####.", "#....", "#....", "#####"]),
+    ('F', ["#####", "#....", "#....", "####.", "#....", "#....", "#...."]),
+    ('G', [".###.", "#...#", "#....", "#.###", "#...#", "#...#", ".####"]),
+    ('H', ["#...#", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('I', [".###.", "..#..", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('J', ["..###", "...#.", "...#.", "...#.", "...#.", "#..#.", ".##.."]),
+    ('K', ["#...#", "#..#.", "#.#..", "##...", "#.#..", "#..#.", "#...#"]),
+    ('L', ["#....", "#....", "#....", "#....", "#....", "#....", "#####"]),
+    ('M', ["#...#", "##.##", "#.#.#", "#.#.#", "#...#", "#...#", "#...#"]),
+    ('N', ["#...#", "#...#", "##..#", "#.#.#", "#..##", "#...#", "#...#"]),
+    ('O', [".###.", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('P', ["####.", "#...#", "#...#", "####.", "#....", "#....", "#...."]),
+    ('Q', [".###.", "#...#", "#...#", "#...#", "#.#.#", "#..#.", ".##.#"]),
+    ('R', ["####.", "#...#", "#...#", "####.", "#.#..", "#..#.", "#...#"]),
+    ('S', [".####", "#....", "#....", ".###.", "....#", "....#", "####."]),
+    ('T', ["#####", "..#..", "..#..", "..#..", "..#..", "..#..", "..#.."]),
+    ('U', ["#...#", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('V', ["#...#", "#...#", "#...#", "#...#", "#...#", ".#.#.", "..#.."]),
+    ('W', ["#...#", "#...#", "#...#", "#.#.#", "#.#.#", "#.#.#", ".#.#."]),
+    ('X', ["#...#", "#...#", ".#.#.", "..#..", ".#.#.", "#...#", "#...#"]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#..", "..#.."]),
+    ('Z', ["#####", "....#", "...#.", "..#..", ".#...", "#....", "#####"]),
+    ('.', [".....", ".....", ".....", ".....", ".....", ".##..", ".##.."]),
+    (',', [".....", ".....", ".....", ".....", ".##..", "..#..", ".#..."]),
+    (':', [".....", ".##..", ".##..", ".....", ".##..", ".##..", "....."]),
+    (';', [".....", ".##..", ".##..", ".....", ".##..", "..#..", ".#..."]),
+    ('!', ["..#..", "..#..", "..#..", "..#..", "..#..", ".....", "..#.."]),
+    ('?', [".###.", "#...#", "....#", "...#.", "..#..", ".....", "..#.."]),
+    ('-', [".....", ".....", ".....", "#####", ".....", ".....", "....."]),
+    ('+', [".....", "..#..", "..#..", "#####", "..#..", "..#..", "....."]),
+    ('=', [".....", ".....", "#####", ".....", "#####", ".....", "....."]),
+    ('_', [".....", ".....", ".....", ".....", ".....", ".....", "#####"]),
+    ('/', ["....#", "....#", "...#.", "..#..", ".#...", "#....", "#...."]),
+    ('%', ["##...", "##..#", "...#.", "..#..", ".#...", "#..##", "...##"]),
+    ('(', ["...#.", "..#..", ".#...", ".#...", ".#...", "..#..", "...#."]),
+    (')', [".#...", "..#..", "...#.", "...#.", "...#.", "..#..", ".#..."]),
+    ('[', [".###.", ".#...", ".#...", ".#...", ".#...", ".#...", ".###."]),
+    (']', [".###.", "...#.", "...#.", "...#.", "...#.", "...#.", ".###."]),
+    ('<', ["...#.", "..#..", ".#...", "#....", ".#...", "..#..", "...#."]),
+    ('>', [".#...", "..#..", "...#.", "....#", "...#.", "..#..", ".#..."]),
+    ('#', [".#.#.", ".#.#.", "#####", ".#.#.", "#####", ".#.#.", ".#.#."]),
+    ('*', [".....", "..#..", "#.#.#", ".###.", "#.#.#", "..#..", "....."]),
+    ('\'', ["..#..", "..#..", ".#...", ".....", ".....", ".....", "....."]),
+    ('"', [".#.#.", ".#.#.", ".....", ".....", ".....", ".....", "....."]),
+];
+
+/// Which point of the text is placed at the position it is drawn at.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Anchor
+{
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor
+{
+    /// How far the anchor point is into the text as fractions of the text's `(width, height)`, from the top left.
+    fn offset(self) -> (f32, f32)
+    {
+        match self
+        {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// The font's glyph atlas.
+pub struct Font
+{
+    atlas: Texture2d,
+}
+
+impl Font
+{
+    /// Packs all of the glyphs side by side into a texture.
+    pub fn new(display: &Display) -> Result<Font, TextureCreationError>
+    {
+        let cell_width = GLYPH_WIDTH + GLYPH_SPACING;
+        let width = GLYPHS.len() * cell_width;
+        let mut pixels = vec![0u8; width * GLYPH_HEIGHT * 4];
+
+        for (i, (_, rows)) in GLYPHS.iter().enumerate()
+        {
+            for (row, line) in rows.iter().enumerate()
+            {
+                // Textures are stored bottom row first.
+                let y = GLYPH_HEIGHT - 1 - row;
+                for (col, pixel) in line.bytes().enumerate()
+                {
+                    if pixel == b'#'
+                    {
+                        let start = (y * width + i * cell_width + col) * 4;
+                        pixels[start..start + 4].copy_from_slice(&[255, 255, 255, 255]);
+                    }
+                }
+            }
+        }
+
+        let image = RawImage2d::from_raw_rgba(pixels, (width as u32, GLYPH_HEIGHT as u32));
+        Ok(Font { atlas: Texture2d::new(display, image)? })
+    }
+
+    /// The texture coords of a character's glyph.
+    fn glyph_coords(&self, c: char) -> ([f32; 2], [f32; 2], [f32; 2], [f32; 2])
+    {
+        let c = c.to_ascii_uppercase();
+        let index = GLYPHS.iter().position(|&(g, _)| g == c)
+            .unwrap_or_else(|| GLYPHS.iter().position(|&(g, _)| g == '?').unwrap());
+
+        let atlas_width = (GLYPHS.len() * (GLYPH_WIDTH + GLYPH_SPACING)) as f32;
+        let left = (index * (GLYPH_WIDTH + GLYPH_SPACING)) as f32 / atlas_width;
+        let right = left + GLYPH_WIDTH as f32 / atlas_width;
+        ([left, 1.0], [right, 1.0], [right, 0.0], [left, 0.0])
+    }
+}
+
+/// The size of a line of text in pixels as `(width, height)` when drawn at the given scale.
+pub fn text_size(text: &str, scale: f32) -> (f32, f32)
+{
+    let chars = text.chars().count();
+    let width = if chars == 0 { 0 } else { chars * (GLYPH_WIDTH + GLYPH_SPACING) - GLYPH_SPACING };
+    (width as f32 * scale, GLYPH_HEIGHT as f32 * scale)
+}
+
+/// Draws a line of text so that its `anchor` point lands on `pos`.
+///
+/// `scale` is how many pixels on the window each font pixel takes up, so text stays the same size no matter the
+/// size of the window.
+pub fn draw_text(text: &str, pos: Pos, anchor: Anchor, scale: f32, color: (f32, f32, f32), font: &Font,
+    canvas: &mut Canvas)
+{
+    let (view_width, view_height) = canvas.view_size();
+    let (pixel_w, pixel_h) = (2.0 / view_width as f32, 2.0 / view_height as f32);
+
+    let (text_w, text_h) = text_size(text, scale);
+    let (offset_x, offset_y) = anchor.offset();
+    let left = pos.position[0] - offset_x * text_w * pixel_w;
+    let top = pos.position[1] + offset_y * text_h * pixel_h;
+    let advance = (GLYPH_WIDTH + GLYPH_SPACING) as f32 * scale * pixel_w;
+
+    for (i, c) in text.chars().enumerate()
+    {
+        if c == ' ' { continue; }
+
+        let tl = Pos { position: [left + i as f32 * advance, top] };
+        let br = Pos { position: [tl.position[0] + GLYPH_WIDTH as f32 * scale * pixel_w, top - text_h * pixel_h] };
+        crate::draw_rect(tl, br, ColorTex::Mask(&font.atlas, font.glyph_coords(c), color), 1.0, canvas);
+    }
+}
@@ -0,0 +1,307 @@
+//! Trigger areas, rectangles of tiles in a level that fire events when the player goes into them, leaves them or
+//! interacts while inside them.
+//!
+//! Triggers are declared in the map file with a `trigger` line:
+//!
+//! ```text
+//! trigger <col,row> [<col,row>] <enter|leave|interact> [once] <event>
+//! ```
+//!
+//! The first tile is one corner of the area and the optional second tile is the opposite corner, otherwise the
+//! area is that one tile. A trigger fires every time unless it is marked `once`. The event is one of:
+//!
+//! * `open <col,row>`, turns the tile into open floor, like opening a door somewhere else.
+//! * `spawn <col,row> <angle>`, places an enemy on the tile.
+//! * `message <text>`, shows the text on the HUD.
+//! * `light <brightness>`, changes how bright the level is, `1` is full brightness.
+//! * `teleport <col,row> <angle>`, moves the player to the tile.
+//! * `script <function>`, runs a function of the level's [script](../script/index.html).
+//!
+//! Fired events go into the world's event queue and are handled together once the triggers have all been checked.
+
+use glium::texture::Texture2d;
+use crate::enemy;
+use crate::hud::Hud;
+use crate::pickup::{self, Item};
+use crate::level::{self, Level};
+use crate::path::{FlowField, Tile};
+use crate::teleport;
+use crate::world::World;
+use crate::{Canvas, Pos};
+
+/// When a trigger fires.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum When
+{
+    /// When the player goes into the area.
+    Enter,
+    /// When the player leaves the area.
+    Leave,
+    /// When the player presses the interact key inside the area.
+    Interact,
+}
+
+/// Something that happens in the level.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event
+{
+    /// A tile's wall type changes, `0` turns it into open floor.
+    SetTile(Tile, u8),
+    /// An enemy appears on a tile, facing the angle.
+    Spawn(Tile, f32),
+    /// An item appears on a tile.
+    Item(Tile, Item),
+    /// A message is shown to the player.
+    Message(String),
+    /// The level's brightness changes.
+    Light(f32),
+    /// The player is moved to a tile, facing the angle.
+    Teleport(Tile, f32),
+    /// A function of the level's script is run.
+    Script(String),
+}
+
+/// The trigger component, for an entity that is an area that fires an event.
+#[derive(Clone, Debug)]
+pub struct Trigger
+{
+    /// Opposite corners of the area, both are inside of it.
+    area: (Tile, Tile),
+    when: When,
+    /// If set the trigger only ever fires once.
+    once: bool,
+    event: Event,
+    /// Whether the player was inside the area last frame.
+    inside: bool,
+    /// Whether the interact key was held last frame.
+    interacting: bool,
+    /// Whether it has fired yet.
+    fired: bool,
+}
+
+impl Trigger
+{
+    /// Reads the values of a `trigger` line in a map file, see the [module docs](index.html) for the format.
+    ///
+    /// Returns a message saying what is wrong if the line can not be read.
+    pub fn parse(values: &[&str]) -> Result<Trigger, String>
+    {
+        let tile = |value: Option<&&str>| -> Result<Tile, String>
+        {
+            level::parse_tile(value.ok_or("expected a tile")?)
+        };
+        let number = |value: Option<&&str>| -> Result<f32, String>
+        {
+            value.and_then(|value| value.parse().ok()).ok_or_else(|| String::from("expected a number"))
+        };
+
+        let mut values = values.iter().peekable();
+        let first = tile(values.next())?;
+        let second = if values.peek().is_some_and(|value| value.contains(',')) { tile(values.next())? } else { first };
+        let when = match values.next()
+        {
+            Some(&"enter") => When::Enter,
+            Some(&"leave") => When::Leave,
+            Some(&"interact") => When::Interact,
+            _ => return Err(String::from("expected enter, leave or interact")),
+        };
+        let once = values.peek() == Some(&&"once");
+        if once { values.next(); }
+
+        let event = match values.next()
+        {
+            Some(&"open") => Event::SetTile(tile(values.next())?, 0),
+            Some(&"spawn") => Event::Spawn(tile(values.next())?, number(values.next())?),
+            Some(&"message") => Event::Message(values.copied().collect::<Vec<&str>>().join(" ").to_uppercase()),
+            Some(&"light") => Event::Light(number(values.next())?),
+            Some(&"teleport") => Event::Teleport(tile(values.next())?, number(values.next())?),
+            Some(&"script") => Event::Script(values.next().ok_or("expected a function")?.to_string()),
+            Some(event) => return Err(format!("unknown event `{}`", event)),
+            None => return Err(String::from("expected an event")),
+        };
+
+        let area = ((first.0.min(second.0), first.1.min(second.1)), (first.0.max(second.0), first.1.max(second.1)));
+        Ok(Trigger { area, when, once, event, inside: false, interacting: false, fired: false })
+    }
+
+    /// The corners of the area and the tile that the event happens on if it has one, so they can be checked
+    /// against the size of the level.
+    pub fn tiles(&self) -> Vec<Tile>
+    {
+        let mut tiles = vec![self.area.0, self.area.1];
+        match self.event
+        {
+            Event::SetTile(tile, _) | Event::Spawn(tile, _) | Event::Item(tile, _) | Event::Teleport(tile, _) =>
+                tiles.push(tile),
+            _ => (),
+        }
+        tiles
+    }
+
+    /// Whether a tile is inside the area.
+    fn contains(&self, tile: Tile) -> bool
+    {
+        let ((left, top), (right, bottom)) = self.area;
+        (left..=right).contains(&tile.0) && (top..=bottom).contains(&tile.1)
+    }
+}
+
+/// Places a trigger in the world.
+pub fn spawn(world: &mut World, trigger: Trigger)
+{
+    let entity = world.spawn();
+    world.triggers.insert(entity, trigger);
+}
+
+/// The trigger system. Checks every trigger against where the player is now, putting the events of the ones that
+/// fire into the world's event queue. `interact` is whether the interact key is held.
+pub fn update(world: &mut World, level: &Level, interact: bool)
+{
+    let tile = level.tile_at(world.player_pos().position);
+    let mut fired = Vec::new();
+    for (_, trigger) in world.triggers.iter_mut()
+    {
+        let inside = tile.is_some_and(|tile| trigger.contains(tile));
+        let fires = match trigger.when
+        {
+            When::Enter => inside && !trigger.inside,
+            When::Leave => !inside && trigger.inside,
+            When::Interact => inside && interact && !trigger.interacting,
+        };
+        trigger.inside = inside;
+        trigger.interacting = interact;
+
+        if fires && !(trigger.once && trigger.fired)
+        {
+            trigger.fired = true;
+            fired.push(trigger.event.clone());
+        }
+    }
+    world.events.extend(fired);
+}
+
+/// Handles every event in the world's event queue, in the order they were fired.
+pub fn process(world: &mut World, level: &mut Level, flow: &mut FlowField, hud: &mut Hud)
+{
+    while let Some(event) = world.events.pop_front()
+    {
+        match event
+        {
+            Event::SetTile((col, row), wall) =>
+            {
+                if col < level.width && row < level.height
+                {
+                    level.tiles[row * level.width + col] = wall;
+                    // The enemies have to find their way around the change.
+                    *flow = FlowField::new(level, flow.target);
+                }
+            },
+            Event::Spawn(tile, ang) =>
+            {
+                if level.is_walkable(tile.0, tile.1)
+                {
+                    enemy::spawn(world, level, tile, ang, &[]);
+                }
+            },
+            Event::Item(tile, item) =>
+            {
+                if level.is_walkable(tile.0, tile.1)
+                {
+                    pickup::spawn(world, level, tile, item);
+                }
+            },
+            Event::Message(message) => hud.show_message(&message),
+            Event::Light(light) => level.light = f32::max(light, 0.0),
+            Event::Teleport(tile, ang) =>
+            {
+                if level.is_walkable(tile.0, tile.1)
+                {
+                    let player = world.player;
+                    teleport::send(world, level, player, tile, ang);
+                }
+            },
+            Event::Script(function) =>
+            {
+                // Taken out while it runs so it can look at the level, the events it fires join the queue.
+                if let Some(mut script) = level.script.take()
+                {
+                    script.call(&function, world, level);
+                    level.script = Some(script);
+                }
+            },
+        }
+    }
+}
+
+/// Draws the outline of every trigger's area from above.
+pub fn draw_2d(world: &World, level: &Level, canvas: &mut Canvas, empty_tex: &Texture2d)
+{
+    let (tile_w, tile_h) = level.tile_size();
+    for (_, trigger) in world.triggers.iter()
+    {
+        let ((left, top), (right, bottom)) = trigger.area;
+        let (x0, y0) = (left as f32 * tile_w - 1.0, top as f32 * tile_h - 1.0);
+        let (x1, y1) = ((right + 1) as f32 * tile_w - 1.0, (bottom + 1) as f32 * tile_h - 1.0);
+        let corners = [[x0, y0], [x1, y0], [x1, y1], [x0, y1]];
+        let color = if trigger.once && trigger.fired { (0.4, 0.4, 0.4) } else { (1.0, 0.0, 1.0) };
+        for i in 0..corners.len()
+        {
+            crate::draw_line(Pos { position: corners[i] }, Pos { position: corners[(i + 1) % corners.len()] }, color,
+                1.0, empty_tex, canvas);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(line: &str) -> Result<Trigger, String>
+    {
+        Trigger::parse(&line.split_whitespace().collect::<Vec<&str>>())
+    }
+
+    #[test]
+    fn parse_single_tile()
+    {
+        let trigger = parse("8,1 enter once light 0.5").unwrap();
+        assert_eq!(trigger.area, ((8, 1), (8, 1)));
+        assert_eq!(trigger.when, When::Enter);
+        assert!(trigger.once);
+        assert_eq!(trigger.event, Event::Light(0.5));
+    }
+
+    #[test]
+    fn parse_area_corners_in_any_order()
+    {
+        let trigger = parse("5,4 3,6 leave spawn 8,1 1.5").unwrap();
+        assert_eq!(trigger.area, ((3, 4), (5, 6)));
+        assert_eq!(trigger.when, When::Leave);
+        assert!(!trigger.once);
+        assert_eq!(trigger.event, Event::Spawn((8, 1), 1.5));
+        assert_eq!(trigger.tiles(), vec![(3, 4), (5, 6), (8, 1)]);
+    }
+
+    #[test]
+    fn parse_events()
+    {
+        assert_eq!(parse("1,1 interact open 2,3").unwrap().event, Event::SetTile((2, 3), 0));
+        assert_eq!(parse("1,1 enter message watch out").unwrap().event, Event::Message(String::from("WATCH OUT")));
+        assert_eq!(parse("1,1 enter teleport 4,5 1.5").unwrap().event, Event::Teleport((4, 5), 1.5));
+        assert_eq!(parse("1,1 enter script alarm").unwrap().event, Event::Script(String::from("alarm")));
+    }
+
+    #[test]
+    fn parse_errors()
+    {
+        assert_eq!(parse("").unwrap_err(), "expected a tile");
+        assert_eq!(parse("1;1 enter light 1").unwrap_err(), "expected a tile as col,row but found `1;1`");
+        assert_eq!(parse("1,1 touch light 1").unwrap_err(), "expected enter, leave or interact");
+        assert_eq!(parse("1,1 enter").unwrap_err(), "expected an event");
+        assert_eq!(parse("1,1 enter explode").unwrap_err(), "unknown event `explode`");
+        assert_eq!(parse("1,1 enter light bright").unwrap_err(), "expected a number");
+        assert_eq!(parse("1,1 enter spawn 2,2").unwrap_err(), "expected a number");
+        assert_eq!(parse("1,1 enter script").unwrap_err(), "expected a function");
+    }
+}
@@ -0,0 +1,318 @@
+//! The player's gun, which hits whatever is first along a ray cast from the player.
+
+use crate::eye;
+use crate::hud;
+use crate::level::{self, Decal, Level};
+use crate::projectile::{self, ProjectileKind};
+use crate::world::{self, Entity, World};
+use crate::{Canvas, ColorTex, PlayerPos, Pos, Textures};
+
+/// How much ammo the player starts with.
+const START_AMMO: u32 = 50;
+/// How many rockets the player starts with.
+const START_ROCKETS: u32 = 5;
+/// How many grenades the player starts with.
+const START_GRENADES: u32 = 5;
+/// Seconds between shots while the fire key is held.
+const FIRE_TIME: f32 = 0.3;
+/// Health taken off of an enemy by each shot.
+const DAMAGE: i32 = 10;
+/// The most that a shot can stray from where the player is looking, in rads either way.
+const SPREAD: f32 = 0.03;
+/// Seconds after a shot that the muzzle flash is shown for.
+const FLASH_TIME: f32 = 0.06;
+/// Seconds after a shot that the gun takes to come back down.
+const RECOIL_TIME: f32 = 0.2;
+/// Number of frames across the weapon sprite sheet.
+const FRAMES: usize = 4;
+/// Height of the weapon on the screen as a fraction of the height of the view.
+const WEAPON_SIZE: f32 = 0.5;
+
+/// What a shot hit first.
+#[derive(Copy, Clone, Debug)]
+pub enum Hit
+{
+    /// The face of a wall of type `wall`, at `position` in play space. `horz` is whether it is on a horizontal grid
+    /// line.
+    Wall { position: [f32; 2], horz: bool, wall: u8, dist: f32 },
+    /// An entity with a collider.
+    Entity { entity: Entity, dist: f32 },
+    /// Nothing, the shot left the level.
+    Nothing,
+}
+
+/// Finds the first wall or entity that is hit by a ray from `origin` going in the direction `ang`. The `shooter`
+/// and projectiles are never hit.
+///
+/// The walls are found with the same ray cast as the 3d view, so a shot lands exactly where the wall is drawn.
+/// Shots do not go through portals. The player shoots along the storey they are on, and everything else is on the
+/// ground floor, so shots from upper storeys only hit walls.
+pub fn hitscan(level: &Level, world: &World, origin: [f32; 2], ang: f32, shooter: Entity) -> Hit
+{
+    let storey = if shooter == world.player { eye::storey(world) } else { 0 };
+    let walls = |tile| level.wall_on(storey, tile);
+    let (wall_dist, horz, wall, ray_pos) = crate::cast_on(level, &walls, &PlayerPos { position: origin, ang }, ang);
+    let wall_dist = if wall == 0 { f32::INFINITY } else { wall_dist };
+
+    let entity_hit = world.colliders.iter()
+        .filter(|&(entity, _)| storey == 0 && entity != shooter && world.projectiles.get(entity).is_none())
+        .filter_map(|(entity, _)|
+        {
+            let center = world.transforms.get(entity)?.position;
+            ray_hits_circle(origin, ang, center, world.collider_radius(level, entity)?).map(|dist| (entity, dist))
+        })
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    match entity_hit
+    {
+        Some((entity, dist)) if dist < wall_dist => Hit::Entity { entity, dist },
+        _ if wall != 0 => Hit::Wall { position: [ray_pos.0, ray_pos.1], horz, wall, dist: wall_dist },
+        _ => Hit::Nothing,
+    }
+}
+
+/// How far along a ray from `origin` going in the direction `ang` it first touches a circle, if it does at all.
+fn ray_hits_circle(origin: [f32; 2], ang: f32, center: [f32; 2], radius: f32) -> Option<f32>
+{
+    let (to_x, to_y) = (center[0] - origin[0], center[1] - origin[1]);
+    let along = to_x * f32::cos(ang) + to_y * f32::sin(ang);
+    let across_sq = to_x * to_x + to_y * to_y - along * along;
+    if along <= 0.0 || across_sq > radius * radius { return None; }
+
+    Some(f32::max(along - f32::sqrt(radius * radius - across_sq), 0.0))
+}
+
+/// The weapon component, the gun that the player holds.
+pub struct Weapon
+{
+    pub ammo: u32,
+    pub rockets: u32,
+    pub grenades: u32,
+    /// Seconds since the last shot.
+    since_fired: f32,
+    /// What the last shot hit, if anything has been fired yet.
+    pub last_hit: Option<Hit>,
+    /// State of the random numbers used for the spread of the shots.
+    rng: u32,
+}
+
+impl Default for Weapon
+{
+    fn default() -> Weapon
+    {
+        Weapon
+        {
+            ammo: START_AMMO,
+            rockets: START_ROCKETS,
+            grenades: START_GRENADES,
+            since_fired: FIRE_TIME,
+            last_hit: None,
+            rng: 0x2545_f491,
+        }
+    }
+}
+
+impl Weapon
+{
+    /// Uses up a bullet if `firing` is set and the gun is ready and loaded.
+    ///
+    /// Returns how far off of straight ahead the shot goes, if one was fired.
+    fn pull_trigger(&mut self, firing: bool, frame_time: f32) -> Option<f32>
+    {
+        self.since_fired += frame_time;
+        if !firing || self.since_fired < FIRE_TIME || self.ammo == 0 { return None; }
+
+        self.since_fired = 0.0;
+        self.ammo -= 1;
+        Some(self.spread())
+    }
+
+    /// Uses up a rocket or a grenade if the gun is ready and there are any left. Any other kind of projectile is
+    /// never ready.
+    fn ready_launch(&mut self, kind: ProjectileKind) -> bool
+    {
+        let count = match kind
+        {
+            ProjectileKind::Rocket => &mut self.rockets,
+            ProjectileKind::Grenade => &mut self.grenades,
+            _ => return false,
+        };
+        if *count == 0 || self.since_fired < FIRE_TIME { return false; }
+
+        *count -= 1;
+        self.since_fired = 0.0;
+        true
+    }
+
+    /// A random angle between `-SPREAD` and `SPREAD`, from a xorshift.
+    fn spread(&mut self) -> f32
+    {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0) * SPREAD
+    }
+
+    /// Draws the gun at the bottom of the 3d view, on top of the HUD's status bar, as if the player is holding it.
+    pub fn draw(&self, canvas: &mut Canvas, textures: &Textures)
+    {
+        let frame = if self.since_fired < FLASH_TIME { 1 }
+            else if self.since_fired < FLASH_TIME + (RECOIL_TIME - FLASH_TIME) / 2.0 { 2 }
+            else if self.since_fired < RECOIL_TIME { 3 }
+            else { 0 };
+        let (left_u, right_u) = (frame as f32 / FRAMES as f32, (frame + 1) as f32 / FRAMES as f32);
+
+        // Keep the gun square no matter the shape of the view.
+        let (view_width, view_height) = canvas.view_size();
+        let bottom = hud::status_bar_top(canvas);
+        let height = WEAPON_SIZE * 2.0;
+        let half_width = height / 2.0 * view_height as f32 / view_width as f32;
+        let tex_coords = ([left_u, 1.0], [right_u, 1.0], [right_u, 0.0], [left_u, 0.0]);
+        crate::draw_rect(Pos { position: [-half_width, bottom + height] }, Pos { position: [half_width, bottom] },
+            ColorTex::Texture(&textures.weapon, tex_coords), 1.0, canvas);
+    }
+}
+
+/// The weapon system. Fires the player's gun if `firing` is set and it is ready and loaded, damaging whatever is hit
+/// or marking the wall.
+pub fn update(world: &mut World, level: &mut Level, firing: bool, frame_time: f32)
+{
+    let player = world.player;
+    let player_pos = world.player_pos();
+    let spread = match world.weapons.get_mut(player).and_then(|weapon| weapon.pull_trigger(firing, frame_time))
+    {
+        Some(spread) => spread,
+        None => return,
+    };
+
+    let hit = hitscan(level, world, player_pos.position, player_pos.ang + spread, player);
+    match hit
+    {
+        // Shots go where the middle of the view is, so they hit the wall at the height of the player's eyes when
+        // looking straight ahead and higher or lower the further away the wall is when looking up or down.
+        // Diagonal walls are not on a grid line, so they are never marked.
+        Hit::Wall { position, horz, wall, dist } if !level::is_diagonal(wall) =>
+        {
+            let height = eye::height(world) - eye::horizon(world) * dist / (2.0 * level.tile_size().1);
+            level.add_decal(Decal { position, horz, height: f32::max(height, 0.0) });
+        },
+        Hit::Entity { entity, .. } => world::damage(world, entity, DAMAGE, player_pos.position),
+        Hit::Wall { .. } | Hit::Nothing => (),
+    }
+    if let Some(weapon) = world.weapons.get_mut(player)
+    {
+        weapon.last_hit = Some(hit);
+    }
+}
+
+/// Fires a rocket or throws a grenade from the player's gun where they are looking, if it is ready and there are
+/// any left. Projectiles only fly on the ground floor, so nothing is launched from the storeys above.
+pub fn launch(world: &mut World, level: &Level, kind: ProjectileKind)
+{
+    if eye::storey(world) > 0 { return; }
+    let player = world.player;
+    let player_pos = world.player_pos();
+    if world.weapons.get_mut(player).is_some_and(|weapon| weapon.ready_launch(kind))
+    {
+        projectile::spawn(world, level, kind, player_pos.position, player_pos.ang, player);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::enemy;
+    use crate::world::Transform;
+
+    /// A hall with the player at the left end, looking down it to the right.
+    fn hall() -> (World, Level)
+    {
+        let level = Level::from_rows(&["11111111", "10000001", "10000001", "11111111"]);
+        (World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 }), level)
+    }
+
+    fn shoot(world: &World, level: &Level) -> Hit
+    {
+        hitscan(level, world, world.player_pos().position, 0.0, world.player)
+    }
+
+    #[test]
+    fn hits_the_nearest_entity()
+    {
+        let (mut world, level) = hall();
+        let far = enemy::spawn(&mut world, &level, (5, 1), 0.0, &[]);
+        let near = enemy::spawn(&mut world, &level, (3, 1), 0.0, &[]);
+        enemy::spawn(&mut world, &level, (3, 2), 0.0, &[]);
+        assert!(matches!(shoot(&world, &level), Hit::Entity { entity, .. } if entity == near));
+
+        world.despawn(near);
+        assert!(matches!(shoot(&world, &level), Hit::Entity { entity, .. } if entity == far));
+    }
+
+    #[test]
+    fn walls_stop_shots()
+    {
+        let level = Level::from_rows(&["11111111", "10010001", "10000001", "11111111"]);
+        let mut world = World::new(Transform { position: level.tile_center((1, 1)), ang: 0.0 });
+        enemy::spawn(&mut world, &level, (5, 1), 0.0, &[]);
+        let (tile_w, _) = level.tile_size();
+        match shoot(&world, &level)
+        {
+            Hit::Wall { wall, dist, horz, .. } =>
+            {
+                assert_eq!((wall, horz), (1, false));
+                assert!((dist - 1.5 * tile_w).abs() < 1e-4);
+            },
+            hit => panic!("expected a wall but hit {:?}", hit),
+        }
+    }
+
+    #[test]
+    fn shooter_and_projectiles_are_not_hit()
+    {
+        let (mut world, level) = hall();
+        let player = world.player;
+        let fireball = projectile::spawn(&mut world, &level, ProjectileKind::Fireball, level.tile_center((2, 1)), 0.0,
+            player);
+        assert!(world.colliders.get(fireball).is_some());
+        assert!(matches!(shoot(&world, &level), Hit::Wall { wall: 1, .. }));
+    }
+
+    #[test]
+    fn only_walls_are_hit_from_the_storey_above()
+    {
+        let (mut world, level) = hall();
+        enemy::spawn(&mut world, &level, (3, 1), 0.0, &[]);
+        eye::set_storey(&mut world, 1);
+        assert!(!matches!(shoot(&world, &level), Hit::Entity { .. }));
+    }
+
+    #[test]
+    fn ray_hits_circle_from_outside()
+    {
+        assert_eq!(ray_hits_circle([0.0, 0.0], 0.0, [1.0, 0.0], 0.25), Some(0.75));
+        assert_eq!(ray_hits_circle([0.0, 0.0], 0.0, [1.0, 0.5], 0.25), None);
+        assert_eq!(ray_hits_circle([0.0, 0.0], 0.0, [-1.0, 0.0], 0.25), None, "behind the shooter");
+        assert_eq!(ray_hits_circle([0.9, 0.0], 0.0, [1.0, 0.0], 0.25), Some(0.0), "inside the circle");
+    }
+
+    #[test]
+    fn shots_use_ammo_and_damage()
+    {
+        let (mut world, mut level) = hall();
+        let target = enemy::spawn(&mut world, &level, (3, 1), 0.0, &[]);
+        let start = world.healths.get(target).unwrap().current;
+        world.weapons.get_mut(world.player).unwrap().rng = 0;
+
+        update(&mut world, &mut level, true, FIRE_TIME);
+        assert_eq!(world.healths.get(target).unwrap().current, start - DAMAGE);
+        assert_eq!(world.weapons.get(world.player).unwrap().ammo, START_AMMO - 1);
+        update(&mut world, &mut level, true, FIRE_TIME / 2.0);
+        assert_eq!(world.weapons.get(world.player).unwrap().ammo, START_AMMO - 1, "not ready again yet");
+
+        world.weapons.get_mut(world.player).unwrap().ammo = 0;
+        update(&mut world, &mut level, true, FIRE_TIME);
+        assert_eq!(world.healths.get(target).unwrap().current, start - DAMAGE);
+    }
+}
@@ -0,0 +1,377 @@
+//! The entity store that holds everything in a level, the player included.
+//!
+//! An entity is only an id. What it is and what it does comes from the components it has, which are kept in a
+//! [`Components`] list per type of component, and the systems (the `update` functions of the other modules) go
+//! over every entity that has the components they care about.
+//!
+//! [`Components`]: struct.Components.html
+
+use std::collections::VecDeque;
+use crate::enemy::Ai;
+use crate::eye::Eye;
+use crate::level::Level;
+use crate::pickup::{Inventory, Pickup};
+use crate::player::{self, PlayerState};
+use crate::projectile::{Explosion, Projectile};
+use crate::teleport::Teleported;
+use crate::trigger::{Event, Trigger};
+use crate::weapon::Weapon;
+use crate::PlayerPos;
+
+/// How much health the player starts with.
+const PLAYER_HEALTH: i32 = 100;
+/// How big the player is compared to a tile, they are only seen in mirrors.
+const PLAYER_SIZE: f32 = 0.8;
+
+/// The id of an entity. Ids of removed entities are given out again.
+pub type Entity = usize;
+
+/// Where an entity is and which way it faces, this is the same as the player's [`PlayerPos`] so the level can be
+/// ray cast from any entity.
+///
+/// [`PlayerPos`]: ../struct.PlayerPos.html
+pub type Transform = PlayerPos;
+
+/// Which texture a sprite's frames are taken from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Sheet
+{
+    /// [`Textures::enemy`](../struct.Textures.html#structfield.enemy)
+    Enemy,
+    /// [`Textures::projectiles`](../struct.Textures.html#structfield.projectiles)
+    Projectiles,
+    /// [`Textures::items`](../struct.Textures.html#structfield.items)
+    Items,
+    /// [`Textures::player`](../struct.Textures.html#structfield.player)
+    Player,
+}
+
+impl Sheet
+{
+    /// Number of frames side by side in the sheet.
+    pub fn frames(self) -> usize
+    {
+        match self
+        {
+            Sheet::Enemy => 6,
+            Sheet::Projectiles => 5,
+            Sheet::Items => 7,
+            Sheet::Player => 1,
+        }
+    }
+}
+
+/// How an entity looks in the 3d view.
+#[derive(Copy, Clone, Debug)]
+pub struct Sprite
+{
+    pub sheet: Sheet,
+    /// Which frame of the sheet is shown.
+    pub frame: usize,
+    /// How big it is compared to a tile.
+    pub size: f32,
+    /// How far off of the floor its bottom is compared to a tile.
+    pub height: f32,
+}
+
+/// What an entity bumps into things with.
+#[derive(Copy, Clone, Debug)]
+pub struct Collider
+{
+    /// How far it reaches out from its position in tiles.
+    pub radius: f32,
+}
+
+/// How much more damage an entity can take.
+#[derive(Copy, Clone, Debug)]
+pub struct Health
+{
+    pub current: i32,
+    /// The most health it can be given back.
+    pub max: i32,
+}
+
+/// A list of one type of component, indexed by entity.
+pub struct Components<T>
+{
+    items: Vec<Option<T>>,
+}
+
+impl<T> Default for Components<T>
+{
+    fn default() -> Components<T>
+    {
+        Components { items: Vec::new() }
+    }
+}
+
+impl<T> Components<T>
+{
+    pub fn get(&self, entity: Entity) -> Option<&T>
+    {
+        self.items.get(entity).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T>
+    {
+        self.items.get_mut(entity).and_then(Option::as_mut)
+    }
+
+    /// Gives an entity the component, replacing the one it had.
+    pub fn insert(&mut self, entity: Entity, component: T)
+    {
+        if entity >= self.items.len()
+        {
+            self.items.resize_with(entity + 1, || None);
+        }
+        self.items[entity] = Some(component);
+    }
+
+    /// Takes the component away from an entity.
+    pub fn remove(&mut self, entity: Entity) -> Option<T>
+    {
+        self.items.get_mut(entity).and_then(Option::take)
+    }
+
+    /// Every entity that has the component, with its component.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)>
+    {
+        self.items.iter().enumerate().filter_map(|(entity, item)| item.as_ref().map(|item| (entity, item)))
+    }
+
+    /// Every entity that has the component, with its component to change.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)>
+    {
+        self.items.iter_mut().enumerate().filter_map(|(entity, item)| item.as_mut().map(|item| (entity, item)))
+    }
+
+    /// The entities that have the component, collected so the components can be changed while going over them.
+    pub fn entities(&self) -> Vec<Entity>
+    {
+        self.iter().map(|(entity, _)| entity).collect()
+    }
+}
+
+/// Every entity in the level and their components.
+pub struct World
+{
+    /// Whether each id is in use by an entity.
+    alive: Vec<bool>,
+    /// The entity that the game is played as.
+    pub player: Entity,
+    pub transforms: Components<Transform>,
+    pub sprites: Components<Sprite>,
+    pub colliders: Components<Collider>,
+    pub healths: Components<Health>,
+    pub ais: Components<Ai>,
+    pub weapons: Components<Weapon>,
+    pub projectiles: Components<Projectile>,
+    pub explosions: Components<Explosion>,
+    pub pickups: Components<Pickup>,
+    pub inventories: Components<Inventory>,
+    pub player_states: Components<PlayerState>,
+    pub triggers: Components<Trigger>,
+    pub teleported: Components<Teleported>,
+    pub eyes: Components<Eye>,
+    /// Events that have been fired but not handled yet, oldest first.
+    pub events: VecDeque<Event>,
+}
+
+impl World
+{
+    /// Makes a world with only the player in it, at `start`.
+    pub fn new(start: Transform) -> World
+    {
+        let mut world = World
+        {
+            alive: Vec::new(),
+            player: 0,
+            transforms: Components::default(),
+            sprites: Components::default(),
+            colliders: Components::default(),
+            healths: Components::default(),
+            ais: Components::default(),
+            weapons: Components::default(),
+            projectiles: Components::default(),
+            explosions: Components::default(),
+            pickups: Components::default(),
+            inventories: Components::default(),
+            player_states: Components::default(),
+            triggers: Components::default(),
+            teleported: Components::default(),
+            eyes: Components::default(),
+            events: VecDeque::new(),
+        };
+        world.player = world.spawn();
+        world.transforms.insert(world.player, start);
+        world.colliders.insert(world.player, Collider { radius: player::RADIUS });
+        world.sprites.insert(world.player, Sprite { sheet: Sheet::Player, frame: 0, size: PLAYER_SIZE, height: 0.0 });
+        world.healths.insert(world.player, Health { current: PLAYER_HEALTH, max: PLAYER_HEALTH });
+        world.weapons.insert(world.player, Weapon::default());
+        world.inventories.insert(world.player, Inventory::default());
+        world.player_states.insert(world.player, PlayerState::new(start));
+        world.eyes.insert(world.player, Eye::default());
+        world
+    }
+
+    /// Makes a new entity without any components.
+    pub fn spawn(&mut self) -> Entity
+    {
+        match self.alive.iter().position(|&alive| !alive)
+        {
+            Some(entity) =>
+            {
+                self.alive[entity] = true;
+                entity
+            },
+            None =>
+            {
+                self.alive.push(true);
+                self.alive.len() - 1
+            },
+        }
+    }
+
+    /// Removes an entity and all of its components.
+    pub fn despawn(&mut self, entity: Entity)
+    {
+        if let Some(alive) = self.alive.get_mut(entity)
+        {
+            *alive = false;
+        }
+        self.transforms.remove(entity);
+        self.sprites.remove(entity);
+        self.colliders.remove(entity);
+        self.healths.remove(entity);
+        self.ais.remove(entity);
+        self.weapons.remove(entity);
+        self.projectiles.remove(entity);
+        self.explosions.remove(entity);
+        self.pickups.remove(entity);
+        self.inventories.remove(entity);
+        self.player_states.remove(entity);
+        self.triggers.remove(entity);
+        self.teleported.remove(entity);
+        self.eyes.remove(entity);
+    }
+
+    /// Where the player is.
+    pub fn player_pos(&self) -> PlayerPos
+    {
+        *self.transforms.get(self.player).expect("the player always has a transform")
+    }
+
+    /// The player's transform, to move them.
+    pub fn player_pos_mut(&mut self) -> &mut PlayerPos
+    {
+        self.transforms.get_mut(self.player).expect("the player always has a transform")
+    }
+
+    /// The play space radius of an entity's collider, if it has one.
+    pub fn collider_radius(&self, level: &Level, entity: Entity) -> Option<f32>
+    {
+        self.colliders.get(entity).map(|collider| collider.radius * level.tile_size().0)
+    }
+}
+
+/// Hurts an entity that has health. It is told through its AI, if it has one, so it can react, and once it runs
+/// out of health it loses its collider so nothing hits it any more. The player's armor takes some of the damage
+/// first.
+///
+/// `from` is where the damage came from.
+pub fn damage(world: &mut World, entity: Entity, amount: i32, from: [f32; 2])
+{
+    let health = match world.healths.get_mut(entity)
+    {
+        Some(health) if health.current > 0 => health,
+        _ => return,
+    };
+    let amount = world.player_states.get_mut(entity).map_or(amount, |state| state.absorb(amount));
+    health.current -= amount;
+    let dead = health.current <= 0;
+
+    if let Some(ai) = world.ais.get_mut(entity)
+    {
+        ai.hurt(dead, from);
+    }
+    if dead
+    {
+        world.colliders.remove(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn start() -> Transform
+    {
+        Transform { position: [0.0, 0.0], ang: 0.0 }
+    }
+
+    #[test]
+    fn components_insert_replace_and_remove()
+    {
+        let mut healths = Components::default();
+        healths.insert(3, Health { current: 5, max: 10 });
+        healths.insert(1, Health { current: 1, max: 1 });
+        assert_eq!(healths.entities(), vec![1, 3]);
+        assert!(healths.get(0).is_none());
+        assert!(healths.get(7).is_none());
+
+        healths.insert(3, Health { current: 7, max: 10 });
+        assert_eq!(healths.get(3).unwrap().current, 7);
+        healths.get_mut(3).unwrap().current += 1;
+        assert_eq!(healths.iter().map(|(_, health)| health.current).collect::<Vec<_>>(), vec![1, 8]);
+
+        assert_eq!(healths.remove(1).unwrap().current, 1);
+        assert!(healths.remove(1).is_none());
+        assert!(healths.remove(9).is_none());
+        assert_eq!(healths.entities(), vec![3]);
+    }
+
+    #[test]
+    fn despawn_removes_every_component_and_frees_the_id()
+    {
+        let mut world = World::new(start());
+        let first = world.spawn();
+        let second = world.spawn();
+        assert_ne!(first, world.player);
+        assert_ne!(first, second);
+        world.transforms.insert(first, start());
+        world.colliders.insert(first, Collider { radius: 0.3 });
+        world.healths.insert(first, Health { current: 10, max: 10 });
+
+        world.despawn(first);
+        assert!(world.transforms.get(first).is_none());
+        assert!(world.colliders.get(first).is_none());
+        assert!(world.healths.get(first).is_none());
+        assert_eq!(world.spawn(), first, "the freed id is used again");
+        assert_eq!(world.spawn(), second + 1);
+    }
+
+    #[test]
+    fn damage_kills_and_removes_the_collider()
+    {
+        let mut world = World::new(start());
+        let entity = world.spawn();
+        world.colliders.insert(entity, Collider { radius: 0.3 });
+        world.healths.insert(entity, Health { current: 10, max: 10 });
+
+        damage(&mut world, entity, 4, [0.0, 0.0]);
+        assert_eq!(world.healths.get(entity).unwrap().current, 6);
+        assert!(world.colliders.get(entity).is_some());
+
+        damage(&mut world, entity, 8, [0.0, 0.0]);
+        assert_eq!(world.healths.get(entity).unwrap().current, -2);
+        assert!(world.colliders.get(entity).is_none());
+
+        damage(&mut world, entity, 8, [0.0, 0.0]);
+        assert_eq!(world.healths.get(entity).unwrap().current, -2, "the dead are not hurt again");
+
+        let no_health = world.spawn();
+        damage(&mut world, no_health, 8, [0.0, 0.0]);
+        assert!(world.healths.get(no_health).is_none());
+    }
+}